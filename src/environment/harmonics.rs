@@ -0,0 +1,115 @@
+use std::f32::consts::PI;
+
+use cgmath::Vector3;
+
+use super::{texel_direction, texel_solid_angle, HdrImage};
+
+/// Convolution of the bands of spherical harmonics by the cosine lobe of
+/// diffuse surfaces.
+const COSINE_LOBE: [f32; 3] = [PI, 2. * PI / 3., PI / 4.];
+
+/// The 9 spherical harmonics of the first 3 bands in the given direction.
+fn basis(d: Vector3<f32>) -> [f32; 9] {
+    [
+        0.282_095,
+        0.488_603 * d.y,
+        0.488_603 * d.z,
+        0.488_603 * d.x,
+        1.092_548 * d.x * d.y,
+        1.092_548 * d.y * d.z,
+        0.315_392 * (3. * d.z * d.z - 1.),
+        1.092_548 * d.x * d.z,
+        0.546_274 * (d.x * d.x - d.y * d.y),
+    ]
+}
+
+/// Irradiance of the surfaces lit by a panorama, from the projection of its
+/// light on the spherical harmonics of the first 3 bands, as in "An Efficient
+/// Representation for Irradiance Environment Maps" by Ramamoorthi and
+/// Hanrahan.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Irradiance {
+    /// Coefficients of the harmonics, already convolved by the cosine lobe.
+    coefficients: [[f32; 3]; 9],
+}
+
+impl Irradiance {
+    /// Project the light of an equirectangular panorama.
+    pub fn new(panorama: &HdrImage) -> Self {
+        let mut coefficients = [[0.; 3]; 9];
+        for y in 0..panorama.height {
+            let solid_angle = texel_solid_angle(panorama, y);
+            for x in 0..panorama.width {
+                let color = panorama.pixels[y * panorama.width + x];
+                let direction = texel_direction(panorama, x, y);
+                for (coefficient, harmonic) in coefficients.iter_mut().zip(&basis(direction)) {
+                    for c in 0..3 {
+                        coefficient[c] += color[c] * harmonic * solid_angle;
+                    }
+                }
+            }
+        }
+        for (k, coefficient) in coefficients.iter_mut().enumerate() {
+            // bands 0, 1 and 2 have 1, 3 and 5 harmonics
+            let band = match k {
+                0 => 0,
+                1..=3 => 1,
+                _ => 2,
+            };
+            coefficient.iter_mut().for_each(|v| *v *= COSINE_LOBE[band]);
+        }
+        Irradiance { coefficients }
+    }
+
+    /// Light received by a surface facing `normal`, of unit length, from the
+    /// half of the panorama in front of it.
+    pub fn at(&self, normal: Vector3<f32>) -> [f32; 3] {
+        let mut irradiance = [0.; 3];
+        for (coefficient, harmonic) in self.coefficients.iter().zip(&basis(normal)) {
+            for c in 0..3 {
+                irradiance[c] += coefficient[c] * harmonic;
+            }
+        }
+        // the ringing of the harmonics may go below 0 around strong lights
+        irradiance.map(|v| v.max(0.))
+    }
+}
+
+#[test]
+fn test_irradiance() {
+    // uniform light, and light only coming from above the horizon
+    let uniform = HdrImage {
+        width: 32,
+        height: 16,
+        pixels: vec![[1., 0.5, 0.]; 32 * 16],
+    };
+    let sky = HdrImage {
+        pixels: (0..32 * 16)
+            .map(|i| if i < 32 * 8 { [1.; 3] } else { [0.; 3] })
+            .collect(),
+        ..uniform.clone()
+    };
+
+    let irradiance = Irradiance::new(&uniform);
+    for &normal in &[Vector3::unit_x(), -Vector3::unit_y(), Vector3::unit_z()] {
+        let [r, g, b] = irradiance.at(normal);
+        assert!(
+            (r - PI).abs() < 0.02 && (g - PI / 2.).abs() < 0.01 && b == 0.,
+            "{} {} {}",
+            r,
+            g,
+            b
+        );
+    }
+    let irradiance = Irradiance::new(&sky);
+    let (up, side, down) = (
+        irradiance.at(Vector3::unit_y())[0],
+        irradiance.at(Vector3::unit_x())[0],
+        irradiance.at(-Vector3::unit_y())[0],
+    );
+    // a surface facing the sky gets all of it, one facing the horizon half
+    // of it, with the error of the truncated projection
+    assert!((up - PI).abs() < 0.2, "{}", up);
+    assert!((side - PI / 2.).abs() < 0.01, "{}", side);
+    assert!(down < 0.2, "{}", down);
+}
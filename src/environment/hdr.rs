@@ -0,0 +1,274 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+/// Largest width or height of the images, which is also the length of the
+/// longest run-length encoded scanlines.
+const MAX_SIZE: usize = 0x7fff;
+
+/// Image with linear colors of any brightness.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    /// Linear RGB colors of the pixels, row after row from the top of the
+    /// image.
+    pub pixels: Vec<[f32; 3]>,
+}
+
+/// Errors parsing or loading a Radiance `.hdr` file.
+#[derive(Debug)]
+pub enum HdrError {
+    Io(io::Error),
+    /// The file doesn't start with the `#?RADIANCE` or `#?RGBE` signature.
+    NotRadiance,
+    /// The pixels are not stored as RGBE, e.g. as XYZE.
+    UnsupportedFormat(String),
+    /// The resolution line is malformed, the image is larger than
+    /// `MAX_SIZE` pixels on a side, or it is not stored row after row from
+    /// the top left corner, as `-Y height +X width`.
+    InvalidResolution(String),
+    /// The file ends before the last scanline, or a run of pixels overflows
+    /// its scanline.
+    InvalidScanline {
+        row: usize,
+    },
+}
+
+impl std::error::Error for HdrError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HdrError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for HdrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HdrError::Io(err) => write!(f, "I/O error loading a .hdr file: {}", err),
+            HdrError::NotRadiance => write!(f, "not a Radiance .hdr file"),
+            HdrError::UnsupportedFormat(format) => {
+                write!(f, "unsupported pixel format in a .hdr file: {}", format)
+            }
+            HdrError::InvalidResolution(line) => {
+                write!(f, "unsupported resolution in a .hdr file: {}", line)
+            }
+            HdrError::InvalidScanline { row } => {
+                write!(f, "invalid scanline in a .hdr file (row: {})", row)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for HdrError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl HdrImage {
+    /// Load a Radiance `.hdr` file from the given path.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, HdrError> {
+        Self::parse(BufReader::new(File::open(path)?))
+    }
+
+    /// Parse the content of a Radiance `.hdr` file, with flat or run-length
+    /// encoded scanlines.
+    pub fn parse<R: BufRead>(mut reader: R) -> Result<Self, HdrError> {
+        let mut line = Vec::new();
+        let mut read_line = |reader: &mut R| -> Result<String, HdrError> {
+            line.clear();
+            reader.read_until(b'\n', &mut line)?;
+            Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+        };
+
+        let signature = read_line(&mut reader)?;
+        if signature != "#?RADIANCE" && signature != "#?RGBE" {
+            return Err(HdrError::NotRadiance);
+        }
+        // the header ends with an empty line
+        loop {
+            let variable = read_line(&mut reader)?;
+            if variable.is_empty() {
+                break;
+            }
+            if let Some(format) = variable.strip_prefix("FORMAT=") {
+                if format != "32-bit_rle_rgbe" {
+                    return Err(HdrError::UnsupportedFormat(format.to_string()));
+                }
+            }
+        }
+
+        let resolution = read_line(&mut reader)?;
+        let size = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", height, "+X", width] => height.parse().ok().zip(width.parse().ok()),
+            _ => None,
+        };
+        let (height, width): (usize, usize) = match size {
+            Some((height, width)) if height <= MAX_SIZE && width <= MAX_SIZE => (height, width),
+            _ => return Err(HdrError::InvalidResolution(resolution)),
+        };
+
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        // the pixels are only reserved up to the size of the data, for a
+        // resolution line not matching it
+        let mut pixels = Vec::with_capacity((width * height).min(data.len()));
+        let mut scanline = vec![[0; 4]; width];
+        let mut position = 0;
+        for row in 0..height {
+            read_scanline(&data, &mut position, &mut scanline)
+                .ok_or(HdrError::InvalidScanline { row })?;
+            pixels.extend(scanline.iter().map(rgbe_to_linear));
+        }
+        Ok(HdrImage {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+/// Decode the scanline starting at `position` in `data` into `scanline`, and
+/// move `position` to the next one. Returns `None` if the scanline is
+/// truncated or invalid.
+fn read_scanline(data: &[u8], position: &mut usize, scanline: &mut [[u8; 4]]) -> Option<()> {
+    let width = scanline.len();
+    let start = data.get(*position..*position + 4)?;
+    let encoded =
+        (8..=MAX_SIZE).contains(&width) && start == [2, 2, (width >> 8) as u8, width as u8];
+    if encoded {
+        *position += 4;
+    }
+    let mut next = || {
+        let byte = data.get(*position).copied();
+        *position += 1;
+        byte
+    };
+
+    if encoded {
+        // the components are stored one after the other, each in runs of
+        // repeated bytes and of literal bytes
+        for c in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let count = next()? as usize;
+                if count > 128 {
+                    let value = next()?;
+                    let run = scanline.get_mut(x..x + count - 128)?;
+                    run.iter_mut().for_each(|pixel| pixel[c] = value);
+                    x += count - 128;
+                } else {
+                    if count == 0 {
+                        return None;
+                    }
+                    for pixel in scanline.get_mut(x..x + count)? {
+                        pixel[c] = next()?;
+                    }
+                    x += count;
+                }
+            }
+        }
+    } else {
+        // flat pixels, where `1 1 1 n` repeats the previous pixel, `n` being
+        // shifted by 8 more bits on every consecutive repeat
+        let (mut x, mut shift) = (0, 0);
+        while x < width {
+            let pixel = [next()?, next()?, next()?, next()?];
+            if pixel[..3] == [1, 1, 1] && x > 0 {
+                if shift >= usize::BITS {
+                    return None;
+                }
+                let count = (pixel[3] as usize) << shift;
+                let previous = scanline[x - 1];
+                scanline.get_mut(x..x.checked_add(count)?)?.fill(previous);
+                x += count;
+                shift += 8;
+            } else {
+                scanline[x] = pixel;
+                x += 1;
+                shift = 0;
+            }
+        }
+    }
+    Some(())
+}
+
+/// Linear color of a pixel with a shared exponent.
+fn rgbe_to_linear(rgbe: &[u8; 4]) -> [f32; 3] {
+    if rgbe[3] == 0 {
+        return [0.; 3];
+    }
+    // the mantissas are fractions of 256
+    let scale = 2f32.powi(rgbe[3] as i32 - 136);
+    [
+        rgbe[0] as f32 * scale,
+        rgbe[1] as f32 * scale,
+        rgbe[2] as f32 * scale,
+    ]
+}
+
+#[test]
+fn test_hdr_parse() {
+    let header = |resolution: &str| {
+        format!(
+            "#?RADIANCE\n# made by hand\nFORMAT=32-bit_rle_rgbe\nEXPOSURE=1\n\n{}\n",
+            resolution
+        )
+        .into_bytes()
+    };
+
+    // flat pixels, with a repeat of the second one
+    let mut flat = header("-Y 1 +X 4");
+    flat.extend(&[128, 64, 0, 129, 0, 0, 128, 128, 1, 1, 1, 2]);
+    let image = HdrImage::parse(&flat[..]).unwrap();
+    assert_eq!((image.width, image.height), (4, 1));
+    assert_eq!(image.pixels[0], [1., 0.5, 0.]);
+    assert_eq!(image.pixels[1..], [[0., 0., 0.5]; 3]);
+
+    // run-length encoded, with a run and literals in every component
+    let mut encoded = header("-Y 2 +X 8");
+    for _ in 0..2 {
+        encoded.extend(&[2, 2, 0, 8]);
+        for &value in &[128, 0, 0, 130] {
+            encoded.extend(&[134, value, 2, value, value]);
+        }
+    }
+    let image = HdrImage::parse(&encoded[..]).unwrap();
+    assert_eq!(image.pixels.len(), 16);
+    assert!(image.pixels.iter().all(|&pixel| pixel == [2., 0., 0.]));
+
+    encoded.truncate(encoded.len() - 1);
+    assert!(matches!(
+        HdrImage::parse(&encoded[..]),
+        Err(HdrError::InvalidScanline { row: 1 })
+    ));
+    // more repeats than the bits of a count
+    let mut repeats = header("-Y 1 +X 4");
+    repeats.extend(&[128, 64, 0, 129]);
+    for _ in 0..9 {
+        repeats.extend(&[1, 1, 1, 0]);
+    }
+    assert!(matches!(
+        HdrImage::parse(&repeats[..]),
+        Err(HdrError::InvalidScanline { row: 0 })
+    ));
+
+    for resolution in [
+        "+Y 1 +X 4",
+        "-Y 100000 +X 100000",
+        "-Y 4 +X 99999999999999999999",
+    ] {
+        assert!(matches!(
+            HdrImage::parse(&header(resolution)[..]),
+            Err(HdrError::InvalidResolution(_))
+        ));
+    }
+    assert!(matches!(
+        HdrImage::parse(&b"P6\n"[..]),
+        Err(HdrError::NotRadiance)
+    ));
+}
@@ -0,0 +1,413 @@
+//! Light coming from every direction around the scene, captured in a
+//! panorama, for image-based lighting.
+//!
+//! An `Environment` given to `set_environment` lights the faces drawn with
+//! `RenderMode::Shaded`, in addition to `Config::light_direction` and
+//! `Config::ambient_light`: the diffuse light comes from the irradiance of the
+//! panorama, and the reflections from copies of it blurred for every
+//! roughness. The panorama may also be drawn as the background.
+
+mod harmonics;
+mod hdr;
+
+use std::f32::consts::PI;
+use std::path::Path;
+
+use cgmath::prelude::*;
+use cgmath::{Matrix3, Rad, Vector3};
+
+use harmonics::Irradiance;
+pub use hdr::{HdrError, HdrImage};
+
+/// Number of prefiltered reflections, for roughnesses evenly spread from a
+/// mirror to a fully rough surface.
+const SPECULAR_LEVELS: usize = 6;
+/// Largest width of the reflection of the first rough level, the following
+/// ones being half as wide as the previous one.
+const SPECULAR_WIDTH: usize = 128;
+
+/// Panorama lighting the scene.
+///
+/// Directions are those of the screen, `x` going to the right, `y` up and `z`
+/// towards the viewer.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    /// Light coming from every direction, in equirectangular projection: the
+    /// columns go around the vertical axis, from behind the scene on the left
+    /// edge, its middle being seen in front of the viewer, and the rows from
+    /// above to below.
+    panorama: HdrImage,
+    irradiance: Irradiance,
+    /// Reflections of the panorama, the first one for a mirror and the last
+    /// one for a fully rough surface.
+    specular: Vec<HdrImage>,
+    /// Scale of the light of the panorama.
+    pub intensity: f32,
+    /// Rotation of the panorama around the vertical axis, in degrees,
+    /// counterclockwise seen from above.
+    pub rotation: f32,
+    /// Draw the panorama behind the mesh with `RenderMode::Shaded`.
+    pub background: bool,
+    /// Vertical field of view of the background, in degrees. The mesh is drawn
+    /// with an orthographic projection, so the background is seen by a
+    /// perspective camera at the center of the screen.
+    pub field_of_view: f32,
+}
+
+impl Environment {
+    /// Light the scene with an equirectangular panorama, twice as wide as it
+    /// is high, as commonly used for `.hdr` environments.
+    ///
+    /// The reflections are prefiltered here, which takes a moment.
+    pub fn from_equirectangular(panorama: HdrImage) -> Self {
+        let irradiance = Irradiance::new(&panorama);
+        let specular = prefilter(&panorama);
+        Environment {
+            panorama,
+            irradiance,
+            specular,
+            intensity: 1.,
+            rotation: 0.,
+            background: true,
+            field_of_view: 60.,
+        }
+    }
+
+    /// Light the scene with the 6 square faces of a cubemap, in the order `+x`,
+    /// `-x`, `+y`, `-y`, `+z` and `-z`, with the orientations of OpenGL.
+    ///
+    /// # Panics
+    ///
+    /// If the faces are not squares of the same size.
+    pub fn from_cubemap(faces: &[HdrImage; 6]) -> Self {
+        let size = faces[0].width;
+        assert!(
+            faces
+                .iter()
+                .all(|face| face.width == size && face.height == size),
+            "the faces of a cubemap must be squares of the same size"
+        );
+        let (width, height) = (4 * size, 2 * size);
+        let mut panorama = HdrImage {
+            width,
+            height,
+            pixels: Vec::with_capacity(width * height),
+        };
+        for y in 0..height {
+            for x in 0..width {
+                let direction = texel_direction(&panorama, x, y);
+                panorama.pixels.push(cubemap_sample(faces, direction));
+            }
+        }
+        Self::from_equirectangular(panorama)
+    }
+
+    /// Load an equirectangular panorama from a Radiance `.hdr` file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, HdrError> {
+        Ok(Self::from_equirectangular(HdrImage::load(path)?))
+    }
+
+    /// Direction in the panorama of a direction of the screen.
+    fn rotate(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        Matrix3::from_angle_y(Rad(-self.rotation.to_radians())) * direction
+    }
+
+    fn scale(&self, color: [f32; 3]) -> [f32; 3] {
+        color.map(|v| v * self.intensity)
+    }
+
+    /// Light coming from the given direction, of unit length.
+    pub fn radiance(&self, direction: Vector3<f32>) -> [f32; 3] {
+        self.scale(sample(&self.panorama, self.rotate(direction)))
+    }
+
+    /// Light reflected by a white diffuse surface facing `normal`, of unit
+    /// length, which is the irradiance divided by pi.
+    pub fn diffuse(&self, normal: Vector3<f32>) -> [f32; 3] {
+        let irradiance = self.irradiance.at(self.rotate(normal));
+        self.scale(irradiance.map(|v| v / PI))
+    }
+
+    /// Light reflected towards the viewer by a surface of the given roughness,
+    /// from 0 for a mirror to 1, whose reflection of the view goes in
+    /// `direction`, of unit length.
+    pub fn specular(&self, direction: Vector3<f32>, roughness: f32) -> [f32; 3] {
+        let direction = self.rotate(direction);
+        let level = roughness.clamp(0., 1.) * (SPECULAR_LEVELS - 1) as f32;
+        let below = (level as usize).min(SPECULAR_LEVELS - 2);
+        let (a, b) = (
+            sample(&self.specular[below], direction),
+            sample(&self.specular[below + 1], direction),
+        );
+        let t = level - below as f32;
+        self.scale([0, 1, 2].map(|c| a[c] + (b[c] - a[c]) * t))
+    }
+
+    /// Draw the panorama, as seen with `field_of_view`, in the linear RGBA
+    /// `colors` of a frame of the given size, row after row from the top.
+    pub(crate) fn draw_background(&self, colors: &mut [f32], width: usize, height: usize) {
+        let tan = (self.field_of_view.to_radians() / 2.).tan();
+        let aspect = width as f32 / height as f32;
+        for (i, pixel) in colors.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (i % width, i / width);
+            let screen_x = x as f32 / (width - 1).max(1) as f32 * 2. - 1.;
+            let screen_y = 1. - y as f32 / (height - 1).max(1) as f32 * 2.;
+            let direction = Vector3::new(screen_x * tan * aspect, screen_y * tan, -1.);
+            let [r, g, b] = self.radiance(direction.normalize());
+            pixel.copy_from_slice(&[r, g, b, 1.]);
+        }
+    }
+}
+
+/// Direction of the center of the texel `(x, y)` of an equirectangular
+/// panorama.
+fn texel_direction(panorama: &HdrImage, x: usize, y: usize) -> Vector3<f32> {
+    let longitude = ((x as f32 + 0.5) / panorama.width as f32 - 0.5) * 2. * PI;
+    let polar = (y as f32 + 0.5) / panorama.height as f32 * PI;
+    Vector3::new(
+        polar.sin() * longitude.sin(),
+        polar.cos(),
+        -polar.sin() * longitude.cos(),
+    )
+}
+
+/// Solid angle covered by the texels of the row `y` of an equirectangular
+/// panorama, smaller towards the poles.
+fn texel_solid_angle(panorama: &HdrImage, y: usize) -> f32 {
+    let polar = (y as f32 + 0.5) / panorama.height as f32 * PI;
+    (2. * PI / panorama.width as f32) * (PI / panorama.height as f32) * polar.sin()
+}
+
+/// Color of the pixel `(x, y)` of `image`, wrapped around horizontally if
+/// `wrap` is set, and clamped to the nearest one otherwise.
+fn texel(image: &HdrImage, x: isize, y: isize, wrap: bool) -> [f32; 3] {
+    let x = if wrap {
+        x.rem_euclid(image.width as isize)
+    } else {
+        x.clamp(0, image.width as isize - 1)
+    };
+    let y = y.clamp(0, image.height as isize - 1);
+    image.pixels[y as usize * image.width + x as usize]
+}
+
+/// Color at the position `(x, y)` of `image`, in pixels from its top left
+/// corner, interpolated between the centers of the 4 pixels around it.
+fn bilinear(image: &HdrImage, x: f32, y: f32, wrap: bool) -> [f32; 3] {
+    let (x, y) = (x - 0.5, y - 0.5);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as isize, y0 as isize);
+    let mut color = [0.; 3];
+    for (dx, dy, weight) in [
+        (0, 0, (1. - fx) * (1. - fy)),
+        (1, 0, fx * (1. - fy)),
+        (0, 1, (1. - fx) * fy),
+        (1, 1, fx * fy),
+    ] {
+        let texel = texel(image, x0 + dx, y0 + dy, wrap);
+        for c in 0..3 {
+            color[c] += texel[c] * weight;
+        }
+    }
+    color
+}
+
+/// Color of an equirectangular panorama in the given direction, of unit
+/// length.
+fn sample(panorama: &HdrImage, direction: Vector3<f32>) -> [f32; 3] {
+    let u = 0.5 + direction.x.atan2(-direction.z) / (2. * PI);
+    let v = direction.y.clamp(-1., 1.).acos() / PI;
+    let (width, height) = (panorama.width as f32, panorama.height as f32);
+    bilinear(panorama, u * width, v * height, true)
+}
+
+/// Color of a cubemap in the given direction.
+fn cubemap_sample(faces: &[HdrImage; 6], d: Vector3<f32>) -> [f32; 3] {
+    let (ax, ay, az) = (d.x.abs(), d.y.abs(), d.z.abs());
+    // face, coordinates along the face from its top left corner, and the
+    // distance to it
+    let (face, s, t, major) = if ax >= ay && ax >= az {
+        if d.x > 0. {
+            (0, -d.z, -d.y, ax)
+        } else {
+            (1, d.z, -d.y, ax)
+        }
+    } else if ay >= az {
+        if d.y > 0. {
+            (2, d.x, d.z, ay)
+        } else {
+            (3, d.x, -d.z, ay)
+        }
+    } else if d.z > 0. {
+        (4, d.x, -d.y, az)
+    } else {
+        (5, -d.x, -d.y, az)
+    };
+    let size = faces[face].width as f32;
+    let (s, t) = ((s / major + 1.) / 2., (t / major + 1.) / 2.);
+    bilinear(&faces[face], s * size, t * size, false)
+}
+
+/// Half the size of `image`, averaging its pixels 2 by 2.
+fn downscale(image: &HdrImage) -> HdrImage {
+    let (width, height) = ((image.width / 2).max(1), (image.height / 2).max(1));
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let mut color = [0.; 3];
+            for &(dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let (x, y) = ((2 * x + dx) as isize, (2 * y + dy) as isize);
+                let texel = texel(image, x, y, false);
+                for c in 0..3 {
+                    color[c] += texel[c] / 4.;
+                }
+            }
+            pixels.push(color);
+        }
+    }
+    HdrImage {
+        width,
+        height,
+        pixels,
+    }
+}
+
+/// Reflections of an equirectangular panorama by surfaces of increasing
+/// roughness, seen along their normal.
+///
+/// Every texel is the average of the panorama weighted by the GGX
+/// distribution of the normals of the surface, as in "Real Shading in Unreal
+/// Engine 4" by Karis. Rougher reflections are blurrier, so they are computed
+/// at a lower resolution.
+fn prefilter(panorama: &HdrImage) -> Vec<HdrImage> {
+    let mut levels = vec![panorama.clone()];
+    let mut source = panorama.clone();
+    for level in 1..SPECULAR_LEVELS {
+        while source.width > (SPECULAR_WIDTH >> (level - 1)).max(8) {
+            source = downscale(&source);
+        }
+        let roughness = level as f32 / (SPECULAR_LEVELS - 1) as f32;
+        let alpha2 = roughness.powi(4);
+
+        // the light of every texel of the source, and where it comes from
+        let lights: Vec<_> = (0..source.height)
+            .flat_map(|y| (0..source.width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let weight = texel_solid_angle(&source, y);
+                let color = source.pixels[y * source.width + x].map(|v| v * weight);
+                (texel_direction(&source, x, y), weight, color)
+            })
+            .collect();
+        let mut pixels = Vec::with_capacity(source.pixels.len());
+        for y in 0..source.height {
+            for x in 0..source.width {
+                let reflected = texel_direction(&source, x, y);
+                let (mut sum, mut total) = ([0.; 3], 0.);
+                for (direction, weight, color) in &lights {
+                    let cos = reflected.dot(*direction);
+                    if cos <= 0. {
+                        continue;
+                    }
+                    // with the normal along the reflection, the half vector
+                    // is halfway to the light
+                    let cos_half2 = (1. + cos) / 2.;
+                    let d = cos_half2 * (alpha2 - 1.) + 1.;
+                    let ggx = alpha2 / (PI * d * d);
+                    for c in 0..3 {
+                        sum[c] += color[c] * ggx * cos;
+                    }
+                    total += weight * ggx * cos;
+                }
+                pixels.push(sum.map(|v| v / total));
+            }
+        }
+        levels.push(HdrImage {
+            width: source.width,
+            height: source.height,
+            pixels,
+        });
+    }
+    levels
+}
+
+#[test]
+fn test_environment() {
+    // a dark panorama with a bright spot on the horizon, in front of the
+    // viewer
+    let mut panorama = HdrImage {
+        width: 32,
+        height: 16,
+        pixels: vec![[0.1; 3]; 32 * 16],
+    };
+    for &(x, y) in &[(15, 7), (16, 7), (15, 8), (16, 8)] {
+        panorama.pixels[y * 32 + x] = [20.; 3];
+    }
+    let front = Vector3::new(0., 0., -1.);
+    let close = |color: [f32; 3], v: f32| color.iter().all(|c| (c - v).abs() < 1e-4);
+    let mut environment = Environment::from_equirectangular(panorama);
+
+    assert!(close(environment.radiance(front), 20.));
+    assert!(close(environment.radiance(Vector3::unit_y()), 0.1));
+    // reflections get blurrier with the roughness
+    let sharp = environment.specular(front, 0.)[0];
+    let rough = environment.specular(front, 0.5)[0];
+    assert!(sharp > rough && rough > environment.specular(front, 1.)[0]);
+    assert!(environment.specular(-front, 0.1)[0] < 0.2);
+    assert!(environment.diffuse(front)[0] > 5. * environment.diffuse(-front)[0]);
+
+    // the spot is turned to the right of the viewer
+    environment.rotation = -90.;
+    environment.intensity = 2.;
+    assert!(close(environment.radiance(Vector3::unit_x()), 40.));
+    assert!(environment.diffuse(Vector3::unit_x())[0] > 5. * environment.diffuse(front)[0]);
+
+    // a cubemap of a single bright face
+    let face = |color| HdrImage {
+        width: 4,
+        height: 4,
+        pixels: vec![color; 16],
+    };
+    let mut faces = [[0.; 3]; 6].map(face);
+    faces[2] = face([1.; 3]);
+    let environment = Environment::from_cubemap(&faces);
+    assert!(close(environment.radiance(Vector3::unit_y()), 1.));
+    assert!(close(environment.radiance(front), 0.));
+}
+
+#[test]
+fn test_environment_rendering() {
+    use crate::fixtures::{self, pixel, squares};
+    use crate::{init_with_mesh, set_environment, Config, Culling, RenderMode};
+
+    let (mesh, mut config) = squares();
+    // only lit by a green panorama
+    config.light_direction = Vector3::new(0., 0., 0.);
+    let panorama = HdrImage {
+        width: 8,
+        height: 4,
+        pixels: vec![[0., 0.5, 0.]; 32],
+    };
+    let mut environment = Environment::from_equirectangular(panorama);
+    // the color drawn in the middle of the screen
+    let draw = |config: Config, environment: &Environment| {
+        let mut rcontext = init_with_mesh(config, mesh.clone());
+        set_environment(&mut rcontext, Some(environment.clone()));
+        pixel(&fixtures::draw(&mut rcontext), &config, 100, 100)[..3].to_vec()
+    };
+
+    // the diffuse light, 0.5 encoded to 188, with a faint reflection
+    let lit = draw(config, &environment);
+    assert!(
+        lit[0] == 0 && lit[1] > 188 && lit[1] < 195 && lit[2] == 0,
+        "{:?}",
+        lit
+    );
+    // the background, behind the culled faces
+    config.culling = Culling::Front;
+    assert_eq!(draw(config, &environment), vec![0, 188, 0]);
+    config.render_mode = RenderMode::Normals;
+    assert_eq!(draw(config, &environment), vec![0, 0, 0]);
+    config.render_mode = RenderMode::Shaded;
+    environment.background = false;
+    assert_eq!(draw(config, &environment), vec![0, 0, 0]);
+}
@@ -0,0 +1,78 @@
+// scenes shared by the tests of the renderer
+
+use std::sync::Arc;
+
+use cgmath::Vector3;
+
+use crate::mesh::{Group, IndexTuple, Material, MeshData, ObjMaterial, Object};
+use crate::{init_with_mesh, render_scene, Config, RendererContext};
+
+/// Two squares of different groups, the front one covering the middle of the
+/// back one, with a `Config` showing them on a 201x201 screen.
+pub fn squares<'a>() -> (MeshData, Config<'a>) {
+    let square = |z: f32, min: f32, max: f32| {
+        vec![
+            Vector3::new(min, min, z),
+            Vector3::new(max, min, z),
+            Vector3::new(max, max, z),
+            Vector3::new(min, max, z),
+        ]
+    };
+    let mut object = Object::new("quads".to_string());
+    for (name, first) in [("front", 0), ("back", 4)].iter() {
+        let mut group = Group::new(name.to_string());
+        group.polys = vec![(*first..first + 4)
+            .map(|i| IndexTuple(i, None, None))
+            .collect()];
+        object.groups.push(group);
+    }
+    let mut position = square(0., 0., 2.);
+    position.extend(square(-1., -1., 3.));
+    let mesh = MeshData {
+        position,
+        objects: vec![object],
+        ..MeshData::default()
+    };
+    let config = Config {
+        width: 201,
+        height: 201,
+        ssao_radius: 0.1,
+        ..Config::default()
+    };
+    (mesh, config)
+}
+
+/// The `squares`, the large one behind being made of a half transparent
+/// material.
+pub fn glass_squares<'a>() -> (MeshData, Config<'a>) {
+    let (mut mesh, config) = squares();
+    let glass = Material {
+        d: Some(0.5),
+        ..Material::new("glass".to_string())
+    };
+    mesh.objects[0].groups[1].material = Some(ObjMaterial::Mtl(Arc::new(glass)));
+    (mesh, config)
+}
+
+/// Draw `mesh` once, returning the frame buffer along with the context, to
+/// read its other buffers.
+pub fn render<'a>(config: Config<'a>, mesh: &MeshData) -> (Vec<u8>, RendererContext<'a>) {
+    let mut rcontext = init_with_mesh(config, mesh.clone());
+    let frame = draw(&mut rcontext);
+    (frame, rcontext)
+}
+
+/// Draw the scene of `rcontext` to a new frame buffer.
+pub fn draw(rcontext: &mut RendererContext) -> Vec<u8> {
+    let config = &rcontext.config;
+    let mut frame = vec![0; (config.width * config.height * 4) as usize];
+    render_scene(rcontext, &mut frame);
+    frame
+}
+
+/// RGBA color of the pixel in column `x` and row `y` of the frame buffer,
+/// rows going down from the top of the screen.
+pub fn pixel(frame: &[u8], config: &Config, x: u32, y: u32) -> [u8; 4] {
+    let i = 4 * (y * config.width + x) as usize;
+    [frame[i], frame[i + 1], frame[i + 2], frame[i + 3]]
+}
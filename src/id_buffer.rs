@@ -0,0 +1,159 @@
+//! Per-pixel labels written by the rasterizer alongside the frame buffer.
+//!
+//! Every covered pixel gets the object, group, material and triangle indices
+//! of the visible triangle, which can be exported to build segmentation
+//! datasets.
+
+use std::io::{self, Write};
+
+/// Label of the pixels not covered by any triangle, and of the material of
+/// groups without one.
+pub const NO_ID: u32 = u32::MAX;
+
+/// One of the labels of `IdBuffers`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdTarget {
+    /// Index of the object in `MeshData::objects`.
+    Object,
+    /// Index of the group in `Object::groups`.
+    Group,
+    /// Index of the material, as numbered by `MeshData::group_materials`.
+    Material,
+    /// Index of the triangle in the `VertexBuffer` of the level of detail
+    /// drawn.
+    Triangle,
+}
+
+/// Labels of a single triangle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ids {
+    pub object: u32,
+    pub group: u32,
+    pub material: u32,
+    pub triangle: u32,
+}
+
+/// `u32` labels of the triangle visible at every pixel.
+///
+/// Pixels are stored like in the frame buffer, row after row from the top of
+/// the image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdBuffers {
+    pub width: u32,
+    pub height: u32,
+    pub object: Vec<u32>,
+    pub group: Vec<u32>,
+    pub material: Vec<u32>,
+    pub triangle: Vec<u32>,
+}
+
+impl IdBuffers {
+    pub fn new(width: u32, height: u32) -> Self {
+        let empty = vec![NO_ID; (width * height) as usize];
+        IdBuffers {
+            width,
+            height,
+            object: empty.clone(),
+            group: empty.clone(),
+            material: empty.clone(),
+            triangle: empty,
+        }
+    }
+
+    /// Reset every pixel to `NO_ID`.
+    pub fn clear(&mut self) {
+        for buffer in [
+            &mut self.object,
+            &mut self.group,
+            &mut self.material,
+            &mut self.triangle,
+        ] {
+            buffer.iter_mut().for_each(|id| *id = NO_ID);
+        }
+    }
+
+    pub(crate) fn set(&mut self, pixel: usize, ids: &Ids) {
+        self.object[pixel] = ids.object;
+        self.group[pixel] = ids.group;
+        self.material[pixel] = ids.material;
+        self.triangle[pixel] = ids.triangle;
+    }
+
+    /// Take the labels of the pixel nearest to the center of every block of
+    /// `factor` by `factor` pixels of `source`.
+    pub(crate) fn downsample_from(&mut self, source: &IdBuffers, factor: u32) {
+        let pairs = [
+            (&mut self.object, &source.object),
+            (&mut self.group, &source.group),
+            (&mut self.material, &source.material),
+            (&mut self.triangle, &source.triangle),
+        ];
+        for (target, source_ids) in pairs {
+            for (i, id) in target.iter_mut().enumerate() {
+                let x = i as u32 % self.width * factor + factor / 2;
+                let y = i as u32 / self.width * factor + factor / 2;
+                *id = source_ids[(y * source.width + x) as usize];
+            }
+        }
+    }
+
+    pub fn get(&self, target: IdTarget) -> &[u32] {
+        match target {
+            IdTarget::Object => &self.object,
+            IdTarget::Group => &self.group,
+            IdTarget::Material => &self.material,
+            IdTarget::Triangle => &self.triangle,
+        }
+    }
+
+    /// Labels as RGBA pixels holding the little endian bytes of every `u32`,
+    /// so that they are kept as is by lossless image formats.
+    pub fn to_rgba(&self, target: IdTarget) -> Vec<u8> {
+        self.get(target)
+            .iter()
+            .flat_map(|id| id.to_le_bytes())
+            .collect()
+    }
+
+    /// Write the labels as raw little endian `u32`.
+    pub fn write_raw<W: Write>(&self, target: IdTarget, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.to_rgba(target))
+    }
+
+    /// Write the labels as a PAM image, with pixels encoded as by `to_rgba`.
+    pub fn write_pam<W: Write>(&self, target: IdTarget, writer: &mut W) -> io::Result<()> {
+        write!(
+            writer,
+            "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+            self.width, self.height
+        )?;
+        self.write_raw(target, writer)
+    }
+}
+
+#[test]
+fn test_id_buffers_export() {
+    let mut ids = IdBuffers::new(2, 1);
+    ids.set(
+        1,
+        &Ids {
+            object: 1,
+            group: 2,
+            material: NO_ID,
+            triangle: 0x0102_0304,
+        },
+    );
+    assert_eq!(ids.get(IdTarget::Object), &[NO_ID, 1]);
+    assert_eq!(
+        ids.to_rgba(IdTarget::Triangle),
+        vec![255, 255, 255, 255, 4, 3, 2, 1]
+    );
+
+    let mut pam = Vec::new();
+    ids.write_pam(IdTarget::Group, &mut pam).unwrap();
+    assert!(pam.starts_with(b"P7\nWIDTH 2\nHEIGHT 1\n"));
+    assert!(pam.ends_with(&[255, 255, 255, 255, 2, 0, 0, 0]));
+
+    ids.clear();
+    assert!(ids.triangle.iter().all(|&id| id == NO_ID));
+}
@@ -10,54 +10,778 @@
 #![feature(test)]
 extern crate test;
 
-mod mesh;
+pub mod environment;
+#[cfg(test)]
+mod fixtures;
+pub mod id_buffer;
+pub mod mesh;
+pub mod post;
 mod renderer;
 mod utils;
 
-use cgmath::Vector3;
-use mesh::{MeshData, MeshLoader};
+use cgmath::{Matrix4, Vector3};
+use environment::Environment;
+use id_buffer::IdBuffers;
+use mesh::{Bvh, FaceRef, LodChain, MeshData, MeshLoader, Normalization};
+use post::PostEffect;
+use renderer::OitBuffers;
 
-const BLACK: [u8; 4] = [0, 0, 0, 255];
+/// What the rasterizer shows, the other modes than `Shaded` helping to debug
+/// meshes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    /// Flat shading lit by `Config::light_direction`.
+    Shaded,
+    /// Normals as RGB, interpolated from the vertex normals if every corner
+    /// has one. The camera is not rotated, so normals in world and view
+    /// coordinates are the same.
+    Normals,
+    /// Depth from the `zbuffer`, from white for the nearest to black for the
+    /// farthest pixel.
+    Depth,
+    /// Texture coordinates as red and green, wrapped to `[0, 1]`.
+    Uv,
+    /// Checkerboard in texture coordinates, showing stretching and seams.
+    UvChecker,
+    /// Front faces, as set by `Config::front_face`, in green and back faces
+    /// in red. Back faces are only seen without culling.
+    FaceOrientation,
+    /// Heatmap of the number of triangles drawn on every pixel, hidden or
+    /// not, from blue to red for 8 and more.
+    Overdraw,
+}
+
+impl RenderMode {
+    const ALL: [RenderMode; 7] = [
+        RenderMode::Shaded,
+        RenderMode::Normals,
+        RenderMode::Depth,
+        RenderMode::Uv,
+        RenderMode::UvChecker,
+        RenderMode::FaceOrientation,
+        RenderMode::Overdraw,
+    ];
+
+    /// The following mode, going back to `Shaded` after the last one.
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|&mode| mode == self).unwrap();
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    /// Whether the colors are computed once every triangle is drawn.
+    fn is_resolved(self) -> bool {
+        matches!(self, RenderMode::Depth | RenderMode::Overdraw)
+    }
+}
+
+/// How the edges of the polygons are drawn, with `Config::default_color`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wireframe {
+    /// Only the faces.
+    None,
+    /// Only the edges, the hidden ones too unless `Config::line_depth_test`
+    /// is set.
+    Lines,
+    /// The faces, with their visible edges drawn over them.
+    Overlay,
+    /// Only the visible edges, on an empty background, as in technical
+    /// drawings.
+    HiddenLine,
+}
+
+impl Wireframe {
+    const ALL: [Wireframe; 4] = [
+        Wireframe::None,
+        Wireframe::Lines,
+        Wireframe::Overlay,
+        Wireframe::HiddenLine,
+    ];
+
+    /// The following mode, going back to `None` after the last one.
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|&mode| mode == self).unwrap();
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    /// Whether the faces are drawn as set by `Config::render_mode`.
+    fn shows_faces(self) -> bool {
+        matches!(self, Wireframe::None | Wireframe::Overlay)
+    }
+}
+
+/// Order of the vertices of front faces, as seen on screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Winding {
+    /// The convention of `.obj` files.
+    CounterClockwise,
+    Clockwise,
+}
+
+/// Faces skipped by the rasterizer, depending on their winding on screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Culling {
+    None,
+    Back,
+    Front,
+}
+
+/// How transparent faces are combined with what is behind them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Blending {
+    /// Mixed by their opacity, for glass-like surfaces.
+    Alpha,
+    /// Added, brightening what is behind, for glows.
+    Additive,
+    /// Multiplied, tinting what is behind, for filters.
+    Multiply,
+}
+
+/// How alpha blended faces are put in order. Additive and multiplicative
+/// blending don't depend on the order of the faces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transparency {
+    /// Transparent groups are drawn from back to front after the opaque
+    /// ones, and so are their triangles. Intersecting groups may still be
+    /// drawn in the wrong order.
+    Sorted,
+    /// Weighted blended order-independent transparency: the transparent
+    /// faces are averaged, the nearest ones weighing more, which handles
+    /// intersecting surfaces at the cost of accuracy with opaque faces.
+    WeightedBlended,
+}
+
+/// Mapping of the linear colors of the shaded faces, which may be brighter
+/// than the screen, to the colors of the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMapping {
+    /// Colors brighter than the screen are clipped.
+    Clamp,
+    /// Reinhard's operator, `c / (1 + c)`, compressing the highlights.
+    Reinhard,
+    /// An approximation of the filmic curve of ACES, with more contrast.
+    AcesFilmic,
+}
+
+/// Smoothing of the edges of the triangles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AntiAliasing {
+    None,
+    /// Multisampling with 2 samples per pixel: the coverage and the depth are
+    /// computed for every sample, but triangles are shaded once per pixel.
+    Msaa2,
+    /// Multisampling with 4 samples per pixel.
+    Msaa4,
+    /// Multisampling with 8 samples per pixel.
+    Msaa8,
+    /// Supersampling: the scene is drawn at the given multiple of the
+    /// resolution, then downsampled. Lines and points are smoothed too, at
+    /// the cost of drawing every pixel several times.
+    Ssaa(u32),
+}
+
+impl AntiAliasing {
+    /// Samples per pixel with multisampling, 1 otherwise.
+    fn msaa_samples(self) -> usize {
+        match self {
+            AntiAliasing::Msaa2 => 2,
+            AntiAliasing::Msaa4 => 4,
+            AntiAliasing::Msaa8 => 8,
+            _ => 1,
+        }
+    }
+
+    /// Scale of the resolution with supersampling, 1 otherwise.
+    fn ssaa_factor(self) -> u32 {
+        match self {
+            AntiAliasing::Ssaa(factor) => factor.max(1),
+            _ => 1,
+        }
+    }
+}
+
+/// What is drawn behind the mesh. The colors are sRGB encoded like
+/// `Config::default_color`, and their alpha is written to the frame buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background<'a> {
+    Color([u8; 4]),
+    /// Vertical gradient from the color at the top of the screen to the one
+    /// at the bottom.
+    Gradient {
+        top: [u8; 4],
+        bottom: [u8; 4],
+    },
+    /// Checkerboard of squares of `size` pixels of both colors, showing the
+    /// transparency of the faces.
+    Checkerboard {
+        colors: [[u8; 4]; 2],
+        size: u32,
+    },
+    /// RGBA image, row after row from the top, stretched over the screen.
+    /// `pixels` must hold `4 * width * height` bytes, the background is
+    /// transparent otherwise or if the image is empty.
+    Image {
+        width: u32,
+        height: u32,
+        pixels: &'a [u8],
+    },
+    /// Fully transparent pixels, for the frames to be composited over
+    /// something else.
+    Transparent,
+}
 
 #[derive(Copy, Clone)]
 pub struct Config<'a> {
     pub width: u32,
     pub height: u32,
     pub mesh_path: &'a str,
+    /// Direction towards the light, whose length is the intensity of the
+    /// light. Lights brighter than 1 need `tone_mapping` not to clip.
     pub light_direction: Vector3<f32>,
-    pub is_wireframe: bool,
+    pub wireframe: Wireframe,
     pub default_color: [u8; 4],
+    /// Drawn where there is nothing else, unless the environment is, see
+    /// `environment::Environment::background`.
+    pub background: Background<'a>,
+    /// Number of simplified levels of detail generated for the mesh.
+    pub lod_levels: usize,
+    /// Largest error allowed on screen, in pixels, when choosing the level of
+    /// detail to draw.
+    pub lod_pixel_error: f32,
+    /// Cross-fade between two levels of detail with a dither pattern when the
+    /// budget gets close to the next coarser level.
+    pub lod_cross_fade: bool,
+    /// How the mesh is placed on screen. The positions of the mesh are kept
+    /// as loaded, the placement is applied when drawing.
+    pub normalization: Normalization,
+    /// Write the object, group, material and triangle of every pixel to
+    /// `IdBuffers`, see `id_buffers`. Nothing is written when the faces are
+    /// not shown, see `Wireframe`.
+    pub id_buffers: bool,
+    /// What is shown of the faces of the mesh.
+    pub render_mode: RenderMode,
+    /// Winding of the faces looking at the viewer.
+    pub front_face: Winding,
+    pub culling: Culling,
+    /// Light the back faces as the other side of the surface instead of
+    /// leaving them dark, for thin geometry seen from both sides.
+    pub two_sided_lighting: bool,
+    pub anti_aliasing: AntiAliasing,
+    /// Intensity of the light coming from every direction, which also lights
+    /// the faces turned away from `light_direction`.
+    pub ambient_light: f32,
+    /// Number of samples of the screen space ambient occlusion of every
+    /// pixel, darkening the ambient light in the creases. 0 disables it.
+    pub ssao_samples: u32,
+    /// Distance from which the surfaces around a point occlude it, in the
+    /// `[-1, 1]` units of the screen.
+    pub ssao_radius: f32,
+    /// Part of the ambient light removed by a full occlusion, from 0 to 1.
+    pub ssao_strength: f32,
+    /// Exposure in stops, the linear colors being scaled by `2^exposure`
+    /// before tone mapping. Only applied with `RenderMode::Shaded`.
+    pub exposure: f32,
+    pub tone_mapping: ToneMapping,
+    /// Width of the wireframe, lines and points, in pixels.
+    pub line_width: f32,
+    /// Draw lines and points with anti-aliased edges.
+    pub smooth_lines: bool,
+    /// Blending of the faces with a material having a `d` or `Tr` opacity
+    /// below 1. Transparency is only shown with `RenderMode::Shaded`, and
+    /// transparent faces are not written to the `IdBuffers`.
+    pub blending: Blending,
+    pub transparency: Transparency,
+    /// Hide the wireframe, lines and points behind the faces. When the faces
+    /// are not shown, their depth is drawn first for the lines to be tested
+    /// against. Always done with `Wireframe::Overlay` and
+    /// `Wireframe::HiddenLine`.
+    pub line_depth_test: bool,
+}
+
+impl Config<'_> {
+    /// Configuration of the frame actually drawn, larger than the frame
+    /// buffer with supersampling.
+    fn supersampled(&self) -> Self {
+        let factor = self.anti_aliasing.ssaa_factor();
+        Config {
+            width: self.width * factor,
+            height: self.height * factor,
+            lod_pixel_error: self.lod_pixel_error * factor as f32,
+            line_width: self.line_width * factor as f32,
+            ..*self
+        }
+    }
+
+    /// Whether lines are hidden behind the faces.
+    fn depth_tests_lines(&self) -> bool {
+        self.line_depth_test || matches!(self.wireframe, Wireframe::Overlay | Wireframe::HiddenLine)
+    }
+}
+
+impl Default for Config<'_> {
+    /// A 512x512 shaded frame of the mesh fitted to the screen and lit from
+    /// the viewer, with the levels of detail, ambient light and effects off.
+    fn default() -> Self {
+        Config {
+            width: 512,
+            height: 512,
+            mesh_path: "",
+            light_direction: Vector3::new(0., 0., 1.),
+            wireframe: Wireframe::None,
+            default_color: [255, 255, 255, 255],
+            background: Background::Color([0, 0, 0, 255]),
+            lod_levels: 0,
+            lod_pixel_error: 1.,
+            lod_cross_fade: false,
+            normalization: Normalization::FitCube,
+            id_buffers: false,
+            render_mode: RenderMode::Shaded,
+            front_face: Winding::CounterClockwise,
+            culling: Culling::Back,
+            two_sided_lighting: false,
+            anti_aliasing: AntiAliasing::None,
+            ambient_light: 0.,
+            ssao_samples: 0,
+            ssao_radius: 0.05,
+            ssao_strength: 1.,
+            exposure: 0.,
+            tone_mapping: ToneMapping::Clamp,
+            line_width: 1.,
+            smooth_lines: false,
+            blending: Blending::Alpha,
+            transparency: Transparency::Sorted,
+            line_depth_test: false,
+        }
+    }
 }
 
 pub struct RendererContext<'a> {
     config: Config<'a>,
-    mesh: MeshData,
+    lods: LodChain,
+    /// Transform from the coordinates of the mesh to the `[-1, 1]` cube.
+    model: Matrix4<f32>,
+    /// Screen coordinates of the vertex buffer being drawn, recomputed every
+    /// frame.
+    screen: Vec<Vector3<f32>>,
+    /// Depth of every pixel, or of every sample with multisampling.
     zbuffer: Vec<f32>,
+    /// Linear colors of the pixels drawn, premultiplied by their alpha,
+    /// encoded to the frame buffer once the scene is drawn.
+    colors: Vec<f32>,
+    /// Colors of the samples with multisampling, empty otherwise.
+    samples: Vec<f32>,
+    /// Ambient occlusion of the pixels, empty unless enabled by
+    /// `Config::ssao_samples`.
+    occlusion: Vec<f32>,
+    /// Colors averaged to the size of the frame buffer with supersampling,
+    /// empty otherwise.
+    downsampled: Vec<f32>,
+    /// Transparent fragments, empty unless enabled by `Config::transparency`.
+    oit: OitBuffers,
+    /// Labels of the pixels, if enabled by `Config::id_buffers`.
+    ids: Option<IdBuffers>,
+    /// Labels of the pixels of `colors` with supersampling, if enabled.
+    supersampled_ids: Option<IdBuffers>,
+    /// Effects applied to the frames, see `post_effects`.
+    post_effects: Vec<Box<dyn PostEffect>>,
+    /// Depth of the pixels of the frame buffer, for the post effects.
+    depth: Vec<f32>,
+    /// Light around the scene, see `set_environment`.
+    environment: Option<Environment>,
+    /// Colors of the pixels of `colors` where nothing is drawn, from
+    /// `Config::background`.
+    background: Vec<f32>,
+    /// Colors of the environment drawn instead of `background` behind the
+    /// shaded faces, empty unless it is drawn.
+    skybox: Vec<f32>,
+    /// Hierarchy over the original mesh, used for picking.
+    bvh: Bvh,
+}
+
+/// What is drawn under a pixel, as returned by `pick`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pick {
+    pub object_name: String,
+    pub group_name: String,
+    /// Index telling apart groups with the same name.
+    pub group_index: usize,
+    /// Indices of the object, the group and the polygon.
+    pub face: FaceRef,
+    /// Indices in the polygon of the corners of the triangle under the pixel,
+    /// polygons being split in triangle fans.
+    pub corners: [usize; 3],
+    /// Weights of `corners` at the picked point.
+    pub barycentric: Vector3<f32>,
+    /// Index in `MeshData::position` of the corner closest to the picked
+    /// point.
+    pub vertex: usize,
+    /// Picked point, in the coordinates of the mesh file.
+    pub position: Vector3<f32>,
 }
 
 pub fn init<'a, 'b: 'a>(config: Config<'b>) -> RendererContext<'a> {
-    let mut mesh = MeshLoader::load(config.mesh_path).unwrap().data;
-    mesh.normalize_vertices();
+    let mut loader = MeshLoader::load(config.mesh_path).unwrap();
+    // groups whose material library can't be loaded are opaque
+    let _ = loader.load_mtls();
+    init_with_mesh(config, loader.data)
+}
 
+/// Same as `init`, with a mesh already in memory instead of `mesh_path`.
+pub fn init_with_mesh<'a, 'b: 'a>(config: Config<'b>, mut mesh: MeshData) -> RendererContext<'a> {
+    // faces pointing to missing vertex data would crash the rasterizer
+    mesh.remove_invalid_faces();
+    let model = mesh.normalization(config.normalization);
+    let bvh = Bvh::new(&mesh);
+
+    let drawn = config.supersampled();
+    let pixels = (drawn.width * drawn.height) as usize;
+    let samples = config.anti_aliasing.msaa_samples();
+    let supersampling = config.anti_aliasing.ssaa_factor() > 1;
+    let mut background = vec![0.; 4 * pixels];
+    renderer::draw_background(
+        &config.background,
+        &mut background,
+        drawn.width as usize,
+        drawn.height as usize,
+        config.anti_aliasing.ssaa_factor(),
+    );
     return RendererContext {
         config: config,
-        mesh: mesh,
-        zbuffer: vec![f32::MIN; (config.width * config.height) as usize],
+        model,
+        lods: LodChain::new(mesh, config.lod_levels),
+        screen: Vec::new(),
+        zbuffer: vec![f32::MIN; pixels * samples],
+        colors: vec![0.; 4 * pixels],
+        samples: if samples > 1 {
+            vec![0.; 4 * pixels * samples]
+        } else {
+            Vec::new()
+        },
+        occlusion: if config.ssao_samples > 0 {
+            vec![1.; pixels]
+        } else {
+            Vec::new()
+        },
+        downsampled: if supersampling {
+            vec![0.; 4 * (config.width * config.height) as usize]
+        } else {
+            Vec::new()
+        },
+        oit: OitBuffers::new(match config.transparency {
+            Transparency::WeightedBlended => pixels * samples,
+            Transparency::Sorted => 0,
+        }),
+        ids: if config.id_buffers {
+            Some(IdBuffers::new(config.width, config.height))
+        } else {
+            None
+        },
+        supersampled_ids: if config.id_buffers && supersampling {
+            Some(IdBuffers::new(drawn.width, drawn.height))
+        } else {
+            None
+        },
+        bvh,
+        post_effects: Vec::new(),
+        depth: Vec::new(),
+        environment: None,
+        background,
+        skybox: Vec::new(),
     };
 }
 
+/// Find the polygon drawn at the pixel `(x, y)` of the frame buffer, counted
+/// from its top left corner.
+///
+/// The original mesh is picked, whatever level of detail is drawn, and lines
+/// and points are ignored.
+pub fn pick(rcontext: &RendererContext, x: u32, y: u32) -> Option<Pick> {
+    let bounds = rcontext.bvh.bounds();
+    let ray = renderer::pixel_ray(x, y, &rcontext.model, &bounds, &rcontext.config)?;
+    let hit = rcontext.bvh.intersect(&ray)?;
+
+    let triangle = &rcontext.bvh.triangles[hit.triangle];
+    let face = triangle.face;
+    let object = &rcontext.lods.base().mesh.objects[face.object];
+    let group = &object.groups[face.group];
+    let closest = (0..3)
+        .max_by(|&a, &b| hit.barycentric[a].total_cmp(&hit.barycentric[b]))
+        .unwrap();
+    Some(Pick {
+        object_name: object.name.clone(),
+        group_name: group.name.clone(),
+        group_index: group.index,
+        face,
+        corners: triangle.corners,
+        barycentric: hit.barycentric,
+        vertex: group.polys[face.face][triangle.corners[closest]].0,
+        position: hit.position,
+    })
+}
+
+/// Labels of the pixels drawn by the last `render_scene`, if enabled by
+/// `Config::id_buffers`.
+pub fn id_buffers<'a>(rcontext: &'a RendererContext) -> Option<&'a IdBuffers> {
+    rcontext.ids.as_ref()
+}
+
+/// Change what the following frames show.
+pub fn set_render_mode(rcontext: &mut RendererContext, mode: RenderMode) {
+    rcontext.config.render_mode = mode;
+}
+
+/// Change how the edges of the following frames are drawn.
+pub fn set_wireframe(rcontext: &mut RendererContext, wireframe: Wireframe) {
+    rcontext.config.wireframe = wireframe;
+}
+
+/// Effects applied in order to the following frames, once they are drawn,
+/// see `post::PostEffect`. Empty at first.
+pub fn post_effects<'a>(rcontext: &'a mut RendererContext) -> &'a mut Vec<Box<dyn PostEffect>> {
+    &mut rcontext.post_effects
+}
+
+/// Light the following frames with an environment, or stop doing so with
+/// `None`, see `environment::Environment`.
+pub fn set_environment(rcontext: &mut RendererContext, environment: Option<Environment>) {
+    rcontext.skybox.clear();
+    if let Some(environment) = environment.as_ref().filter(|e| e.background) {
+        let drawn = rcontext.config.supersampled();
+        rcontext.skybox = vec![0.; rcontext.colors.len()];
+        environment.draw_background(
+            &mut rcontext.skybox,
+            drawn.width as usize,
+            drawn.height as usize,
+        );
+    }
+    rcontext.environment = environment;
+}
+
 pub fn render_scene(rcontext: &mut RendererContext, frame_buffer: &mut [u8]) {
-    let mesh = &rcontext.mesh;
     let config = rcontext.config;
-    let zbuffer = &mut rcontext.zbuffer[..];
+    let drawn = config.supersampled();
+    let supersampling = drawn.width != config.width;
 
-    // clear the frame buffer
-    utils::clear(frame_buffer, &BLACK);
+    // clear the color buffers to the background, the environment behind the
+    // shaded faces. The modes resolving the pixels once drawn start from
+    // transparent ones instead, the background being put behind them after
+    let faces = config.wireframe.shows_faces();
+    let resolved = faces && config.render_mode.is_resolved();
+    let shaded = faces && config.render_mode == RenderMode::Shaded;
+    let background = if shaded && !rcontext.skybox.is_empty() {
+        &rcontext.skybox
+    } else {
+        &rcontext.background
+    };
+    if resolved {
+        utils::clear(&mut rcontext.colors, &[0.; 4]);
+        utils::clear(&mut rcontext.samples, &[0.; 4]);
+    } else {
+        rcontext.colors.copy_from_slice(background);
+        let count = config.anti_aliasing.msaa_samples();
+        for (pixel, samples) in background
+            .chunks_exact(4)
+            .zip(rcontext.samples.chunks_exact_mut(4 * count))
+        {
+            utils::clear(samples, pixel);
+        }
+    }
 
     // clear z buffer
-    for i in 0..(config.width * config.height) as usize {
-        zbuffer[i] = f32::MIN;
+    for z in rcontext.zbuffer.iter_mut() {
+        *z = f32::MIN;
+    }
+
+    let mut ids = if supersampling {
+        rcontext.supersampled_ids.as_mut()
+    } else {
+        rcontext.ids.as_mut()
+    };
+    if let Some(ids) = ids.as_deref_mut() {
+        ids.clear();
+    }
+
+    let mut targets = renderer::Targets {
+        frame: &mut rcontext.colors,
+        zbuffer: &mut rcontext.zbuffer,
+        samples: &mut rcontext.samples,
+        occlusion: &mut rcontext.occlusion,
+        oit: &mut rcontext.oit,
+        ids,
+    };
+    renderer::render_object(
+        &rcontext.lods,
+        &rcontext.model,
+        &mut rcontext.screen,
+        rcontext.environment.as_ref(),
+        &drawn,
+        &mut targets,
+    );
+    if resolved {
+        renderer::put_behind(&mut rcontext.colors, &rcontext.background);
+    }
+
+    let factor = config.anti_aliasing.ssaa_factor();
+    let colors = if supersampling {
+        renderer::downsample(
+            &rcontext.colors,
+            &mut rcontext.downsampled,
+            config.width as usize,
+            factor as usize,
+        );
+        if let (Some(ids), Some(supersampled)) = (&mut rcontext.ids, &rcontext.supersampled_ids) {
+            ids.downsample_from(supersampled, factor);
+        }
+        &mut rcontext.downsampled
+    } else {
+        &mut rcontext.colors
+    };
+
+    if rcontext.post_effects.is_empty() {
+        renderer::expose(colors, &config);
+    } else {
+        rcontext
+            .depth
+            .resize((config.width * config.height) as usize, f32::MIN);
+        renderer::resolve_depth(
+            &rcontext.zbuffer,
+            &mut rcontext.depth,
+            config.anti_aliasing.msaa_samples(),
+            config.width as usize,
+            factor as usize,
+        );
+        post::apply_effects(&mut rcontext.post_effects, colors, &rcontext.depth, &config);
     }
+    renderer::encode(colors, frame_buffer);
+}
+
+#[test]
+fn test_pick() {
+    use cgmath::InnerSpace;
+
+    let (mesh, config) = fixtures::squares();
+    let rcontext = init_with_mesh(config, mesh);
+
+    // the middle of the right half of the front square, at (1.5, 1, 0)
+    let picked = pick(&rcontext, 125, 100).unwrap();
+    assert_eq!(picked.object_name, "quads");
+    assert_eq!(picked.group_name, "front");
+    assert_eq!(picked.face.face, 0);
+    assert_eq!(picked.corners, [0, 1, 2]);
+    assert!((picked.barycentric - Vector3::new(0.25, 0.25, 0.5)).magnitude2() < 1e-8);
+    assert_eq!(picked.vertex, 2);
+    assert!((picked.position - Vector3::new(1.5, 1., 0.)).magnitude2() < 1e-8);
+
+    // the top left half of the front square, in its second triangle
+    let top = pick(&rcontext, 75, 60).unwrap();
+    assert_eq!((top.group_name.as_str(), top.face.face), ("front", 0));
+    assert_eq!(top.corners, [0, 2, 3]);
+    assert_eq!(top.vertex, 3);
+    assert!((top.position - Vector3::new(0.5, 1.8, 0.)).magnitude2() < 1e-8);
+
+    assert_eq!(pick(&rcontext, 10, 100).unwrap().group_name, "back");
+    assert_eq!(pick(&rcontext, 201, 100), None);
+}
+
+#[test]
+fn test_id_buffers() {
+    use id_buffer::NO_ID;
+
+    let (mesh, mut config) = fixtures::squares();
+    config.id_buffers = true;
+    let mut rcontext = init_with_mesh(config, mesh);
+    let mut frame = vec![0; (config.width * config.height * 4) as usize];
+    render_scene(&mut rcontext, &mut frame);
+
+    let ids = id_buffers(&rcontext).unwrap();
+    let at = |x: u32, y: u32| (y * config.width + x) as usize;
+    // the pixels used by `test_pick`
+    let front = at(125, 100);
+    assert_eq!((ids.object[front], ids.group[front]), (0, 0));
+    assert_eq!(ids.material[front], NO_ID);
+    let top = at(75, 60);
+    assert_eq!((ids.object[top], ids.group[top]), (0, 0));
+    assert_eq!((ids.triangle[front], ids.triangle[top]), (0, 1));
+    let back = at(10, 100);
+    assert_eq!((ids.object[back], ids.group[back]), (0, 1));
+    assert!(ids.triangle[back] >= 2);
+}
+
+#[test]
+fn test_post_effects() {
+    use std::sync::{Arc, Mutex};
 
-    renderer::render_object(mesh, &config, frame_buffer, zbuffer);
+    /// Whether the effect is before tone mapping, the width of the frame, and
+    /// the red channel and the depth of the pixels it saw.
+    type Record = (bool, usize, [f32; 2], [f32; 2]);
+
+    /// Effect recording the red channel and the depth of the middle of the
+    /// screen, and of the large square on its left.
+    struct Probe {
+        hdr: bool,
+        seen: Arc<Mutex<Vec<Record>>>,
+    }
+    impl PostEffect for Probe {
+        fn before_tone_mapping(&self) -> bool {
+            self.hdr
+        }
+
+        fn apply(&mut self, frame: &mut post::Frame) {
+            let pixels = [100 * frame.width + 100, 100 * frame.width + 10];
+            let red = pixels.map(|i| frame.colors[4 * i]);
+            let depth = pixels.map(|i| frame.depth[i]);
+            self.seen
+                .lock()
+                .unwrap()
+                .push((self.hdr, frame.width, red, depth));
+        }
+    }
+
+    let (mesh, mut config) = fixtures::squares();
+    // twice as bright as the screen
+    config.light_direction = Vector3::new(0., 0., 2.);
+    config.anti_aliasing = AntiAliasing::Ssaa(2);
+    let mut rcontext = init_with_mesh(config, mesh);
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    for &hdr in &[false, true] {
+        let seen = seen.clone();
+        post_effects(&mut rcontext).push(Box::new(Probe { hdr, seen }));
+    }
+    let mut frame = vec![0; (config.width * config.height * 4) as usize];
+    render_scene(&mut rcontext, &mut frame);
+
+    // the effects before tone mapping come first, and see the bright colors,
+    // at the size of the frame buffer
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.len(), 2);
+    assert_eq!((seen[0].0, seen[0].1, seen[0].2), (true, 201, [2., 2.]));
+    assert_eq!((seen[1].0, seen[1].1, seen[1].2), (false, 201, [1., 1.]));
+    let depth = seen[0].3;
+    assert!(depth[0] > depth[1] && depth[1] > f32::MIN);
+}
+
+#[test]
+fn test_anti_aliasing() {
+    let (mesh, mut config) = fixtures::squares();
+    // the edges of the front square are between two pixels
+    config.render_mode = RenderMode::Depth;
+    config.id_buffers = true;
+    let edge_pixels = |config: Config| {
+        let mut rcontext = init_with_mesh(config, mesh.clone());
+        let mut frame = vec![0; (config.width * config.height * 4) as usize];
+        render_scene(&mut rcontext, &mut frame);
+        let ids = id_buffers(&rcontext).unwrap();
+        assert_eq!(ids.group[(100 * config.width + 125) as usize], 0);
+        frame.iter().filter(|&&v| v > 0 && v < 255).count()
+    };
+
+    assert_eq!(edge_pixels(config), 0);
+    for &anti_aliasing in &[
+        AntiAliasing::Msaa2,
+        AntiAliasing::Msaa4,
+        AntiAliasing::Msaa8,
+        AntiAliasing::Ssaa(2),
+    ] {
+        config.anti_aliasing = anti_aliasing;
+        assert!(edge_pixels(config) > 0, "{:?}", anti_aliasing);
+    }
 }
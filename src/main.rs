@@ -1,11 +1,13 @@
 use cgmath::Vector3;
 use pixels::{wgpu::Surface, Pixels, SurfaceTexture};
 use std::env;
-use winit::dpi::LogicalSize;
-use winit::event::{Event, WindowEvent};
+use winit::dpi::{LogicalSize, PhysicalPosition};
+use winit::event::{ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 
+use toy_renderer::environment::Environment;
+use toy_renderer::post;
 use toy_renderer::Config;
 
 // global variables
@@ -45,12 +47,26 @@ fn main() {
         width: WIDTH,
         height: HEIGHT,
         mesh_path: file_path,
-        is_wireframe: false,
         light_direction: LIGHT_DIR,
         default_color: WHITE,
+        lod_levels: 3,
+        lod_cross_fade: true,
+        ambient_light: 0.2,
+        ssao_samples: 16,
+        ..Config::default()
     };
 
     let mut rcontext = toy_renderer::init(config);
+    // the mesh may be lit by a panorama given after it
+    if let Some(path) = args.get(2) {
+        match Environment::load(path) {
+            Ok(environment) => toy_renderer::set_environment(&mut rcontext, Some(environment)),
+            Err(err) => println!("environment not loaded: {}", err),
+        }
+    }
+    let mut cursor = PhysicalPosition::new(0., 0.);
+    let mut render_mode = config.render_mode;
+    let mut wireframe = config.wireframe;
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Wait;
@@ -68,6 +84,110 @@ fn main() {
                 pixels.resize(size.width, size.height);
             }
 
+            Event::WindowEvent {
+                event: WindowEvent::CursorMoved { position, .. },
+                window_id: _,
+            } => cursor = position,
+
+            Event::WindowEvent {
+                event:
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: MouseButton::Left,
+                        ..
+                    },
+                window_id: _,
+            } => {
+                // the frame buffer is stretched over the window
+                let size = window.inner_size();
+                let x = (cursor.x * WIDTH as f64 / size.width as f64) as u32;
+                let y = (cursor.y * HEIGHT as f64 / size.height as f64) as u32;
+                match toy_renderer::pick(&rcontext, x, y) {
+                    Some(pick) => println!(
+                        "({}, {}): object {:?}, group {:?} #{}, face {}, vertex {}, \
+                         barycentric ({:.3}, {:.3}, {:.3}), position ({:.4}, {:.4}, {:.4})",
+                        x,
+                        y,
+                        pick.object_name,
+                        pick.group_name,
+                        pick.group_index,
+                        pick.face.face,
+                        pick.vertex,
+                        pick.barycentric.x,
+                        pick.barycentric.y,
+                        pick.barycentric.z,
+                        pick.position.x,
+                        pick.position.y,
+                        pick.position.z,
+                    ),
+                    None => println!("({}, {}): nothing", x, y),
+                }
+            }
+
+            // cycle through the render modes
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::M),
+                                ..
+                            },
+                        ..
+                    },
+                window_id: _,
+            } => {
+                render_mode = render_mode.next();
+                toy_renderer::set_render_mode(&mut rcontext, render_mode);
+                println!("render mode: {:?}", render_mode);
+            }
+
+            // cycle through the ways of drawing the edges
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::W),
+                                ..
+                            },
+                        ..
+                    },
+                window_id: _,
+            } => {
+                wireframe = wireframe.next();
+                toy_renderer::set_wireframe(&mut rcontext, wireframe);
+                println!("wireframe: {:?}", wireframe);
+            }
+
+            // toggle the post-processing effects
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::P),
+                                ..
+                            },
+                        ..
+                    },
+                window_id: _,
+            } => {
+                let effects = toy_renderer::post_effects(&mut rcontext);
+                if effects.is_empty() {
+                    effects.push(Box::new(post::Fxaa::default()));
+                    effects.push(Box::new(post::Outline::default()));
+                    effects.push(Box::new(post::Vignette::default()));
+                    println!("post effects: on");
+                } else {
+                    effects.clear();
+                    println!("post effects: off");
+                }
+            }
+
             Event::MainEventsCleared => {
                 window.request_redraw();
             }
@@ -0,0 +1,370 @@
+// bounding volumes of `MeshData`
+
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Vector3, Vector4};
+
+use super::MeshData;
+
+/// Axis aligned bounding box.
+///
+/// An empty box has `min` greater than `max`, so extending it with a point
+/// gives a box containing only that point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Aabb {
+    pub fn empty() -> Self {
+        Aabb {
+            min: Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a Vector3<f32>>) -> Self {
+        let mut aabb = Aabb::empty();
+        for p in points {
+            aabb.extend(*p);
+        }
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn extend(&mut self, p: Vector3<f32>) {
+        for i in 0..3 {
+            self.min[i] = self.min[i].min(p[i]);
+            self.max[i] = self.max[i].max(p[i]);
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let mut aabb = *self;
+        aabb.extend(other.min);
+        aabb.extend(other.max);
+        aabb
+    }
+
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) / 2.
+    }
+
+    pub fn size(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+
+    /// Area of the faces of the box, 0 if it is empty.
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.;
+        }
+        let d = self.size();
+        2. * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    /// Distance along the ray at which it enters the box, if it does so
+    /// before `t_max`.
+    pub fn intersect_ray(&self, ray: &Ray, t_max: f32) -> Option<f32> {
+        let mut t0 = 0f32;
+        let mut t1 = t_max;
+        for i in 0..3 {
+            // a zero direction gives infinite distances, which work out
+            let inverse = 1. / ray.direction[i];
+            let mut near = (self.min[i] - ray.origin[i]) * inverse;
+            let mut far = (self.max[i] - ray.origin[i]) * inverse;
+            if near > far {
+                std::mem::swap(&mut near, &mut far);
+            }
+            // written so that NaN distances are ignored
+            t0 = if near > t0 { near } else { t0 };
+            t1 = if far < t1 { far } else { t1 };
+            if t0 > t1 {
+                return None;
+            }
+        }
+        Some(t0)
+    }
+}
+
+/// Bounding sphere.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sphere {
+    pub center: Vector3<f32>,
+    pub radius: f32,
+}
+
+impl Sphere {
+    /// Sphere around the points, using Ritter's algorithm.
+    ///
+    /// The sphere is not the smallest possible, but usually within a few
+    /// percents of it. It has a radius of 0 if there are no points. Points
+    /// with a NaN or infinite coordinate are left out.
+    pub fn from_points(points: &[Vector3<f32>]) -> Self {
+        let finite = || {
+            points
+                .iter()
+                .cloned()
+                .filter(|p| p.x.is_finite() && p.y.is_finite() && p.z.is_finite())
+        };
+        let first = match finite().next() {
+            Some(p) => p,
+            None => {
+                return Sphere {
+                    center: Vector3::zero(),
+                    radius: 0.,
+                }
+            }
+        };
+        let farthest = |from: Vector3<f32>| {
+            finite()
+                .max_by(|a, b| (a - from).magnitude2().total_cmp(&(b - from).magnitude2()))
+                .unwrap()
+        };
+        let a = farthest(first);
+        let b = farthest(a);
+        let mut sphere = Sphere {
+            center: (a + b) / 2.,
+            radius: (b - a).magnitude() / 2.,
+        };
+        for p in finite() {
+            let distance = (p - sphere.center).magnitude();
+            if distance > sphere.radius {
+                // grow the sphere just enough to contain the point
+                let radius = (sphere.radius + distance) / 2.;
+                sphere.center += (p - sphere.center) * ((radius - sphere.radius) / distance);
+                sphere.radius = radius;
+            }
+        }
+        sphere
+    }
+}
+
+/// A half line starting at `origin`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    /// Direction of the ray, distances along the ray are in units of its
+    /// length.
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: Vector3<f32>, direction: Vector3<f32>) -> Self {
+        Ray { origin, direction }
+    }
+
+    pub fn at(&self, t: f32) -> Vector3<f32> {
+        self.origin + self.direction * t
+    }
+}
+
+/// Convex volume bounded by planes, like the volume seen by a camera.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frustum {
+    /// Planes `(a, b, c, d)` such that points inside verify
+    /// `a x + b y + c z + d >= 0`.
+    pub planes: Vec<Vector4<f32>>,
+}
+
+impl Frustum {
+    /// Volume projected inside the `[-1, 1]` cube by the `view_projection`
+    /// matrix.
+    pub fn from_matrix(view_projection: Matrix4<f32>) -> Self {
+        let m = view_projection;
+        let rows = [m.row(0), m.row(1), m.row(2), m.row(3)];
+        let planes = (0..3)
+            .flat_map(|i| vec![rows[3] + rows[i], rows[3] - rows[i]])
+            .collect();
+        Frustum { planes }
+    }
+
+    /// Whether the box is at least partly inside. Boxes outside but close to
+    /// a corner of the frustum may also be reported as inside.
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        if aabb.is_empty() {
+            return false;
+        }
+        self.planes.iter().all(|plane| {
+            // corner of the box the farthest along the plane normal
+            let corner = Vector4::new(
+                if plane.x >= 0. {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                if plane.y >= 0. {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                if plane.z >= 0. {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+                1.,
+            );
+            plane.dot(corner) >= 0.
+        })
+    }
+}
+
+/// How a mesh is placed in the `[-1, 1]` cube shown by the renderer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Normalization {
+    /// Center the bounding box, and scale its largest side to 2.
+    FitCube,
+    /// Center the bounding sphere, and scale it to a radius of 1.
+    FitSphere,
+    /// Center the bounding box, keeping the size of the mesh.
+    Center,
+    /// Keep the positions as they are.
+    None,
+    /// Keep the origin and the units of the file, showing the given number of
+    /// model units on every side of the origin, so that meshes in the same
+    /// units keep their relative sizes.
+    Units(f32),
+}
+
+impl MeshData {
+    /// Transform placing the mesh as selected by `normalization`.
+    ///
+    /// Meshes without extent are only moved.
+    pub fn normalization(&self, normalization: Normalization) -> Matrix4<f32> {
+        let aabb = self.aabb();
+        if aabb.is_empty() {
+            return Matrix4::identity();
+        }
+        let fit = |center: Vector3<f32>, half_size: f32| {
+            let scale = if half_size > 0. { 1. / half_size } else { 1. };
+            Matrix4::from_scale(scale) * Matrix4::from_translation(-center)
+        };
+        match normalization {
+            Normalization::FitCube => {
+                let size = aabb.size();
+                fit(aabb.center(), size.x.max(size.y).max(size.z) / 2.)
+            }
+            Normalization::FitSphere => {
+                let sphere = self.bounding_sphere();
+                fit(sphere.center, sphere.radius)
+            }
+            Normalization::Center => Matrix4::from_translation(-aabb.center()),
+            Normalization::None => Matrix4::identity(),
+            Normalization::Units(extent) => fit(Vector3::zero(), extent),
+        }
+    }
+
+    /// Box around every position of the mesh.
+    pub fn aabb(&self) -> Aabb {
+        Aabb::from_points(&self.position)
+    }
+
+    /// Positions used by the polygons, lines and points of a group.
+    fn group_positions(&self, object: usize, group: usize) -> Vec<Vector3<f32>> {
+        let group = &self.objects[object].groups[group];
+        let polys = group.polys.iter().chain(group.lines.iter());
+        polys
+            .flat_map(|poly| poly.iter().map(|v| v.0))
+            .chain(group.points.iter().cloned())
+            .filter_map(|p| self.position.get(p).cloned())
+            .collect()
+    }
+
+    fn object_positions(&self, object: usize) -> Vec<Vector3<f32>> {
+        (0..self.objects[object].groups.len())
+            .flat_map(|group| self.group_positions(object, group))
+            .collect()
+    }
+
+    /// Box around the positions used by an object.
+    pub fn object_aabb(&self, object: usize) -> Aabb {
+        Aabb::from_points(&self.object_positions(object))
+    }
+
+    /// Box around the positions used by a group.
+    pub fn group_aabb(&self, object: usize, group: usize) -> Aabb {
+        Aabb::from_points(&self.group_positions(object, group))
+    }
+
+    /// Sphere around every position of the mesh.
+    pub fn bounding_sphere(&self) -> Sphere {
+        Sphere::from_points(&self.position)
+    }
+
+    /// Sphere around the positions used by an object.
+    pub fn object_bounding_sphere(&self, object: usize) -> Sphere {
+        Sphere::from_points(&self.object_positions(object))
+    }
+
+    /// Sphere around the positions used by a group.
+    pub fn group_bounding_sphere(&self, object: usize, group: usize) -> Sphere {
+        Sphere::from_points(&self.group_positions(object, group))
+    }
+}
+
+#[test]
+fn test_normalization() {
+    let mesh = MeshData {
+        position: vec![Vector3::new(0.1, 0.2, 0.2), Vector3::new(0.3, 0.2, 0.6)],
+        ..MeshData::default()
+    };
+    let transform = |n| {
+        let m = mesh.normalization(n);
+        [0, 1].map(|i| (m * mesh.position[i].extend(1.)).truncate())
+    };
+
+    // small meshes are scaled up too
+    let cube = transform(Normalization::FitCube);
+    assert!((cube[0] - Vector3::new(-0.5, 0., -1.)).magnitude() < 1e-5);
+    assert!((cube[1] - Vector3::new(0.5, 0., 1.)).magnitude() < 1e-5);
+    let sphere = transform(Normalization::FitSphere);
+    assert!(((sphere[0] - sphere[1]).magnitude() - 2.).abs() < 1e-5);
+    let center = transform(Normalization::Center);
+    assert!((center[1] - Vector3::new(0.1, 0., 0.2)).magnitude() < 1e-5);
+    assert_eq!(
+        transform(Normalization::None),
+        [mesh.position[0], mesh.position[1]]
+    );
+    let units = transform(Normalization::Units(0.5));
+    assert!((units[1] - Vector3::new(0.6, 0.4, 1.2)).magnitude() < 1e-5);
+}
+
+#[test]
+fn test_bounds() {
+    let points = [
+        Vector3::new(1., 2., 3.),
+        Vector3::new(2., 4., 3.),
+        Vector3::new(1.5, 3., 5.),
+    ];
+    let aabb = Aabb::from_points(&points);
+    assert_eq!(aabb.min, Vector3::new(1., 2., 3.));
+    assert_eq!(aabb.max, Vector3::new(2., 4., 5.));
+    assert!(Aabb::empty().is_empty());
+
+    let sphere = Sphere::from_points(&points);
+    for p in &points {
+        assert!((p - sphere.center).magnitude() <= sphere.radius + 1e-5);
+    }
+    let broken = [
+        points[0],
+        Vector3::new(f32::NAN, 0., 0.),
+        points[1],
+        points[2],
+    ];
+    assert_eq!(Sphere::from_points(&broken), sphere);
+    let sphere = Sphere::from_points(&[Vector3::new(0., f32::INFINITY, 0.)]);
+    assert_eq!(sphere.radius, 0.);
+
+    let ray = Ray::new(Vector3::new(0., 3., 4.), Vector3::new(1., 0., 0.));
+    assert_eq!(aabb.intersect_ray(&ray, f32::INFINITY), Some(1.));
+    assert_eq!(aabb.intersect_ray(&ray, 0.5), None);
+
+    // the cube from -4 to 4
+    let frustum = Frustum::from_matrix(Matrix4::from_scale(0.25));
+    assert!(frustum.intersects(&aabb));
+    assert!(!frustum.intersects(&Aabb::from_points(&[Vector3::new(5., 0., 0.)])));
+}
@@ -0,0 +1,384 @@
+// bounding volume hierarchy over the triangles of `MeshData`
+
+use cgmath::prelude::*;
+use cgmath::Vector3;
+
+use super::{Aabb, FaceRef, Frustum, MeshData, Ray};
+
+/// Number of bins along each axis used to evaluate the splits of a node.
+const SAH_BINS: usize = 12;
+/// Nodes with at most this many triangles are never split.
+const MIN_LEAF_SIZE: usize = 2;
+/// Cost of traversing a node, relative to intersecting a triangle.
+const TRAVERSAL_COST: f32 = 1.;
+
+/// A triangle of the fan of a polygon.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BvhTriangle {
+    pub face: FaceRef,
+    /// Indices of the corners of the triangle in its polygon.
+    pub corners: [usize; 3],
+    pub vertices: [Vector3<f32>; 3],
+}
+
+/// A node of a `Bvh`.
+///
+/// Leaves hold `count` triangles starting at `first`, while the children of
+/// other nodes are the nodes `first` and `first + 1`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BvhNode {
+    pub bounds: Aabb,
+    pub first: usize,
+    pub count: usize,
+}
+
+impl BvhNode {
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+}
+
+/// Closest intersection of a ray with a `Bvh`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    /// Distance along the ray, in units of the ray direction.
+    pub distance: f32,
+    /// Index of the triangle in `Bvh::triangles`.
+    pub triangle: usize,
+    /// Weights of the corners of the triangle at the intersection.
+    pub barycentric: Vector3<f32>,
+    pub position: Vector3<f32>,
+}
+
+/// Bounding volume hierarchy over the triangles of a mesh, built with the
+/// surface area heuristic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bvh {
+    /// Nodes of the tree, the root first.
+    pub nodes: Vec<BvhNode>,
+    /// Triangles, ordered so that every leaf holds a contiguous range.
+    pub triangles: Vec<BvhTriangle>,
+}
+
+/// Möller-Trumbore intersection, returning the distance and the weights of the
+/// second and third corners.
+fn intersect_triangle(ray: &Ray, v: &[Vector3<f32>; 3]) -> Option<(f32, f32, f32)> {
+    let e1 = v[1] - v[0];
+    let e2 = v[2] - v[0];
+    let p = ray.direction.cross(e2);
+    let det = e1.dot(p);
+    if det.abs() < f32::EPSILON {
+        return None;
+    }
+    let inverse = 1. / det;
+    let s = ray.origin - v[0];
+    let u = s.dot(p) * inverse;
+    if !(0. ..=1.).contains(&u) {
+        return None;
+    }
+    let q = s.cross(e1);
+    let w = ray.direction.dot(q) * inverse;
+    if w < 0. || u + w > 1. {
+        return None;
+    }
+    let t = e2.dot(q) * inverse;
+    if t >= 0. {
+        Some((t, u, w))
+    } else {
+        None
+    }
+}
+
+impl Bvh {
+    /// Build the hierarchy over the triangle fans of the valid polygons.
+    pub fn new(mesh: &MeshData) -> Self {
+        let mut triangles = Vec::new();
+        for (face, poly) in mesh.faces() {
+            if !mesh.is_valid_face(poly) {
+                continue;
+            }
+            for i in 1..poly.len().saturating_sub(1) {
+                let corners = [0, i, i + 1];
+                triangles.push(BvhTriangle {
+                    face,
+                    corners,
+                    vertices: corners.map(|c| mesh.position[poly[c].0]),
+                });
+            }
+        }
+
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            triangles,
+        };
+        let bounds: Vec<Aabb> = bvh
+            .triangles
+            .iter()
+            .map(|t| Aabb::from_points(&t.vertices))
+            .collect();
+        let mut order: Vec<usize> = (0..bvh.triangles.len()).collect();
+        bvh.nodes.push(BvhNode {
+            bounds: Aabb::empty(),
+            first: 0,
+            count: 0,
+        });
+        bvh.build(0, &mut order, 0, &bounds);
+
+        let mut triangles: Vec<Option<BvhTriangle>> = bvh.triangles.drain(..).map(Some).collect();
+        bvh.triangles = order
+            .iter()
+            .map(|&i| triangles[i].take().unwrap())
+            .collect();
+        bvh
+    }
+
+    /// Fill the node with the triangles `order[first..]`, splitting it if
+    /// that lowers the estimated cost of the intersections.
+    fn build(&mut self, node: usize, order: &mut [usize], first: usize, bounds: &[Aabb]) {
+        let node_bounds = order
+            .iter()
+            .fold(Aabb::empty(), |acc, &t| acc.union(&bounds[t]));
+        let leaf = BvhNode {
+            bounds: node_bounds,
+            first,
+            count: order.len(),
+        };
+        self.nodes[node] = leaf;
+        if order.len() <= MIN_LEAF_SIZE {
+            return;
+        }
+
+        let centroids = Aabb::from_points(
+            &order
+                .iter()
+                .map(|&t| bounds[t].center())
+                .collect::<Vec<_>>(),
+        );
+        let mut best: Option<(f32, usize, f32)> = None;
+        for axis in 0..3 {
+            let (min, max) = (centroids.min[axis], centroids.max[axis]);
+            if max <= min {
+                continue;
+            }
+            let bin = |t: usize| {
+                let b = ((bounds[t].center()[axis] - min) / (max - min) * SAH_BINS as f32) as usize;
+                b.min(SAH_BINS - 1)
+            };
+            let mut bins = [(Aabb::empty(), 0usize); SAH_BINS];
+            for &t in order.iter() {
+                let b = &mut bins[bin(t)];
+                b.0 = b.0.union(&bounds[t]);
+                b.1 += 1;
+            }
+
+            // sweep from the right to know the cost of every right side
+            let mut right = [(0f32, 0usize); SAH_BINS];
+            let mut acc = (Aabb::empty(), 0);
+            for b in (1..SAH_BINS).rev() {
+                acc = (acc.0.union(&bins[b].0), acc.1 + bins[b].1);
+                right[b] = (acc.0.surface_area(), acc.1);
+            }
+            let mut left = (Aabb::empty(), 0);
+            for b in 0..SAH_BINS - 1 {
+                left = (left.0.union(&bins[b].0), left.1 + bins[b].1);
+                let (right_area, right_count) = right[b + 1];
+                if left.1 == 0 || right_count == 0 {
+                    continue;
+                }
+                let cost = left.0.surface_area() * left.1 as f32 + right_area * right_count as f32;
+                if best.is_none_or(|(c, _, _)| cost < c) {
+                    let split = min + (max - min) * (b + 1) as f32 / SAH_BINS as f32;
+                    best = Some((cost, axis, split));
+                }
+            }
+        }
+
+        let area = node_bounds.surface_area();
+        let (axis, split) = match best {
+            Some((cost, axis, split))
+                if area <= 0. || TRAVERSAL_COST + cost / area < order.len() as f32 =>
+            {
+                (axis, split)
+            }
+            _ => return,
+        };
+
+        // partition in place, left of the split first
+        let mut mid = 0;
+        for i in 0..order.len() {
+            if bounds[order[i]].center()[axis] < split {
+                order.swap(i, mid);
+                mid += 1;
+            }
+        }
+        if mid == 0 || mid == order.len() {
+            return;
+        }
+
+        let children = self.nodes.len();
+        self.nodes[node].count = 0;
+        self.nodes[node].first = children;
+        for _ in 0..2 {
+            self.nodes.push(leaf);
+        }
+        let (left, right) = order.split_at_mut(mid);
+        self.build(children, left, first, bounds);
+        self.build(children + 1, right, first + mid, bounds);
+    }
+
+    /// Stack of the nodes to visit, starting with the root unless there are no
+    /// triangles, in which case the root is not a valid leaf.
+    fn root(&self) -> Vec<usize> {
+        if self.triangles.is_empty() {
+            Vec::new()
+        } else {
+            vec![0]
+        }
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.nodes[0].bounds
+    }
+
+    /// Closest intersection of the ray with the triangles, both sides of the
+    /// triangles being considered.
+    pub fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        let mut closest: Option<Hit> = None;
+        let mut stack = self.root();
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            let t_max = closest.as_ref().map_or(f32::INFINITY, |hit| hit.distance);
+            if node.bounds.intersect_ray(ray, t_max).is_none() {
+                continue;
+            }
+            if !node.is_leaf() {
+                stack.push(node.first);
+                stack.push(node.first + 1);
+                continue;
+            }
+            for t in node.first..node.first + node.count {
+                if let Some((distance, u, w)) = intersect_triangle(ray, &self.triangles[t].vertices)
+                {
+                    if distance < closest.as_ref().map_or(f32::INFINITY, |hit| hit.distance) {
+                        closest = Some(Hit {
+                            distance,
+                            triangle: t,
+                            barycentric: Vector3::new(1. - u - w, u, w),
+                            position: ray.at(distance),
+                        });
+                    }
+                }
+            }
+        }
+        closest
+    }
+
+    /// Whether the ray hits any triangle closer than `t_max`, for shadow and
+    /// visibility queries.
+    pub fn occluded(&self, ray: &Ray, t_max: f32) -> bool {
+        let mut stack = self.root();
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            if node.bounds.intersect_ray(ray, t_max).is_none() {
+                continue;
+            }
+            if !node.is_leaf() {
+                stack.push(node.first);
+                stack.push(node.first + 1);
+                continue;
+            }
+            let triangles = &self.triangles[node.first..node.first + node.count];
+            if triangles
+                .iter()
+                .any(|t| intersect_triangle(ray, &t.vertices).is_some_and(|hit| hit.0 < t_max))
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Call `visit` with the index of every triangle in a leaf intersecting
+    /// the frustum.
+    pub fn cull<F: FnMut(usize)>(&self, frustum: &Frustum, mut visit: F) {
+        let mut stack = self.root();
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            if !frustum.intersects(&node.bounds) {
+                continue;
+            }
+            if node.is_leaf() {
+                (node.first..node.first + node.count).for_each(&mut visit);
+            } else {
+                stack.push(node.first);
+                stack.push(node.first + 1);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_bvh_matches_brute_force() {
+    // a bumpy grid of 32 x 32 quads
+    let mut mesh = MeshData::default();
+    let n = 32;
+    for y in 0..=n {
+        for x in 0..=n {
+            let z = ((x * 7 + y * 13) % 5) as f32 * 0.1;
+            mesh.position.push(Vector3::new(x as f32, y as f32, z));
+        }
+    }
+    let mut object = super::Object::new("grid".to_string());
+    let mut group = super::Group::new("grid".to_string());
+    for y in 0..n {
+        for x in 0..n {
+            let i = y * (n + 1) + x;
+            let quad = [i, i + 1, i + n + 2, i + n + 1];
+            group.polys.push(
+                quad.iter()
+                    .map(|&p| super::IndexTuple(p, None, None))
+                    .collect(),
+            );
+        }
+    }
+    object.groups.push(group);
+    mesh.objects.push(object);
+
+    let bvh = Bvh::new(&mesh);
+    assert_eq!(bvh.triangles.len(), 2 * n * n);
+    assert!(bvh.nodes.len() > 1);
+
+    for i in 0..50 {
+        let origin = Vector3::new(i as f32 * 0.61 + 0.3, i as f32 * 0.37 + 0.2, 5.);
+        let ray = Ray::new(origin, Vector3::new(0.05, -0.02, -1.));
+        let brute = bvh
+            .triangles
+            .iter()
+            .filter_map(|t| intersect_triangle(&ray, &t.vertices))
+            .map(|hit| hit.0)
+            .fold(f32::INFINITY, f32::min);
+        let hit = bvh.intersect(&ray);
+        assert_eq!(
+            hit.as_ref().map_or(f32::INFINITY, |hit| hit.distance),
+            brute
+        );
+        assert_eq!(bvh.occluded(&ray, 10.), hit.is_some());
+    }
+
+    // the quads with x and y between 1 and 3
+    let frustum = Frustum::from_matrix(
+        cgmath::Matrix4::from_nonuniform_scale(1., 1., 0.1)
+            * cgmath::Matrix4::from_translation(Vector3::new(-2., -2., 0.)),
+    );
+    let mut visible = Vec::new();
+    bvh.cull(&frustum, |t| visible.push(t));
+    assert!(visible.len() < bvh.triangles.len() / 4);
+    for t in &bvh.triangles {
+        let inside = t
+            .vertices
+            .iter()
+            .all(|v| v.x > 1. && v.x < 3. && v.y > 1. && v.y < 3.);
+        let index = bvh.triangles.iter().position(|other| other == t).unwrap();
+        assert!(!inside || visible.contains(&index));
+    }
+}
@@ -0,0 +1,36 @@
+// meshes shared by the tests of the mesh operations
+
+use cgmath::Vector3;
+
+use super::{Group, IndexTuple, MeshData, Object};
+
+/// The unit cube, as a closed mesh of 6 quads wound counter-clockwise seen
+/// from the outside, all in a single group.
+///
+/// Vertex `i` is at `(i & 1, (i >> 1) & 1, (i >> 2) & 1)`, and the face on top
+/// is the 4th one.
+pub fn cube() -> MeshData {
+    let mut mesh = MeshData::default();
+    for i in 0..8 {
+        let bit = |b: usize| ((i >> b) & 1) as f32;
+        mesh.position.push(Vector3::new(bit(0), bit(1), bit(2)));
+    }
+    let mut object = Object::new("cube".to_string());
+    let mut group = Group::new("cube".to_string());
+    let faces = [
+        [0, 2, 3, 1],
+        [4, 5, 7, 6],
+        [0, 1, 5, 4],
+        [2, 6, 7, 3],
+        [0, 4, 6, 2],
+        [1, 3, 7, 5],
+    ];
+    for face in faces.iter() {
+        group
+            .polys
+            .push(face.iter().map(|&p| IndexTuple(p, None, None)).collect());
+    }
+    object.groups.push(group);
+    mesh.objects.push(object);
+    mesh
+}
@@ -0,0 +1,340 @@
+// tessellation of free-form curves & surfaces (`cstype`, `curv`, `surf`)
+
+use cgmath::{Vector2, Vector3, Zero};
+
+use super::{IndexTuple, MeshData, SimplePolygon};
+
+/// Number of line segments (or quads in each direction for surfaces) generated
+/// for every polynomial span of a free-form element.
+const SEGMENTS_PER_SPAN: usize = 8;
+
+/// Basis of a free-form element as set by the `cstype` command.
+///
+/// Only Bézier and B-spline bases are supported, elements using other bases
+/// (`bmatrix`, `cardinal`, `taylor`) are skipped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CurveType {
+    Bezier,
+    BSpline,
+}
+
+/// A free-form element collected between its `curv`/`surf` command and the
+/// matching `end`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+    Curve {
+        /// Parameter range `u0 u1` to evaluate.
+        range: [f32; 2],
+        /// Control points.
+        control: Vec<IndexTuple>,
+    },
+    Surface {
+        /// Parameter ranges `s0 s1 t0 t1` to evaluate.
+        range: [f32; 4],
+        /// Control points, varying fastest in the `u` direction.
+        control: Vec<IndexTuple>,
+    },
+}
+
+/// Free-form state of the .obj parser.
+///
+/// `cstype` and `deg` are state commands and stay in effect until changed,
+/// while the element and its `parm` values are reset after every `end`.
+///
+/// Elements are only tessellated once the whole file is parsed, so that the
+/// generated vertices are appended after the ones of the file and do not shift
+/// their indices.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FreeForm {
+    /// `None` if the current basis is not supported.
+    pub kind: Option<CurveType>,
+    pub rational: bool,
+    pub degree: [usize; 2],
+    pub element: Option<Element>,
+    pub parm: [Vec<f32>; 2],
+}
+
+/// Polynomial basis in a single parameter direction.
+struct Spline<'a> {
+    kind: CurveType,
+    degree: usize,
+    knots: &'a [f32],
+}
+
+impl<'a> Spline<'a> {
+    /// Number of control points required by the degree and the global
+    /// parameter values, or 0 if they are inconsistent.
+    fn count(kind: CurveType, degree: usize, knots: &[f32]) -> usize {
+        match kind {
+            _ if degree == 0 => 0,
+            CurveType::Bezier if knots.len() >= 2 => (knots.len() - 1) * degree + 1,
+            CurveType::BSpline if knots.len() > 2 * degree + 1 => knots.len() - degree - 1,
+            _ => 0,
+        }
+    }
+
+    /// Returns `None` if `count` control points do not match the degree and
+    /// the global parameter values.
+    fn new(kind: CurveType, degree: usize, knots: &'a [f32], count: usize) -> Option<Self> {
+        if count > 0 && count == Self::count(kind, degree, knots) {
+            Some(Spline {
+                kind,
+                degree,
+                knots,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn spans(&self) -> usize {
+        match self.kind {
+            CurveType::Bezier => self.knots.len() - 1,
+            CurveType::BSpline => self.knots.len() - 2 * self.degree - 1,
+        }
+    }
+
+    /// Evenly spaced parameter values covering `[t0, t1]`.
+    fn samples(&self, t0: f32, t1: f32) -> Vec<f32> {
+        let n = self.spans() * SEGMENTS_PER_SPAN;
+        (0..=n)
+            .map(|i| t0 + (t1 - t0) * i as f32 / n as f32)
+            .collect()
+    }
+
+    /// Index of the knot span containing `t`, clamped to the valid spans.
+    fn find_span(&self, t: f32, first: usize, last: usize) -> usize {
+        let mut span = first;
+        while span < last && t >= self.knots[span + 1] {
+            span += 1;
+        }
+        span
+    }
+
+    /// Non zero basis functions at `t` as `(control point, value)` pairs.
+    fn eval(&self, t: f32) -> Vec<(usize, f32)> {
+        let p = self.degree;
+        match self.kind {
+            CurveType::Bezier => {
+                let segment = self.find_span(t, 0, self.knots.len() - 2);
+                let (u0, u1) = (self.knots[segment], self.knots[segment + 1]);
+                let s = if u1 > u0 { (t - u0) / (u1 - u0) } else { 0. };
+                (0..=p)
+                    .map(|j| (segment * p + j, bernstein(p, j, s)))
+                    .collect()
+            }
+            CurveType::BSpline => {
+                // Cox-de Boor recursion, only evaluating the p + 1 functions
+                // that are non zero in the knot span
+                let span = self.find_span(t, p, self.knots.len() - p - 2);
+                let mut n = vec![0f32; p + 1];
+                let mut left = vec![0f32; p + 1];
+                let mut right = vec![0f32; p + 1];
+                n[0] = 1.;
+                for j in 1..=p {
+                    left[j] = t - self.knots[span + 1 - j];
+                    right[j] = self.knots[span + j] - t;
+                    let mut saved = 0.;
+                    for r in 0..j {
+                        let denom = right[r + 1] + left[j - r];
+                        let temp = if denom != 0. { n[r] / denom } else { 0. };
+                        n[r] = saved + right[r + 1] * temp;
+                        saved = left[j - r] * temp;
+                    }
+                    n[j] = saved;
+                }
+                n.into_iter()
+                    .enumerate()
+                    .map(|(j, value)| (span - p + j, value))
+                    .collect()
+            }
+        }
+    }
+}
+
+fn bernstein(n: usize, i: usize, t: f32) -> f32 {
+    let mut binomial = 1f32;
+    for k in 0..i {
+        binomial = binomial * (n - k) as f32 / (k + 1) as f32;
+    }
+    binomial * t.powi(i as i32) * (1. - t).powi((n - i) as i32)
+}
+
+/// Blend the attributes of the control points with the given basis values.
+///
+/// The positions of the control points are Euclidean, `weights` only weigh
+/// them for rational elements. Texture coordinates and normals are only
+/// produced if every control point has them.
+fn blend(
+    dat: &MeshData,
+    weights: &[f32],
+    rational: bool,
+    control: &[IndexTuple],
+    basis: &[(usize, f32)],
+) -> (Vector3<f32>, Option<Vector2<f32>>, Option<Vector3<f32>>) {
+    let mut position = Vector3::zero();
+    let mut texture = Some(Vector2::zero());
+    let mut normal = Some(Vector3::zero());
+    let mut total = 0.;
+    for &(i, value) in basis {
+        let IndexTuple(p, t, n) = control[i];
+        let w = if rational {
+            value * weights.get(p).cloned().unwrap_or(1.)
+        } else {
+            value
+        };
+        total += w;
+        position += dat.position[p] * w;
+        texture = match (texture, t) {
+            (Some(acc), Some(t)) => Some(acc + dat.texture[t] * w),
+            _ => None,
+        };
+        normal = match (normal, n) {
+            (Some(acc), Some(n)) => Some(acc + dat.normal[n] * w),
+            _ => None,
+        };
+    }
+    if total != 0. && total != 1. {
+        position /= total;
+        texture = texture.map(|t| t / total);
+        normal = normal.map(|n| n / total);
+    }
+    (position, texture, normal)
+}
+
+fn in_range(dat: &MeshData, &IndexTuple(p, t, n): &IndexTuple) -> bool {
+    p < dat.position.len()
+        && t.is_none_or(|t| t < dat.texture.len())
+        && n.is_none_or(|n| n < dat.normal.len())
+}
+
+/// Push a new vertex and return its index tuple.
+fn push_vertex(
+    dat: &mut MeshData,
+    (position, texture, normal): (Vector3<f32>, Option<Vector2<f32>>, Option<Vector3<f32>>),
+) -> IndexTuple {
+    dat.position.push(position);
+    let t = texture.map(|t| {
+        dat.texture.push(t);
+        dat.texture.len() - 1
+    });
+    let n = normal.map(|n| {
+        dat.normal.push(n);
+        dat.normal.len() - 1
+    });
+    IndexTuple(dat.position.len() - 1, t, n)
+}
+
+/// A complete free-form element, along with the state needed to tessellate
+/// it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tessellation {
+    kind: CurveType,
+    rational: bool,
+    degree: [usize; 2],
+    parm: [Vec<f32>; 2],
+    element: Element,
+}
+
+/// Output of the tessellation of a single free-form element.
+pub enum Geometry {
+    Polyline(SimplePolygon),
+    Triangles(Vec<SimplePolygon>),
+}
+
+impl FreeForm {
+    /// Take the element ended by an `end` command, resetting it and its `parm`
+    /// values.
+    ///
+    /// Returns `None` if there was no element, or if its basis is not
+    /// supported.
+    pub fn take(&mut self) -> Option<Tessellation> {
+        let element = self.element.take();
+        let parm = std::mem::take(&mut self.parm);
+        match (element, self.kind) {
+            (Some(element), Some(kind)) => Some(Tessellation {
+                kind,
+                rational: self.rational,
+                degree: self.degree,
+                parm,
+                element,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Tessellation {
+    /// Tessellate the element into a polyline (curves) or triangles
+    /// (surfaces), appending the generated vertices to `dat`.
+    ///
+    /// Returns `None` if the element is malformed.
+    pub fn run(self, dat: &mut MeshData, weights: &[f32]) -> Option<Geometry> {
+        let Tessellation {
+            kind,
+            rational,
+            degree,
+            parm,
+            element,
+        } = self;
+
+        match element {
+            Element::Curve { ref control, .. } | Element::Surface { ref control, .. }
+                if !control.iter().all(|c| in_range(dat, c)) =>
+            {
+                None
+            }
+            Element::Curve { range, control } => {
+                let spline = Spline::new(kind, degree[0], &parm[0], control.len())?;
+                let line = spline
+                    .samples(range[0], range[1])
+                    .into_iter()
+                    .map(|t| {
+                        let vertex = blend(dat, weights, rational, &control, &spline.eval(t));
+                        push_vertex(dat, vertex)
+                    })
+                    .collect();
+                Some(Geometry::Polyline(line))
+            }
+            Element::Surface { range, control } => {
+                let nu = Spline::count(kind, degree[0], &parm[0]);
+                let nv = Spline::count(kind, degree[1], &parm[1]);
+                if nu * nv != control.len() {
+                    return None;
+                }
+                let su = Spline::new(kind, degree[0], &parm[0], nu)?;
+                let sv = Spline::new(kind, degree[1], &parm[1], nv)?;
+                let us = su.samples(range[0], range[1]);
+                let vs = sv.samples(range[2], range[3]);
+
+                let mut grid = Vec::with_capacity(us.len() * vs.len());
+                for &v in &vs {
+                    let bv = sv.eval(v);
+                    for &u in &us {
+                        let bu = su.eval(u);
+                        let basis: Vec<(usize, f32)> = bv
+                            .iter()
+                            .flat_map(|&(j, wv)| bu.iter().map(move |&(i, wu)| (j * nu + i, wu * wv)))
+                            .collect();
+                        let vertex = blend(dat, weights, rational, &control, &basis);
+                        grid.push(push_vertex(dat, vertex));
+                    }
+                }
+
+                let row = us.len();
+                let mut triangles = Vec::with_capacity(2 * (row - 1) * (vs.len() - 1));
+                for j in 0..vs.len() - 1 {
+                    for i in 0..row - 1 {
+                        let a = grid[j * row + i];
+                        let b = grid[j * row + i + 1];
+                        let c = grid[(j + 1) * row + i + 1];
+                        let d = grid[(j + 1) * row + i];
+                        triangles.push(vec![a, b, c]);
+                        triangles.push(vec![a, c, d]);
+                    }
+                }
+                Some(Geometry::Triangles(triangles))
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+// levels of detail of a mesh
+
+use std::collections::HashSet;
+
+use super::{Aabb, MeshData, Sphere, VertexBuffer};
+
+/// Ratio of the triangles kept by every level of a `LodChain`.
+const LOD_RATIO: f32 = 0.5;
+
+/// A single level of detail, ready to be rasterized.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lod {
+    pub mesh: MeshData,
+    pub buffer: VertexBuffer,
+    /// Estimated distance between this level and the original mesh, in model
+    /// units. Always 0 for the original mesh.
+    pub error: f32,
+    /// Box around every group, in the order of `buffer.ranges`.
+    pub bounds: Vec<Aabb>,
+    /// Position indices of the edges of the polygons of every group, in the
+    /// order of `buffer.ranges`. An edge shared by several polygons is only
+    /// listed once, with the first group using it.
+    pub edges: Vec<Vec<[usize; 2]>>,
+}
+
+impl Lod {
+    pub fn new(mesh: MeshData, error: f32) -> Self {
+        let buffer = VertexBuffer::new(&mesh);
+        let bounds = buffer
+            .ranges
+            .iter()
+            .map(|range| mesh.group_aabb(range.object, range.group))
+            .collect();
+        let mut seen = HashSet::new();
+        let edges = buffer
+            .ranges
+            .iter()
+            .map(|range| {
+                let mut edges = Vec::new();
+                for poly in &mesh.objects[range.object].groups[range.group].polys {
+                    for (i, a) in poly.iter().enumerate() {
+                        let (a, b) = (a.0, poly[(i + 1) % poly.len()].0);
+                        if a != b && seen.insert((a.min(b), a.max(b))) {
+                            edges.push([a, b]);
+                        }
+                    }
+                }
+                edges
+            })
+            .collect();
+        Lod {
+            buffer,
+            mesh,
+            error,
+            bounds,
+            edges,
+        }
+    }
+}
+
+/// A mesh along with simplified versions of it, from the finest to the
+/// coarsest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LodChain {
+    /// The original mesh first, then levels of increasing error.
+    pub levels: Vec<Lod>,
+    /// Bounding sphere of the original mesh.
+    pub sphere: Sphere,
+}
+
+impl LodChain {
+    /// Chain of the mesh followed by up to `levels` simplified versions, each
+    /// one with about half the triangles of the previous one.
+    pub fn new(mesh: MeshData, levels: usize) -> Self {
+        let simplified = mesh.simplify_levels(levels, LOD_RATIO);
+        let sphere = mesh.bounding_sphere();
+
+        let mut chain = LodChain {
+            levels: vec![Lod::new(mesh, 0.)],
+            sphere,
+        };
+        chain.levels.extend(
+            simplified
+                .into_iter()
+                .map(|(mesh, error)| Lod::new(mesh, error)),
+        );
+        chain
+    }
+
+    /// The original mesh.
+    pub fn base(&self) -> &Lod {
+        &self.levels[0]
+    }
+}
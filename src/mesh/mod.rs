@@ -1,18 +1,41 @@
 //! Parses different file format into Mesh object
 //!
-//! Right now it only supports wavefront i.e (.obj) file formats
+//! Right now it only supports wavefront i.e (.obj) file formats, either as
+//! plain files, gzip compressed (.obj.gz) or bundled inside a zip archive.
+mod bounds;
+mod bvh;
+#[cfg(test)]
+mod fixtures;
+mod freeform;
+mod lod;
+mod simplify;
+mod subdivide;
+mod validate;
+mod vertex_buffer;
 mod wavefront;
 
 use std::{
+    collections::HashMap,
     ffi::OsStr,
     fmt,
     fs::File,
-    io::{self, BufReader, Read},
-    path::{Path, PathBuf},
+    io::{self, BufRead, BufReader, Cursor, Read},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
 };
 
-use cgmath::{Vector2, Vector3};
-use wavefront::*;
+use cgmath::{Vector2, Vector3, Vector4};
+use flate2::read::GzDecoder;
+pub use bounds::*;
+pub use bvh::*;
+pub use lod::*;
+use memmap::Mmap;
+pub use simplify::*;
+pub use subdivide::*;
+pub use validate::*;
+pub use vertex_buffer::*;
+pub use wavefront::*;
+use zip::ZipArchive;
 
 /// The data model associated with each `Obj` file.
 #[derive(Clone, Debug, PartialEq)]
@@ -23,6 +46,12 @@ pub struct MeshData {
     pub texture: Vec<Vector2<f32>>,
     /// A set of normals.
     pub normal: Vec<Vector3<f32>>,
+    /// RGBA vertex colors from the `v x y z r g b [a]` extension.
+    ///
+    /// Either empty or of the same length as `position`.
+    pub color: Vec<Vector4<f32>>,
+    /// Parameter space vertices (`vp`) used by free-form trimming curves.
+    pub param: Vec<Vector3<f32>>,
     /// A collection of associated objects indicated by `o`, as well as the
     /// default object at the top level.
     pub objects: Vec<Object>,
@@ -36,6 +65,8 @@ impl Default for MeshData {
             position: Vec::new(),
             texture: Vec::new(),
             normal: Vec::new(),
+            color: Vec::new(),
+            param: Vec::new(),
             objects: Vec::new(),
             material_libs: Vec::new(),
         }
@@ -43,75 +74,36 @@ impl Default for MeshData {
 }
 
 impl MeshData {
+    /// Move and scale the positions in place to fit the `[-1, 1]` cube.
+    ///
+    /// The renderer leaves the positions untouched and applies the
+    /// `Normalization` selected in its `Config` instead.
     pub fn normalize_vertices(&mut self) {
-        let vertices = &mut self.position;
-        let values = Self::min_max_vertices(&vertices);
-
-        // check if already normalized
-        let mut is_valid = true;
-        for i in values.iter() {
-            if *i <= 1. && *i >= -1. {
-                is_valid = is_valid & true;
-            } else {
-                is_valid = false;
-                break;
-            }
-        }
-        if is_valid {
-            return;
-        }
-
-        let translation = [
-            -(values[0] + (values[1] - values[0]) / 2.),
-            -(values[2] + (values[3] - values[2]) / 2.),
-            -(values[4] + (values[5] - values[4]) / 2.),
-        ];
-
-        let scale = [
-            1. / ((values[1] - values[0]) / 2.),
-            1. / ((values[3] - values[2]) / 2.),
-            1. / ((values[5] - values[4]) / 2.),
-        ];
-        let scale_by = scale
-            .iter()
-            .min_by(|i, j| i.partial_cmp(j).unwrap())
-            .unwrap();
-
-        for vertex in vertices.iter_mut() {
-            vertex[0] = (vertex[0] + translation[0]) * scale_by;
-            vertex[1] = (vertex[1] + translation[1]) * scale_by;
-            vertex[2] = (vertex[2] + translation[2]) * scale_by;
+        let transform = self.normalization(Normalization::FitCube);
+        for p in &mut self.position {
+            *p = (transform * p.extend(1.)).truncate();
         }
     }
 
-    fn min_max_vertices(vertices: &Vec<Vector3<f32>>) -> [f32; 6] {
-        let mut x_min = 0f32;
-        let mut x_max = 0f32;
-        let mut y_min = 0f32;
-        let mut y_max = 0f32;
-        let mut z_min = 0f32;
-        let mut z_max = 0f32;
-        for vertex in vertices {
-            let x = vertex.x;
-            let y = vertex.y;
-            let z = vertex.z;
-            if x > x_max {
-                x_max = x;
-            } else if x < x_min {
-                x_min = x;
-            }
-            if y > y_max {
-                y_max = y;
-            } else if y < y_min {
-                y_min = y;
-            }
-            if z > z_max {
-                z_max = z;
-            } else if z < z_min {
-                z_min = z;
-            }
-        }
-        return [x_min, x_max, y_min, y_max, z_min, z_max];
+    /// Index of the material of every group, by object. Materials are
+    /// numbered by name in the order they are first used, and groups without
+    /// material have `None`.
+    pub fn group_materials(&self) -> Vec<Vec<Option<usize>>> {
+        let mut names: HashMap<&str, usize> = HashMap::new();
+        self.objects
+            .iter()
+            .map(|object| {
+                object
+                    .groups
+                    .iter()
+                    .map(|group| {
+                        let name = group.material.as_ref()?.name();
+                        let next = names.len();
+                        Some(*names.entry(name).or_insert(next))
+                    })
+                    .collect()
+            })
+            .collect()
     }
 }
 
@@ -147,8 +139,14 @@ pub struct Group {
     /// After material libs are loaded, this will point to the loaded `Material`
     /// struct.
     pub material: Option<ObjMaterial>,
-    /// A list of polygons appearing as `f ...` in the `.obj` file.
+    /// A list of polygons appearing as `f ...` in the `.obj` file, as well as
+    /// the triangles of tessellated free-form surfaces.
     pub polys: Vec<SimplePolygon>,
+    /// A list of polylines appearing as `l ...` in the `.obj` file, as well as
+    /// tessellated free-form curves.
+    pub lines: Vec<SimplePolygon>,
+    /// Position indices of the points appearing as `p ...` in the `.obj` file.
+    pub points: Vec<usize>,
 }
 
 impl Group {
@@ -158,8 +156,28 @@ impl Group {
             index: 0,
             material: None,
             polys: Vec::new(),
+            lines: Vec::new(),
+            points: Vec::new(),
+        }
+    }
+
+    /// Opacity of the loaded material of the group, 1 without one.
+    pub fn opacity(&self) -> f32 {
+        match &self.material {
+            Some(ObjMaterial::Mtl(material)) => material.opacity(),
+            _ => 1.,
         }
     }
+
+    /// Specular color and roughness of the loaded material of the group, from
+    /// `Ks` and `Ns`, those of a half rough dielectric where they are not set.
+    pub fn specular(&self) -> ([f32; 3], f32) {
+        let (ks, roughness) = match &self.material {
+            Some(ObjMaterial::Mtl(material)) => (material.ks, material.roughness()),
+            _ => (None, None),
+        };
+        (ks.unwrap_or([0.04; 3]), roughness.unwrap_or(0.5))
+    }
 }
 
 /// A tuple of position, texture and normal indices assigned to each polygon
@@ -188,8 +206,21 @@ impl std::fmt::Display for IndexTuple {
     }
 }
 
+/// Options controlling how a mesh file is loaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LoadOptions {
+    /// Skip malformed statements and clamp out-of-range indices instead of
+    /// failing, reporting each problem as a `Diagnostic`.
+    pub lenient: bool,
+    /// Subdivide every object of the mesh once loaded.
+    pub subdivision: Option<Subdivision>,
+}
+
 trait MeshParser {
-    fn parse_mesh_data<R: Read>(input: R) -> Result<MeshData, ObjError>;
+    fn parse_mesh_data_with<R: Read>(
+        input: R,
+        options: &LoadOptions,
+    ) -> Result<(MeshData, Vec<Diagnostic>), ObjError>;
 }
 
 /// A struct used to store `Mesh` data as well as its source directory used to
@@ -201,24 +232,159 @@ pub struct MeshLoader {
     /// The path of the parent directory from which this file was read.
     ///
     /// It is not always set since the file may have been read from a `String`.
+    /// If the file was read from a zip archive, this is the directory of the
+    /// mesh entry inside that archive.
     pub path: PathBuf,
+    /// The zip archive the file was read from, if any.
+    ///
+    /// When set, referenced .mtl files and textures are looked up as sibling
+    /// entries inside the same archive instead of on disk.
+    pub archive: Option<PathBuf>,
+    /// Problems found in the file when loaded in lenient mode.
+    pub diagnostics: Vec<Diagnostic>,
 }
 
 impl MeshLoader {
     /// Load an file from the given path with the default load
     /// configuration.
+    ///
+    /// Files ending in `.obj.gz` are decompressed transparently and `.zip`
+    /// archives are searched for the first `.obj` (or `.obj.gz`) entry, see
+    /// [`load_from_archive`].
+    ///
+    /// [`load_from_archive`]: #method.load_from_archive
     pub fn load(path: impl AsRef<Path>) -> Result<MeshLoader, ObjError> {
-        let f = File::open(path.as_ref())?;
+        Self::load_with(path, &LoadOptions::default())
+    }
 
-        let data = match path.as_ref().extension().and_then(OsStr::to_str) {
-            Some("obj") => ObjData::parse_mesh_data(&f)?,
-            _ => return Err(ObjError::Unsupported),
+    /// Load an file from the given path with the given load configuration.
+    ///
+    /// See also [`load`].
+    ///
+    /// [`load`]: #method.load
+    pub fn load_with(
+        path: impl AsRef<Path>,
+        options: &LoadOptions,
+    ) -> Result<MeshLoader, ObjError> {
+        let path = path.as_ref();
+        if is_extension(path, "zip") {
+            return Self::load_from_archive(path, None, options);
+        }
+
+        let f = File::open(path)?;
+        let (mut data, diagnostics) = if is_extension(path, "obj") {
+            Self::parse_file(&f, options)?
+        } else {
+            Self::parse(path, f, options)?
         };
+        if let Some(subdivision) = options.subdivision {
+            data.subdivide(subdivision);
+        }
 
         // unwrap is safe since we've read this file before.
-        let path = path.as_ref().parent().unwrap().to_owned();
+        let path = path.parent().unwrap().to_owned();
+
+        Ok(MeshLoader {
+            data,
+            path,
+            archive: None,
+            diagnostics,
+        })
+    }
 
-        Ok(MeshLoader { data, path })
+    /// Load a mesh stored as an entry of a zip archive.
+    ///
+    /// If `entry` is `None` the first `.obj` or `.obj.gz` entry of the archive
+    /// is used.
+    pub fn load_from_archive(
+        archive: impl AsRef<Path>,
+        entry: Option<&str>,
+        options: &LoadOptions,
+    ) -> Result<MeshLoader, ObjError> {
+        let archive = archive.as_ref();
+        let mut zip = ZipArchive::new(File::open(archive)?).map_err(io::Error::from)?;
+
+        let entry = match entry {
+            Some(entry) => entry.to_string(),
+            None => zip
+                .file_names()
+                .find(|name| Self::is_mesh_file(Path::new(name)))
+                .ok_or(ObjError::MissingArchiveEntry)?
+                .to_string(),
+        };
+
+        let (mut data, diagnostics) = {
+            let f = zip.by_name(&entry).map_err(io::Error::from)?;
+            Self::parse(Path::new(&entry), f, options)?
+        };
+        if let Some(subdivision) = options.subdivision {
+            data.subdivide(subdivision);
+        }
+        let path = Path::new(&entry)
+            .parent()
+            .map(Path::to_owned)
+            .unwrap_or_default();
+
+        Ok(MeshLoader {
+            data,
+            path,
+            archive: Some(archive.to_owned()),
+            diagnostics,
+        })
+    }
+
+    fn is_mesh_file(path: &Path) -> bool {
+        match path.extension().and_then(OsStr::to_str) {
+            Some("obj") => true,
+            Some("gz") => path.file_stem().is_some_and(|s| is_extension(s, "obj")),
+            _ => false,
+        }
+    }
+
+    /// Parse an uncompressed .obj file by memory mapping it.
+    fn parse_file(
+        f: &File,
+        options: &LoadOptions,
+    ) -> Result<(MeshData, Vec<Diagnostic>), ObjError> {
+        let parser = ObjData::with_options(options);
+        // mapping an empty file fails on some platforms
+        if f.metadata()?.len() == 0 {
+            return parser.load_bytes(&[]);
+        }
+        // the map is only valid as long as the file is not modified while
+        // being parsed, which we can not guarantee but is fine for assets
+        let map = unsafe { Mmap::map(f)? };
+
+        #[cfg(feature = "parallel")]
+        return parser.load_bytes_parallel(&map);
+        #[cfg(not(feature = "parallel"))]
+        return parser.load_bytes(&map);
+    }
+
+    /// Parse the mesh read from `input`, choosing the format from the
+    /// extension of `path`.
+    fn parse(
+        path: &Path,
+        input: impl Read,
+        options: &LoadOptions,
+    ) -> Result<(MeshData, Vec<Diagnostic>), ObjError> {
+        if !Self::is_mesh_file(path) {
+            return Err(ObjError::Unsupported);
+        }
+        if is_extension(path, "gz") {
+            ObjData::parse_mesh_data_with(GzDecoder::new(input), options)
+        } else {
+            ObjData::parse_mesh_data_with(input, options)
+        }
+    }
+
+    /// Open a file referenced by the mesh, like a .mtl library or a texture
+    /// map, relative to the directory of the mesh.
+    ///
+    /// If the mesh was loaded from a zip archive, the file is read from the
+    /// sibling entry inside the archive.
+    pub fn open_resource(&self, name: &str) -> io::Result<Box<dyn BufRead>> {
+        open_resource(self.archive.as_deref(), &self.path, name)
     }
 
     /// Loads the .mtl files referenced in the .obj file.
@@ -226,7 +392,8 @@ impl MeshLoader {
     /// If it encounters an error for an .mtl, it appends its error to the
     /// returning Vec, and tries the rest.
     pub fn load_mtls(&mut self) -> Result<(), MtlLibsLoadError> {
-        self.load_mtls_fn(|obj_dir, mtllib| File::open(&obj_dir.join(mtllib)).map(BufReader::new))
+        let archive = self.archive.clone();
+        self.load_mtls_fn(|obj_dir, mtllib| open_resource(archive.as_deref(), obj_dir, mtllib))
     }
 
     /// Loads the .mtl files referenced in the .obj file with user provided
@@ -235,7 +402,8 @@ impl MeshLoader {
     /// See also [`load_mtls`].
     ///
     /// The provided function must take two arguments:
-    ///  - `&Path` - The parent directory of the .obj file
+    ///  - `&Path` - The parent directory of the .obj file (or of the .obj
+    ///    entry, when loaded from an archive)
     ///  - `&str`  - The name of the mtllib as listed in the file.
     ///
     /// This function allows loading .mtl files in directories different from
@@ -252,45 +420,143 @@ impl MeshLoader {
         R: io::BufRead,
         F: FnMut(&Path, &str) -> io::Result<R>,
     {
-        // let mut errs = Vec::new();
-        // let mut materials = HashMap::new();
-
-        // for mtl_lib in &mut self.data.material_libs {
-        //     match mtl_lib.reload_with(&self.path, &mut resolve) {
-        //         Ok(mtl_lib) => {
-        //             for m in &mtl_lib.materials {
-        //                 // We don't want to overwrite existing entries because of how
-        // the materials                 // are looked up. From the spec:
-        //                 // "If multiple filenames are specified, the first file
-        //                 //  listed is searched first for the material definition, the
-        // second                 //  file is searched next, and so on."
-        //                 materials
-        //                     .entry(m.name.clone())
-        //                     .or_insert_with(|| Arc::clone(m));
-        //             }
-        //         }
-        //         Err(err) => {
-        //             errs.push((mtl_lib.filename.clone(), err));
-        //         }
-        //     }
-        // }
-
-        // // Assign loaded materials to the corresponding objects.
-        // for object in &mut self.data.objects {
-        //     for group in &mut object.groups {
-        //         if let Some(ref mut mat) = group.material {
-        //             if let Some(newmat) = materials.get(mat.name()) {
-        //                 *mat = ObjMaterial::Mtl(Arc::clone(newmat));
-        //             }
-        //         }
-        //     }
-        // }
-
-        // if errs.is_empty() {
-        //     Ok(())
-        // } else {
-        //     Err(errs.into())
-        // }
-        Ok(())
+        let mut errs = Vec::new();
+        let mut materials = HashMap::new();
+
+        for mtl_lib in &mut self.data.material_libs {
+            match mtl_lib.reload_with(&self.path, &mut resolve) {
+                Ok(mtl_lib) => {
+                    for m in &mtl_lib.materials {
+                        // We don't want to overwrite existing entries because of how the
+                        // materials are looked up. From the spec:
+                        // "If multiple filenames are specified, the first file
+                        //  listed is searched first for the material definition, the second
+                        //  file is searched next, and so on."
+                        materials
+                            .entry(m.name.clone())
+                            .or_insert_with(|| Arc::clone(m));
+                    }
+                }
+                Err(err) => {
+                    errs.push((mtl_lib.filename.clone(), err));
+                }
+            }
+        }
+
+        // Assign loaded materials to the corresponding objects.
+        for object in &mut self.data.objects {
+            for group in &mut object.groups {
+                if let Some(ref mut mat) = group.material {
+                    if let Some(newmat) = materials.get(mat.name()) {
+                        *mat = ObjMaterial::Mtl(Arc::clone(newmat));
+                    }
+                }
+            }
+        }
+
+        if errs.is_empty() {
+            Ok(())
+        } else {
+            Err(errs.into())
+        }
+    }
+}
+
+fn is_extension(path: impl AsRef<Path>, extension: &str) -> bool {
+    path.as_ref().extension().and_then(OsStr::to_str) == Some(extension)
+}
+
+/// Open `name` relative to `dir`, either on disk or inside the zip `archive`.
+fn open_resource(archive: Option<&Path>, dir: &Path, name: &str) -> io::Result<Box<dyn BufRead>> {
+    let archive = match archive {
+        Some(archive) => archive,
+        None => return Ok(Box::new(BufReader::new(File::open(dir.join(name))?))),
+    };
+
+    // zip entries always use `/` as separator, and may not contain `..`
+    let path = dir.join(name);
+    let mut entry: Vec<&str> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(c) => {
+                let c = c.to_str().ok_or_else(|| {
+                    let message = format!("archive entry name is not valid UTF-8: {:?}", path);
+                    io::Error::new(io::ErrorKind::InvalidInput, message)
+                })?;
+                entry.push(c);
+            }
+            Component::ParentDir => {
+                entry.pop();
+            }
+            _ => (),
+        }
+    }
+
+    let mut zip = ZipArchive::new(File::open(archive)?)?;
+    let mut f = zip.by_name(&entry.join("/"))?;
+    let mut buf = Vec::with_capacity(f.size() as usize);
+    f.read_to_end(&mut buf)?;
+    Ok(Box::new(Cursor::new(buf)))
+}
+
+/// Write `contents` to a file named `name` in the temporary directory.
+#[cfg(test)]
+fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("toy_renderer_{}", name));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn test_load_compressed() {
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+    use zip::{write::FileOptions, ZipWriter};
+
+    let obj = "mtllib triangle.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n";
+    let mtl = "newmtl red\nKd 1 0 0\n";
+
+    let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+    gz.write_all(obj.as_bytes()).unwrap();
+    let path = temp_file("triangle.obj.gz", &gz.finish().unwrap());
+    let loader = MeshLoader::load(&path).unwrap();
+    assert_eq!(loader.data.position.len(), 3);
+    assert_eq!(loader.data.objects[0].groups[0].polys.len(), 1);
+    assert_eq!(loader.archive, None);
+
+    // the .mtl is a sibling entry of the mesh inside the archive
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, contents) in &[("models/triangle.obj", obj), ("models/triangle.mtl", mtl)] {
+        zip.start_file(*name, FileOptions::default()).unwrap();
+        zip.write_all(contents.as_bytes()).unwrap();
+    }
+    let path = temp_file("triangle.zip", &zip.finish().unwrap().into_inner());
+    let mut loader = MeshLoader::load(&path).unwrap();
+    assert_eq!(loader.path, Path::new("models"));
+    assert_eq!(loader.archive.as_deref(), Some(path.as_path()));
+    loader.load_mtls().unwrap();
+    match &loader.data.objects[0].groups[0].material {
+        Some(ObjMaterial::Mtl(material)) => assert_eq!(material.kd, Some([1., 0., 0.])),
+        material => panic!("{:?}", material),
+    }
+    assert!(loader.open_resource("../models/triangle.mtl").is_ok());
+    assert!(loader.open_resource("missing.mtl").is_err());
+
+    let loader =
+        MeshLoader::load_from_archive(&path, Some("models/triangle.obj"), &LoadOptions::default());
+    assert!(loader.is_ok());
+    let loader =
+        MeshLoader::load_from_archive(&path, Some("models/triangle.mtl"), &LoadOptions::default());
+    assert!(matches!(loader, Err(ObjError::Unsupported)));
+
+    // names of entries must be valid UTF-8
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let dir = Path::new(OsStr::from_bytes(b"\xff"));
+        let err = open_resource(Some(&path), dir, "triangle.mtl")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
     }
 }
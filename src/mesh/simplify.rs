@@ -0,0 +1,545 @@
+// quadric error metric simplification of `MeshData` (Garland & Heckbert)
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use cgmath::prelude::*;
+use cgmath::Vector3;
+
+use super::{IndexTuple, MeshData};
+
+/// Weight of the planes keeping borders, seams and group boundaries in place,
+/// relative to the planes of the faces.
+const BOUNDARY_WEIGHT: f64 = 1000.;
+
+/// Options of `MeshData::simplify`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimplifyOptions {
+    /// Stop once the mesh has at most this many triangles.
+    pub target_triangles: usize,
+    /// Stop before a collapse would exceed this error, which is roughly the
+    /// squared distance of the moved vertex to the original surface.
+    pub max_error: f32,
+}
+
+impl Default for SimplifyOptions {
+    fn default() -> Self {
+        SimplifyOptions {
+            target_triangles: 0,
+            max_error: f32::INFINITY,
+        }
+    }
+}
+
+/// Symmetric 4x4 matrix summing the squared distances to a set of planes.
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    /// Quadric of the plane `n . p + d = 0`, with `n` normalized.
+    fn plane(n: Vector3<f64>, d: f64, weight: f64) -> Self {
+        let (a, b, c) = (n.x, n.y, n.z);
+        let q = [
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ];
+        Quadric(q.map(|v| v * weight))
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a += b;
+        }
+    }
+
+    fn error(&self, p: Vector3<f32>) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (p.x as f64, p.y as f64, p.z as f64);
+        q[0] * x * x
+            + 2. * q[1] * x * y
+            + 2. * q[2] * x * z
+            + 2. * q[3] * x
+            + q[4] * y * y
+            + 2. * q[5] * y * z
+            + 2. * q[6] * y
+            + q[7] * z * z
+            + 2. * q[8] * z
+            + q[9]
+    }
+}
+
+/// A candidate collapse of the position `from` into `to`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Collapse {
+    error: f64,
+    from: usize,
+    to: usize,
+    version: usize,
+}
+
+impl Eq for Collapse {}
+
+impl Ord for Collapse {
+    // reversed, so that the binary heap pops the lowest error first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .error
+            .partial_cmp(&self.error)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| other.from.cmp(&self.from))
+    }
+}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Triangle soup being simplified.
+struct Simplifier<'a> {
+    mesh: &'a MeshData,
+    triangles: Vec<[IndexTuple; 3]>,
+    /// `(object, group)` of every triangle.
+    owner: Vec<(usize, usize)>,
+    alive: Vec<bool>,
+    /// Triangles around every position, including removed ones.
+    around: Vec<Vec<usize>>,
+    quadrics: Vec<Quadric>,
+    version: Vec<usize>,
+}
+
+/// How a position edge is used by the triangles around it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EdgeKind {
+    Interior,
+    /// Border of the surface, or edge between different groups, texture
+    /// coordinates or normals.
+    Seam,
+    NonManifold,
+}
+
+fn normal(p: [Vector3<f32>; 3]) -> Vector3<f32> {
+    (p[1] - p[0]).cross(p[2] - p[0])
+}
+
+fn to_f64(v: Vector3<f32>) -> Vector3<f64> {
+    Vector3::new(v.x as f64, v.y as f64, v.z as f64)
+}
+
+impl<'a> Simplifier<'a> {
+    fn new(mesh: &'a MeshData) -> Self {
+        let mut simplifier = Simplifier {
+            mesh,
+            triangles: Vec::new(),
+            owner: Vec::new(),
+            alive: Vec::new(),
+            around: vec![Vec::new(); mesh.position.len()],
+            quadrics: vec![Quadric::default(); mesh.position.len()],
+            version: vec![0; mesh.position.len()],
+        };
+        for (face, poly) in mesh.faces() {
+            if !mesh.is_valid_face(poly) || mesh.is_degenerate_face(poly) {
+                continue;
+            }
+            for i in 1..poly.len() - 1 {
+                let triangle = [poly[0], poly[i], poly[i + 1]];
+                for v in &triangle {
+                    simplifier.around[v.0].push(simplifier.triangles.len());
+                }
+                simplifier.triangles.push(triangle);
+                simplifier.owner.push((face.object, face.group));
+                simplifier.alive.push(true);
+            }
+        }
+
+        for t in 0..simplifier.triangles.len() {
+            let p = simplifier.positions(t);
+            let n = normal(p);
+            if n.magnitude2() == 0. {
+                continue;
+            }
+            let n = to_f64(n.normalize());
+            let plane = Quadric::plane(n, -n.dot(to_f64(p[0])), 1.);
+            for v in &simplifier.triangles[t] {
+                simplifier.quadrics[v.0].add(&plane);
+            }
+
+            // planes perpendicular to the face keep its seam edges in place
+            for i in 0..3 {
+                let (a, b) = (
+                    simplifier.triangles[t][i].0,
+                    simplifier.triangles[t][(i + 1) % 3].0,
+                );
+                if simplifier.edge_kind(a, b) == EdgeKind::Interior {
+                    continue;
+                }
+                let edge = to_f64(p[(i + 1) % 3] - p[i]);
+                let side = edge.cross(n);
+                if side.magnitude2() == 0. {
+                    continue;
+                }
+                let side = side.normalize();
+                let weight = BOUNDARY_WEIGHT * edge.magnitude2();
+                let plane = Quadric::plane(side, -side.dot(to_f64(p[i])), weight);
+                simplifier.quadrics[a].add(&plane);
+                simplifier.quadrics[b].add(&plane);
+            }
+        }
+        simplifier
+    }
+
+    fn positions(&self, t: usize) -> [Vector3<f32>; 3] {
+        self.triangles[t].map(|v| self.mesh.position[v.0])
+    }
+
+    fn live_around(&self, p: usize) -> impl Iterator<Item = usize> + '_ {
+        self.around[p]
+            .iter()
+            .cloned()
+            .filter(move |&t| self.alive[t])
+    }
+
+    /// Corner of the position `p` in the triangle `t`.
+    fn corner(&self, t: usize, p: usize) -> Option<IndexTuple> {
+        self.triangles[t].iter().cloned().find(|v| v.0 == p)
+    }
+
+    /// Sorted positions sharing a triangle with `p`.
+    fn neighbours(&self, p: usize) -> Vec<usize> {
+        let mut neighbours: Vec<usize> = self
+            .live_around(p)
+            .flat_map(|t| self.triangles[t].iter().map(|v| v.0))
+            .filter(|&q| q != p)
+            .collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        neighbours
+    }
+
+    fn shared(&self, a: usize, b: usize) -> Vec<usize> {
+        self.live_around(a)
+            .filter(|&t| self.corner(t, b).is_some())
+            .collect()
+    }
+
+    fn edge_kind(&self, a: usize, b: usize) -> EdgeKind {
+        match *self.shared(a, b).as_slice() {
+            [t0, t1] => {
+                let same = self.owner[t0] == self.owner[t1]
+                    && self.corner(t0, a) == self.corner(t1, a)
+                    && self.corner(t0, b) == self.corner(t1, b);
+                if same {
+                    EdgeKind::Interior
+                } else {
+                    EdgeKind::Seam
+                }
+            }
+            [_] => EdgeKind::Seam,
+            _ => EdgeKind::NonManifold,
+        }
+    }
+
+    /// Error of collapsing `from` into its neighbour `to`, or `None` if the
+    /// collapse would damage the topology or flip a triangle.
+    fn collapse_error(&self, from: usize, to: usize, neighbours: &[usize]) -> Option<f64> {
+        // link condition: the only common neighbours are the opposite corners
+        // of the triangles sharing the edge
+        let shared = self.shared(from, to);
+        if self.live_around(from).all(|t| shared.contains(&t)) {
+            // would remove whole parts of the mesh
+            return None;
+        }
+        let common = self
+            .neighbours(to)
+            .iter()
+            .filter(|q| neighbours.binary_search(q).is_ok())
+            .count();
+        if common != shared.len() {
+            return None;
+        }
+
+        let target = self.mesh.position[to];
+        for t in self.live_around(from).filter(|t| !shared.contains(t)) {
+            let before = self.positions(t);
+            let after = self.triangles[t].map(|v| {
+                if v.0 == from {
+                    target
+                } else {
+                    self.mesh.position[v.0]
+                }
+            });
+            let (n0, n1) = (normal(before), normal(after));
+            if n1.magnitude2() == 0. || n0.dot(n1) <= 0. {
+                return None;
+            }
+        }
+
+        let mut quadric = self.quadrics[from];
+        quadric.add(&self.quadrics[to]);
+        Some(quadric.error(target).max(0.))
+    }
+
+    /// Cheapest valid collapse of the position `from`.
+    fn best_collapse(&self, from: usize) -> Option<Collapse> {
+        let neighbours = self.neighbours(from);
+        let mut seams = Vec::new();
+        for &q in &neighbours {
+            match self.edge_kind(from, q) {
+                EdgeKind::Interior => {}
+                EdgeKind::Seam => seams.push(q),
+                EdgeKind::NonManifold => return None,
+            }
+        }
+
+        // positions on seams may only slide along their seam, and corners
+        // where several seams meet are kept
+        let candidates = match seams.len() {
+            0 => neighbours.clone(),
+            2 => seams,
+            _ => return None,
+        };
+        candidates
+            .into_iter()
+            .filter_map(|to| {
+                self.collapse_error(from, to, &neighbours)
+                    .map(|error| Collapse {
+                        error,
+                        from,
+                        to,
+                        version: self.version[from],
+                    })
+            })
+            .min_by(|a, b| a.error.partial_cmp(&b.error).unwrap_or(Ordering::Equal))
+    }
+
+    /// Collapse `from` into `to`, returning the number of removed triangles.
+    fn collapse(&mut self, from: usize, to: usize) -> usize {
+        let shared = self.shared(from, to);
+        // corners of `from` take the attributes of `to` on the same side of
+        // the edge, so texture coordinates & normals stay continuous
+        let mut remap = Vec::new();
+        for &t in &shared {
+            if let (Some(a), Some(b)) = (self.corner(t, from), self.corner(t, to)) {
+                remap.push((a, b));
+            }
+            self.alive[t] = false;
+        }
+
+        let around: Vec<usize> = self.live_around(from).collect();
+        for &t in &around {
+            for v in self.triangles[t].iter_mut() {
+                if v.0 == from {
+                    *v = remap
+                        .iter()
+                        .find(|(a, _)| a == v)
+                        .map_or(IndexTuple(to, v.1, v.2), |&(_, b)| b);
+                }
+            }
+        }
+        self.around[to].extend(around);
+        self.around[from].clear();
+        let alive = &self.alive;
+        self.around[to].retain(|&t| alive[t]);
+        let quadric = self.quadrics[from];
+        self.quadrics[to].add(&quadric);
+        shared.len()
+    }
+
+    /// Collapse edges until the options are met, returning the largest error
+    /// of the collapses.
+    fn run(&mut self, options: &SimplifyOptions) -> f64 {
+        let mut max_error = 0f64;
+        let mut count = self.alive.len();
+        let mut heap: BinaryHeap<Collapse> = (0..self.around.len())
+            .filter_map(|p| self.best_collapse(p))
+            .collect();
+
+        while count > options.target_triangles {
+            let candidate = match heap.pop() {
+                Some(candidate) => candidate,
+                None => break,
+            };
+            if candidate.version != self.version[candidate.from] {
+                continue;
+            }
+            // the neighbourhood may have changed since the candidate was found
+            let collapse = match self.best_collapse(candidate.from) {
+                Some(collapse) if collapse == candidate => collapse,
+                Some(collapse) => {
+                    heap.push(collapse);
+                    continue;
+                }
+                None => continue,
+            };
+            if collapse.error > options.max_error as f64 {
+                break;
+            }
+
+            count -= self.collapse(collapse.from, collapse.to);
+            max_error = max_error.max(collapse.error);
+            self.version[collapse.from] += 1;
+            let mut changed = self.neighbours(collapse.to);
+            changed.push(collapse.to);
+            for p in changed {
+                self.version[p] += 1;
+                if let Some(collapse) = self.best_collapse(p) {
+                    heap.push(collapse);
+                }
+            }
+        }
+        max_error
+    }
+}
+
+impl MeshData {
+    /// Number of triangles of the polygons, once split into triangle fans.
+    pub fn triangle_count(&self) -> usize {
+        self.faces()
+            .map(|(_, poly)| poly.len().saturating_sub(2))
+            .sum()
+    }
+
+    /// Simplified copy of the mesh, by repeatedly collapsing the edge whose
+    /// removal changes the surface the least.
+    ///
+    /// Borders, texture and normal seams and boundaries between groups are
+    /// kept in place, so materials stay where they were. Polygons are split
+    /// into triangles, and invalid or degenerate ones are dropped. Positions
+    /// are matched by index, so meshes with duplicated positions should be
+    /// welded first.
+    pub fn simplify(&self, options: &SimplifyOptions) -> MeshData {
+        self.simplify_with_error(options).0
+    }
+
+    /// Like `simplify`, also returning an estimate of the largest distance
+    /// between the simplified and the original surface.
+    pub fn simplify_with_error(&self, options: &SimplifyOptions) -> (MeshData, f32) {
+        let mut simplifier = Simplifier::new(self);
+        let error = simplifier.run(options).sqrt() as f32;
+
+        let mut mesh = self.clone();
+        for object in &mut mesh.objects {
+            for group in &mut object.groups {
+                group.polys.clear();
+            }
+        }
+        for (t, triangle) in simplifier.triangles.iter().enumerate() {
+            if simplifier.alive[t] {
+                let (o, g) = simplifier.owner[t];
+                mesh.objects[o].groups[g].polys.push(triangle.to_vec());
+            }
+        }
+        mesh.remove_unreferenced_vertices();
+        (mesh, error)
+    }
+
+    /// Chain of up to `levels` simplified copies of the mesh, each one with
+    /// about `ratio` times the triangles of the previous one, along with their
+    /// estimated distance to the original surface.
+    ///
+    /// The chain stops early once the mesh cannot be simplified further.
+    pub fn simplify_levels(&self, levels: usize, ratio: f32) -> Vec<(MeshData, f32)> {
+        let mut chain: Vec<(MeshData, f32)> = Vec::with_capacity(levels);
+        for _ in 0..levels {
+            let (previous, previous_error) = chain.last().map_or((self, 0.), |(m, e)| (m, *e));
+            let count = previous.triangle_count();
+            let options = SimplifyOptions {
+                target_triangles: (count as f32 * ratio) as usize,
+                ..SimplifyOptions::default()
+            };
+            let (level, error) = previous.simplify_with_error(&options);
+            if level.triangle_count() >= count {
+                break;
+            }
+            // errors add up, as every level is simplified from the previous one
+            chain.push((level, previous_error + error));
+        }
+        chain
+    }
+}
+
+/// A flat `n` x `n` grid of quads, split in two groups along `x = n / 2`.
+#[cfg(test)]
+fn grid(n: usize) -> MeshData {
+    let mut mesh = MeshData::default();
+    for y in 0..=n {
+        for x in 0..=n {
+            mesh.position.push(Vector3::new(x as f32, y as f32, 0.));
+        }
+    }
+    let mut object = super::Object::new("grid".to_string());
+    let mut groups = [
+        super::Group::new("left".to_string()),
+        super::Group::new("right".to_string()),
+    ];
+    for y in 0..n {
+        for x in 0..n {
+            let i = y * (n + 1) + x;
+            let quad = [i, i + 1, i + n + 2, i + n + 1];
+            groups[(x >= n / 2) as usize]
+                .polys
+                .push(quad.iter().map(|&p| IndexTuple(p, None, None)).collect());
+        }
+    }
+    object.groups.extend(groups.iter().cloned());
+    mesh.objects.push(object);
+    mesh
+}
+
+#[test]
+fn test_simplify_flat_grid() {
+    let mesh = grid(8);
+    assert_eq!(mesh.triangle_count(), 128);
+
+    let options = SimplifyOptions {
+        max_error: 1e-6,
+        ..SimplifyOptions::default()
+    };
+    let simple = mesh.simplify(&options);
+    assert!(simple.triangle_count() < 32);
+    assert!(simple
+        .validate()
+        .iter()
+        .all(|issue| matches!(issue, super::Issue::BoundaryLoop { .. })));
+
+    // no triangle crosses the boundary between the groups
+    for (g, group) in simple.objects[0].groups.iter().enumerate() {
+        assert!(!group.polys.is_empty());
+        for poly in &group.polys {
+            for v in poly {
+                let x = simple.position[v.0].x;
+                assert!(if g == 0 { x <= 4. } else { x >= 4. });
+            }
+        }
+    }
+
+    // the corners of the grid are kept
+    for &(x, y) in &[(0., 0.), (8., 0.), (0., 8.), (8., 8.)] {
+        assert!(simple.position.contains(&Vector3::new(x, y, 0.)));
+    }
+}
+
+#[test]
+fn test_simplify_target() {
+    let mesh = grid(8);
+    let options = SimplifyOptions {
+        target_triangles: 100,
+        ..SimplifyOptions::default()
+    };
+    let simple = mesh.simplify(&options);
+    assert!(simple.triangle_count() <= 100 && simple.triangle_count() >= 96);
+
+    let levels = mesh.simplify_levels(2, 0.5);
+    assert_eq!(levels.len(), 2);
+    assert!(levels[1].0.triangle_count() < levels[0].0.triangle_count());
+    assert!(levels[1].1 >= levels[0].1);
+}
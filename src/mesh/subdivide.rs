@@ -0,0 +1,375 @@
+// Loop & Catmull-Clark subdivision of `MeshData`
+
+use std::collections::{BTreeMap, HashMap};
+
+use cgmath::prelude::*;
+use cgmath::Vector3;
+
+use super::{IndexTuple, MeshData, SimplePolygon};
+
+/// Subdivision scheme, see `MeshData::subdivide`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scheme {
+    /// Loop subdivision, splitting every triangle in 4. Other polygons are
+    /// split into triangle fans first.
+    Loop,
+    /// Catmull-Clark subdivision, splitting every polygon of `n` sides into `n`
+    /// quads.
+    CatmullClark,
+}
+
+/// Options of `MeshData::subdivide`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Subdivision {
+    pub scheme: Scheme,
+    /// Number of times the mesh is subdivided.
+    pub levels: usize,
+}
+
+/// New point as a weighted sum of the points of the previous level.
+type Stencil = Vec<(usize, f32)>;
+
+fn average(points: &[usize]) -> Stencil {
+    let weight = 1. / points.len() as f32;
+    points.iter().map(|&p| (p, weight)).collect()
+}
+
+fn scaled(stencil: &[(usize, f32)], scale: f32) -> impl Iterator<Item = (usize, f32)> + '_ {
+    stencil.iter().map(move |&(p, w)| (p, w * scale))
+}
+
+fn apply<V: VectorSpace<Scalar = f32>>(values: &[V], stencil: &[(usize, f32)]) -> V {
+    stencil
+        .iter()
+        .fold(V::zero(), |acc, &(p, w)| acc + values[p] * w)
+}
+
+/// Edges and faces around a point of the previous level.
+#[derive(Default)]
+struct Ring {
+    faces: Vec<usize>,
+    /// Other end of every edge, and whether the edge is sharp.
+    edges: Vec<(usize, bool)>,
+}
+
+/// Subdivided connectivity of a single attribute, like positions or texture
+/// coordinates.
+struct Channel {
+    /// The points of the new level.
+    stencils: Vec<Stencil>,
+    /// The polygons every polygon of the previous level is split into, `None`
+    /// for polygons without this attribute.
+    faces: Vec<Option<Vec<Vec<usize>>>>,
+}
+
+impl Channel {
+    /// Subdivide the polygons given as indices of a single attribute.
+    ///
+    /// Edges used by a single polygon, by more than two polygons, or between
+    /// polygons of different `groups` are kept sharp. Since the connectivity
+    /// only depends on the indices of the attribute, seams in texture
+    /// coordinates or normals are kept sharp as well.
+    fn new(scheme: Scheme, faces: &[Option<Vec<usize>>], groups: &[usize]) -> Self {
+        let mut edges: BTreeMap<(usize, usize), Vec<usize>> = BTreeMap::new();
+        for (f, face) in faces.iter().enumerate() {
+            if let Some(face) = face {
+                for i in 0..face.len() {
+                    let (a, b) = (face[i], face[(i + 1) % face.len()]);
+                    edges.entry((a.min(b), a.max(b))).or_default().push(f);
+                }
+            }
+        }
+        let is_sharp = |faces: &[usize]| faces.len() != 2 || groups[faces[0]] != groups[faces[1]];
+
+        let mut rings: BTreeMap<usize, Ring> = BTreeMap::new();
+        for (&(a, b), adjacent) in &edges {
+            let sharp = is_sharp(adjacent);
+            rings.entry(a).or_default().edges.push((b, sharp));
+            rings.entry(b).or_default().edges.push((a, sharp));
+        }
+        for (f, face) in faces.iter().enumerate() {
+            for &p in face.iter().flatten() {
+                rings.entry(p).or_default().faces.push(f);
+            }
+        }
+
+        let face_points: Vec<Stencil> = faces
+            .iter()
+            .map(|face| face.as_ref().map_or(Vec::new(), |face| average(face)))
+            .collect();
+
+        // vertex points, then edge points, then face points
+        let mut stencils = Vec::new();
+        let mut vertex_index = HashMap::new();
+        for (&p, ring) in &rings {
+            vertex_index.insert(p, stencils.len());
+            stencils.push(Self::vertex_point(scheme, p, ring, &face_points));
+        }
+        let mut edge_index = HashMap::new();
+        for (&(a, b), adjacent) in &edges {
+            edge_index.insert((a, b), stencils.len());
+            let stencil = if is_sharp(adjacent) {
+                average(&[a, b])
+            } else {
+                match scheme {
+                    Scheme::CatmullClark => {
+                        let mut stencil = average(&[a, b]);
+                        for &f in adjacent {
+                            stencil.extend(scaled(&face_points[f], 0.5));
+                        }
+                        scaled(&stencil, 0.5).collect()
+                    }
+                    Scheme::Loop => {
+                        let mut stencil = vec![(a, 3. / 8.), (b, 3. / 8.)];
+                        for &f in adjacent {
+                            let face = faces[f].as_ref().unwrap();
+                            let opposite = face.iter().find(|&&p| p != a && p != b);
+                            stencil.extend(opposite.map(|&p| (p, 1. / 8.)));
+                        }
+                        stencil
+                    }
+                }
+            };
+            stencils.push(stencil);
+        }
+
+        let edge = |a: usize, b: usize| edge_index[&(a.min(b), a.max(b))];
+        let faces = faces
+            .iter()
+            .zip(face_points)
+            .map(|(face, face_point)| {
+                let face = face.as_ref()?;
+                let n = face.len();
+                let children = match scheme {
+                    Scheme::CatmullClark => {
+                        let center = stencils.len();
+                        stencils.push(face_point);
+                        (0..n)
+                            .map(|i| {
+                                let (prev, p, next) =
+                                    (face[(i + n - 1) % n], face[i], face[(i + 1) % n]);
+                                vec![vertex_index[&p], edge(p, next), center, edge(prev, p)]
+                            })
+                            .collect()
+                    }
+                    Scheme::Loop => {
+                        let (a, b, c) = (face[0], face[1], face[2]);
+                        let (ab, bc, ca) = (edge(a, b), edge(b, c), edge(c, a));
+                        vec![
+                            vec![vertex_index[&a], ab, ca],
+                            vec![vertex_index[&b], bc, ab],
+                            vec![vertex_index[&c], ca, bc],
+                            vec![ab, bc, ca],
+                        ]
+                    }
+                };
+                Some(children)
+            })
+            .collect();
+
+        Channel { stencils, faces }
+    }
+
+    fn vertex_point(scheme: Scheme, p: usize, ring: &Ring, face_points: &[Stencil]) -> Stencil {
+        let sharp: Vec<usize> = ring.edges.iter().filter(|e| e.1).map(|e| e.0).collect();
+        match sharp.len() {
+            // smooth, or the end of a crease
+            0 | 1 => {}
+            // along a border or crease, use the cubic B-spline curve rule
+            2 => return vec![(p, 6. / 8.), (sharp[0], 1. / 8.), (sharp[1], 1. / 8.)],
+            // corners stay in place
+            _ => return vec![(p, 1.)],
+        }
+
+        let n = ring.edges.len() as f32;
+        match scheme {
+            Scheme::CatmullClark => {
+                // (Q + 2R + (n - 3)P) / n, with Q the average of the face
+                // points and R the average of the edge midpoints
+                let mut stencil = vec![(p, (n - 3.) / n + 1. / n)];
+                let face_weight = 1. / (ring.faces.len() as f32 * n);
+                for &f in &ring.faces {
+                    stencil.extend(scaled(&face_points[f], face_weight));
+                }
+                stencil.extend(ring.edges.iter().map(|e| (e.0, 1. / (n * n))));
+                stencil
+            }
+            Scheme::Loop => {
+                let beta = if ring.edges.len() == 3 {
+                    3. / 16.
+                } else {
+                    3. / (8. * n)
+                };
+                let mut stencil = vec![(p, 1. - n * beta)];
+                stencil.extend(ring.edges.iter().map(|e| (e.0, beta)));
+                stencil
+            }
+        }
+    }
+}
+
+impl MeshData {
+    /// Subdivide every object of the mesh, see `subdivide_object`.
+    pub fn subdivide(&mut self, subdivision: Subdivision) {
+        for object in 0..self.objects.len() {
+            self.subdivide_object(object, subdivision);
+        }
+    }
+
+    /// Smooth the polygons of a single object by subdividing them.
+    ///
+    /// Texture coordinates, normals and vertex colors are subdivided along
+    /// with the positions. Borders, boundaries between groups and seams of
+    /// the texture coordinates and normals are kept sharp, so materials do not
+    /// bleed into each other. Invalid polygons are dropped.
+    pub fn subdivide_object(&mut self, object: usize, subdivision: Subdivision) {
+        for _ in 0..subdivision.levels {
+            self.subdivide_once(object, subdivision.scheme);
+        }
+        self.remove_unreferenced_vertices();
+    }
+
+    fn subdivide_once(&mut self, object: usize, scheme: Scheme) {
+        let mut polys: Vec<SimplePolygon> = Vec::new();
+        let mut groups = Vec::new();
+        for (g, group) in self.objects[object].groups.iter_mut().enumerate() {
+            for poly in group.polys.drain(..) {
+                match scheme {
+                    Scheme::Loop => polys.extend(
+                        (1..poly.len().saturating_sub(1))
+                            .map(|i| vec![poly[0], poly[i], poly[i + 1]]),
+                    ),
+                    Scheme::CatmullClark => polys.push(poly),
+                }
+                groups.resize(polys.len(), g);
+            }
+        }
+        let (polys, groups): (Vec<_>, Vec<_>) = polys
+            .into_iter()
+            .zip(groups)
+            .filter(|(poly, _)| poly.len() >= 3 && self.is_valid_face(poly))
+            .unzip();
+
+        let channel = |attribute: &dyn Fn(&IndexTuple) -> Option<usize>| {
+            let faces: Vec<Option<Vec<usize>>> = polys
+                .iter()
+                .map(|poly| poly.iter().map(attribute).collect())
+                .collect();
+            Channel::new(scheme, &faces, &groups)
+        };
+        let position = channel(&|v| Some(v.0));
+        let texture = channel(&|v| v.1);
+        let normal = channel(&|v| v.2);
+
+        let offsets = (self.position.len(), self.texture.len(), self.normal.len());
+        let subdivide = |values: &[_], channel: &Channel| -> Vec<_> {
+            channel.stencils.iter().map(|s| apply(values, s)).collect()
+        };
+        let positions: Vec<Vector3<f32>> = subdivide(&self.position, &position);
+        self.position.extend(positions);
+        if !self.color.is_empty() {
+            let colors = position
+                .stencils
+                .iter()
+                .map(|s| apply(&self.color, s))
+                .collect::<Vec<_>>();
+            self.color.extend(colors);
+        }
+        let textures = texture
+            .stencils
+            .iter()
+            .map(|s| apply(&self.texture, s))
+            .collect::<Vec<_>>();
+        self.texture.extend(textures);
+        let normals: Vec<Vector3<f32>> = subdivide(&self.normal, &normal);
+        self.normal.extend(normals.into_iter().map(|n| {
+            if n.magnitude2() > 0. {
+                n.normalize()
+            } else {
+                n
+            }
+        }));
+
+        for (f, &g) in groups.iter().enumerate() {
+            let children = position.faces[f].as_ref().unwrap();
+            for (c, child) in children.iter().enumerate() {
+                let poly = (0..child.len())
+                    .map(|i| {
+                        let attribute = |channel: &Channel, offset: usize| {
+                            channel.faces[f].as_ref().map(|faces| faces[c][i] + offset)
+                        };
+                        IndexTuple(
+                            child[i] + offsets.0,
+                            attribute(&texture, offsets.1),
+                            attribute(&normal, offsets.2),
+                        )
+                    })
+                    .collect();
+                self.objects[object].groups[g].polys.push(poly);
+            }
+        }
+    }
+}
+
+/// The shared cube scaled to a side of 2 around the origin, with its top face
+/// moved to a group of its own.
+#[cfg(test)]
+fn cube() -> MeshData {
+    let mut mesh = super::fixtures::cube();
+    for p in &mut mesh.position {
+        *p = *p * 2. - Vector3::new(1., 1., 1.);
+    }
+    let sides = &mut mesh.objects[0].groups[0];
+    sides.name = "sides".to_string();
+    let mut top = super::Group::new("top".to_string());
+    top.polys.push(sides.polys.remove(3));
+    mesh.objects[0].groups.push(top);
+    mesh
+}
+
+#[test]
+fn test_catmull_clark() {
+    let mut mesh = cube();
+    let mut single_group = mesh.clone();
+    let top = single_group.objects[0].groups.pop().unwrap();
+    single_group.objects[0].groups[0].polys.extend(top.polys);
+
+    let subdivision = Subdivision {
+        scheme: Scheme::CatmullClark,
+        levels: 1,
+    };
+    single_group.subdivide(subdivision);
+    // 8 vertex points, 12 edge points & 6 face points
+    assert_eq!(single_group.position.len(), 26);
+    assert_eq!(single_group.triangle_count(), 24 * 2);
+    assert!(single_group.validate().is_empty());
+    // the corners of a smooth cube move inwards
+    let corner = 5. / 9.;
+    assert!(single_group
+        .position
+        .iter()
+        .any(|p| (p.x - corner).abs() < 1e-5
+            && (p.y - corner).abs() < 1e-5
+            && (p.z - corner).abs() < 1e-5));
+
+    // the edges around the top face are kept sharp, so their edge points stay
+    // in the middle of the edges
+    mesh.subdivide(subdivision);
+    assert!(mesh.position.contains(&Vector3::new(0., 1., 1.)));
+    assert!(!single_group.position.contains(&Vector3::new(0., 1., 1.)));
+    assert!(mesh.validate().is_empty());
+}
+
+#[test]
+fn test_loop() {
+    let mut mesh = cube();
+    let top = mesh.objects[0].groups.pop().unwrap();
+    mesh.objects[0].groups[0].polys.extend(top.polys);
+    mesh.subdivide(Subdivision {
+        scheme: Scheme::Loop,
+        levels: 2,
+    });
+    assert_eq!(mesh.triangle_count(), 12 * 16);
+    assert!(mesh.validate().is_empty());
+    assert!(mesh.position.iter().all(|p| p.magnitude() < 3f32.sqrt()));
+}
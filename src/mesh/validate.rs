@@ -0,0 +1,556 @@
+// validation & repair of `MeshData`
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use cgmath::prelude::*;
+use cgmath::Vector3;
+
+use super::{IndexTuple, MeshData, SimplePolygon};
+
+/// Faces with an area below this are reported as degenerate.
+const DEGENERATE_AREA: f32 = 1e-12;
+
+/// Position of a polygon in a mesh.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FaceRef {
+    /// Index of the object in `MeshData::objects`.
+    pub object: usize,
+    /// Index of the group in `Object::groups`.
+    pub group: usize,
+    /// Index of the polygon in `Group::polys`.
+    pub face: usize,
+}
+
+/// A problem found by `MeshData::validate`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    /// The face references a position, texture coordinate or normal that does
+    /// not exist. These faces are ignored by the other checks.
+    IndexOutOfRange { face: FaceRef },
+    /// The face has less than 3 distinct positions, or no area.
+    DegenerateFace { face: FaceRef },
+    /// The face uses the same positions as an earlier face.
+    DuplicateFace { face: FaceRef, original: FaceRef },
+    /// The edge between two positions is shared by more than two faces.
+    NonManifoldEdge { edge: (usize, usize), faces: usize },
+    /// A closed chain of edges used by a single face, i.e. a hole or the border
+    /// of an open surface. Positions are in the order of their faces.
+    BoundaryLoop { vertices: Vec<usize> },
+    /// Two faces sharing an edge traverse it in the same direction, so one of
+    /// them is flipped.
+    InconsistentWinding { face: FaceRef, neighbour: FaceRef },
+    /// The position is not used by any face, line or point.
+    UnreferencedVertex { vertex: usize },
+}
+
+/// Directed edges of the valid faces, with the faces using them.
+type EdgeMap = HashMap<(usize, usize), Vec<FaceRef>>;
+
+fn edges(poly: &SimplePolygon) -> impl Iterator<Item = (usize, usize)> + '_ {
+    (0..poly.len()).map(move |i| (poly[i].0, poly[(i + 1) % poly.len()].0))
+}
+
+impl MeshData {
+    /// Iterate over every polygon of the mesh.
+    pub fn faces(&self) -> impl Iterator<Item = (FaceRef, &SimplePolygon)> {
+        self.objects.iter().enumerate().flat_map(|(o, object)| {
+            object
+                .groups
+                .iter()
+                .enumerate()
+                .flat_map(move |(g, group)| {
+                    group.polys.iter().enumerate().map(move |(f, poly)| {
+                        let face = FaceRef {
+                            object: o,
+                            group: g,
+                            face: f,
+                        };
+                        (face, poly)
+                    })
+                })
+        })
+    }
+
+    /// Keep only the polygons for which `keep` returns `true`, returning the
+    /// number of removed polygons.
+    pub fn retain_faces<F>(&mut self, mut keep: F) -> usize
+    where
+        F: FnMut(FaceRef, &SimplePolygon) -> bool,
+    {
+        let mut removed = 0;
+        for (o, object) in self.objects.iter_mut().enumerate() {
+            for (g, group) in object.groups.iter_mut().enumerate() {
+                let before = group.polys.len();
+                let mut f = 0;
+                group.polys.retain(|poly| {
+                    let face = FaceRef {
+                        object: o,
+                        group: g,
+                        face: f,
+                    };
+                    f += 1;
+                    keep(face, poly)
+                });
+                removed += before - group.polys.len();
+            }
+        }
+        removed
+    }
+
+    fn is_in_range(&self, &IndexTuple(p, t, n): &IndexTuple) -> bool {
+        p < self.position.len()
+            && t.is_none_or(|t| t < self.texture.len())
+            && n.is_none_or(|n| n < self.normal.len())
+    }
+
+    /// Whether every index of the polygon points to existing vertex data.
+    pub fn is_valid_face(&self, poly: &SimplePolygon) -> bool {
+        poly.iter().all(|v| self.is_in_range(v))
+    }
+
+    /// Area of a (possibly non planar) polygon, using Newell's method.
+    pub fn face_area(&self, poly: &SimplePolygon) -> f32 {
+        let mut normal = Vector3::zero();
+        for (a, b) in edges(poly) {
+            normal += self.position[a].cross(self.position[b]);
+        }
+        normal.magnitude() / 2.
+    }
+
+    /// Whether a valid polygon has less than 3 distinct positions, or no area.
+    pub fn is_degenerate_face(&self, poly: &SimplePolygon) -> bool {
+        let distinct: HashSet<usize> = poly.iter().map(|v| v.0).collect();
+        distinct.len() < 3 || self.face_area(poly) < DEGENERATE_AREA
+    }
+
+    fn edge_map(&self) -> EdgeMap {
+        let mut map = EdgeMap::new();
+        for (face, poly) in self.faces() {
+            if !self.is_valid_face(poly) {
+                continue;
+            }
+            for edge in edges(poly).filter(|(a, b)| a != b) {
+                map.entry(edge).or_default().push(face);
+            }
+        }
+        map
+    }
+
+    /// Number of faces using the edge in either direction.
+    fn edge_use(map: &EdgeMap, (a, b): (usize, usize)) -> usize {
+        map.get(&(a, b)).map_or(0, Vec::len) + map.get(&(b, a)).map_or(0, Vec::len)
+    }
+
+    /// Chains the edges used by a single face into loops.
+    ///
+    /// Edges are followed regardless of their direction, so that loops next to
+    /// flipped faces are still found. Chains stopping before they get back to
+    /// their first vertex, e.g. next to a non manifold edge, are returned as
+    /// not closed.
+    fn boundary_loops(map: &EdgeMap) -> Vec<(Vec<usize>, bool)> {
+        let mut boundary: Vec<(usize, usize)> = map
+            .keys()
+            .filter(|&&edge| Self::edge_use(map, edge) == 1)
+            .cloned()
+            .collect();
+        // sort so loops are reported in a stable order
+        boundary.sort();
+        let mut adjacent: HashMap<usize, Vec<(usize, usize)>> = HashMap::new();
+        for (i, &(a, b)) in boundary.iter().enumerate() {
+            adjacent.entry(a).or_default().push((b, i));
+            adjacent.entry(b).or_default().push((a, i));
+        }
+
+        let mut used = vec![false; boundary.len()];
+        let mut loops = Vec::new();
+        for (i, &(start, second)) in boundary.iter().enumerate() {
+            if used[i] {
+                continue;
+            }
+            used[i] = true;
+            let mut vertices = vec![start];
+            let mut current = second;
+            while current != start {
+                vertices.push(current);
+                let next = adjacent[&current].iter().find(|&&(_, e)| !used[e]);
+                match next {
+                    Some(&(other, e)) => {
+                        used[e] = true;
+                        current = other;
+                    }
+                    None => break,
+                }
+            }
+            loops.push((vertices, current == start));
+        }
+        loops
+    }
+
+    /// Check the mesh for problems that may crash the renderer or show up as
+    /// artifacts.
+    pub fn validate(&self) -> Vec<Issue> {
+        let mut issues = Vec::new();
+        let mut shapes: HashMap<Vec<usize>, FaceRef> = HashMap::new();
+        let mut referenced = vec![false; self.position.len()];
+
+        for (face, poly) in self.faces() {
+            if !self.is_valid_face(poly) {
+                issues.push(Issue::IndexOutOfRange { face });
+                continue;
+            }
+            for v in poly {
+                referenced[v.0] = true;
+            }
+            if self.is_degenerate_face(poly) {
+                issues.push(Issue::DegenerateFace { face });
+                continue;
+            }
+            let mut shape: Vec<usize> = poly.iter().map(|v| v.0).collect();
+            shape.sort();
+            match shapes.get(&shape) {
+                Some(&original) => issues.push(Issue::DuplicateFace { face, original }),
+                None => {
+                    shapes.insert(shape, face);
+                }
+            }
+        }
+
+        let map = self.edge_map();
+        let mut undirected: Vec<(usize, usize)> = map
+            .keys()
+            .map(|&(a, b)| (a.min(b), a.max(b)))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        undirected.sort();
+        for edge in undirected {
+            let faces = Self::edge_use(&map, edge);
+            if faces > 2 {
+                issues.push(Issue::NonManifoldEdge { edge, faces });
+                continue;
+            }
+            // a manifold edge used twice in the same direction
+            for &directed in &[edge, (edge.1, edge.0)] {
+                if let Some(&[face, neighbour]) = map.get(&directed).map(Vec::as_slice) {
+                    issues.push(Issue::InconsistentWinding { face, neighbour });
+                }
+            }
+        }
+
+        for (vertices, _) in Self::boundary_loops(&map) {
+            issues.push(Issue::BoundaryLoop { vertices });
+        }
+
+        for object in &self.objects {
+            for group in &object.groups {
+                let lines = group.lines.iter().flat_map(|line| line.iter().map(|v| v.0));
+                for p in lines.chain(group.points.iter().cloned()) {
+                    if p < referenced.len() {
+                        referenced[p] = true;
+                    }
+                }
+            }
+        }
+        for (vertex, referenced) in referenced.into_iter().enumerate() {
+            if !referenced {
+                issues.push(Issue::UnreferencedVertex { vertex });
+            }
+        }
+
+        issues
+    }
+
+    /// Remove the polygons, lines and points referencing vertex data that does
+    /// not exist, returning how many were removed.
+    pub fn remove_invalid_faces(&mut self) -> usize {
+        let mesh = self.clone_vertices();
+        let mut removed = self.retain_faces(|_, poly| mesh.is_valid_face(poly));
+        for group in self.objects.iter_mut().flat_map(|o| o.groups.iter_mut()) {
+            let before = group.lines.len() + group.points.len();
+            group.lines.retain(|line| mesh.is_valid_face(line));
+            group.points.retain(|&p| p < mesh.position.len());
+            removed += before - group.lines.len() - group.points.len();
+        }
+        removed
+    }
+
+    /// Remove invalid polygons, and those with less than 3 distinct positions
+    /// or no area, returning how many were removed.
+    pub fn remove_degenerate_faces(&mut self) -> usize {
+        let mesh = self.clone_vertices();
+        self.retain_faces(|_, poly| mesh.is_valid_face(poly) && !mesh.is_degenerate_face(poly))
+    }
+
+    /// Remove polygons using the same positions as an earlier one, returning
+    /// how many were removed.
+    pub fn remove_duplicate_faces(&mut self) -> usize {
+        let mut shapes = HashSet::new();
+        self.retain_faces(|_, poly| {
+            let mut shape: Vec<usize> = poly.iter().map(|v| v.0).collect();
+            shape.sort();
+            shapes.insert(shape)
+        })
+    }
+
+    /// A copy of the mesh without its objects, to check polygons while the
+    /// objects are being modified.
+    fn clone_vertices(&self) -> MeshData {
+        MeshData {
+            position: self.position.clone(),
+            texture: self.texture.clone(),
+            normal: self.normal.clone(),
+            ..MeshData::default()
+        }
+    }
+
+    /// Apply `remap` to every position index of the faces, lines and points.
+    fn remap_positions(&mut self, remap: &[usize]) {
+        for object in &mut self.objects {
+            for group in &mut object.groups {
+                let polys = group.polys.iter_mut().chain(group.lines.iter_mut());
+                for v in polys.flat_map(|poly| poly.iter_mut()) {
+                    if let Some(&p) = remap.get(v.0) {
+                        v.0 = p;
+                    }
+                }
+                for p in &mut group.points {
+                    if let Some(&new) = remap.get(*p) {
+                        *p = new;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Merge positions closer than `epsilon` to each other, returning how many
+    /// positions were removed.
+    ///
+    /// Vertex colors are kept from the first merged position.
+    pub fn weld_vertices(&mut self, epsilon: f32) -> usize {
+        let cell = |p: Vector3<f32>| {
+            let c = p / epsilon.max(f32::EPSILON);
+            (c.x.floor() as i64, c.y.floor() as i64, c.z.floor() as i64)
+        };
+
+        // positions are hashed on a grid of `epsilon` sized cells, and compared
+        // against the positions of the neighbouring cells
+        let mut grid: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+        let mut remap = Vec::with_capacity(self.position.len());
+        let mut kept: Vec<usize> = Vec::new();
+        for (i, &p) in self.position.iter().enumerate() {
+            let (x, y, z) = cell(p);
+            let mut found: Option<usize> = None;
+            'search: for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        for &k in grid.get(&(x + dx, y + dy, z + dz)).into_iter().flatten() {
+                            if (self.position[kept[k]] - p).magnitude() <= epsilon {
+                                found = Some(k);
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+            }
+            remap.push(found.unwrap_or_else(|| {
+                kept.push(i);
+                grid.entry((x, y, z)).or_default().push(kept.len() - 1);
+                kept.len() - 1
+            }));
+        }
+
+        let removed = self.position.len() - kept.len();
+        self.position = kept.iter().map(|&i| self.position[i]).collect();
+        if !self.color.is_empty() {
+            self.color = kept.iter().map(|&i| self.color[i]).collect();
+        }
+        self.remap_positions(&remap);
+        removed
+    }
+
+    /// Remove the positions not used by any face, line or point, returning how
+    /// many were removed.
+    pub fn remove_unreferenced_vertices(&mut self) -> usize {
+        let mut referenced = vec![false; self.position.len()];
+        for object in &self.objects {
+            for group in &object.groups {
+                let polys = group.polys.iter().chain(group.lines.iter());
+                let used = polys.flat_map(|poly| poly.iter().map(|v| v.0));
+                for p in used.chain(group.points.iter().cloned()) {
+                    if p < referenced.len() {
+                        referenced[p] = true;
+                    }
+                }
+            }
+        }
+
+        let mut remap = vec![0; self.position.len()];
+        let mut count = 0;
+        for (i, &used) in referenced.iter().enumerate() {
+            if used {
+                remap[i] = count;
+                self.position[count] = self.position[i];
+                if !self.color.is_empty() {
+                    self.color[count] = self.color[i];
+                }
+                count += 1;
+            }
+        }
+        let removed = self.position.len() - count;
+        self.position.truncate(count);
+        if !self.color.is_empty() {
+            self.color.truncate(count);
+        }
+        self.remap_positions(&remap);
+        removed
+    }
+
+    /// Flip polygons so that neighbouring faces traverse their shared edges in
+    /// opposite directions, returning how many polygons were flipped.
+    ///
+    /// The first face of every connected part of the mesh keeps its winding.
+    /// Non manifold edges are ignored.
+    pub fn fix_winding(&mut self) -> usize {
+        let map = self.edge_map();
+        let mut neighbours: HashMap<FaceRef, Vec<(FaceRef, bool)>> = HashMap::new();
+        for (&(a, b), faces) in &map {
+            if Self::edge_use(&map, (a, b)) != 2 {
+                continue;
+            }
+            // `same` if both faces use the edge in the same direction
+            let (pair, same) = match (faces.as_slice(), map.get(&(b, a))) {
+                (&[f0, f1], _) => ((f0, f1), true),
+                (&[f0], Some(other)) if (a, b) < (b, a) => ((f0, other[0]), false),
+                _ => continue,
+            };
+            neighbours.entry(pair.0).or_default().push((pair.1, same));
+            neighbours.entry(pair.1).or_default().push((pair.0, same));
+        }
+
+        // breadth first traversal, tracking if each face has to be flipped
+        let mut flip: HashMap<FaceRef, bool> = HashMap::new();
+        let mut faces: Vec<FaceRef> = self
+            .faces()
+            .filter(|(_, poly)| self.is_valid_face(poly))
+            .map(|(face, _)| face)
+            .collect();
+        faces.sort();
+        for start in faces {
+            if flip.contains_key(&start) {
+                continue;
+            }
+            flip.insert(start, false);
+            let mut queue = VecDeque::new();
+            queue.push_back(start);
+            while let Some(face) = queue.pop_front() {
+                let flipped = flip[&face];
+                for &(neighbour, same) in neighbours.get(&face).into_iter().flatten() {
+                    if let Entry::Vacant(entry) = flip.entry(neighbour) {
+                        entry.insert(flipped ^ same);
+                        queue.push_back(neighbour);
+                    }
+                }
+            }
+        }
+
+        let mut count = 0;
+        for (face, flipped) in flip {
+            if flipped {
+                let poly = &mut self.objects[face.object].groups[face.group].polys[face.face];
+                poly.reverse();
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Close the boundary loops of at most `max_edges` edges with new
+    /// polygons, returning how many holes were filled.
+    ///
+    /// Holes are filled with a single polygon, wound consistently with its
+    /// neighbours and added to the group of one of them.
+    pub fn fill_holes(&mut self, max_edges: usize) -> usize {
+        let map = self.edge_map();
+        let mut count = 0;
+        for (vertices, closed) in Self::boundary_loops(&map) {
+            // a polygon across the gap of an open chain would be made up
+            if !closed || vertices.len() < 3 || vertices.len() > max_edges {
+                continue;
+            }
+            // the loop starts with an edge in the direction of its face
+            let face = map[&(vertices[0], vertices[1])][0];
+            let hole: SimplePolygon = vertices
+                .iter()
+                .rev()
+                .map(|&p| IndexTuple(p, None, None))
+                .collect();
+            self.objects[face.object].groups[face.group]
+                .polys
+                .push(hole);
+            count += 1;
+        }
+        count
+    }
+}
+
+#[test]
+fn test_validate_closed_mesh() {
+    assert_eq!(super::fixtures::cube().validate(), vec![]);
+}
+
+#[test]
+fn test_repair() {
+    let mut mesh = super::fixtures::cube();
+    let polys = &mut mesh.objects[0].groups[0].polys;
+    polys[1].reverse();
+    let top = polys.remove(3);
+    polys.push(vec![
+        IndexTuple(0, None, None),
+        IndexTuple(9, None, None),
+        IndexTuple(1, None, None),
+    ]);
+    mesh.position.push(Vector3::new(1., 1., 1.));
+
+    let issues = mesh.validate();
+    assert!(issues.contains(&Issue::IndexOutOfRange {
+        face: FaceRef {
+            object: 0,
+            group: 0,
+            face: 5
+        }
+    }));
+    assert!(issues.contains(&Issue::BoundaryLoop {
+        vertices: vec![2, 3, 7, 6]
+    }));
+    assert!(issues.contains(&Issue::UnreferencedVertex { vertex: 8 }));
+    assert!(issues
+        .iter()
+        .any(|issue| matches!(issue, Issue::InconsistentWinding { .. })));
+
+    assert_eq!(mesh.remove_invalid_faces(), 1);
+    assert_eq!(mesh.weld_vertices(1e-4), 1);
+    assert_eq!(mesh.fix_winding(), 1);
+    assert_eq!(mesh.fill_holes(4), 1);
+    assert_eq!(mesh.validate(), vec![]);
+
+    // the hole is closed with the winding of the removed face
+    let filled = mesh.objects[0].groups[0].polys.last().unwrap().clone();
+    let mut shape: Vec<usize> = filled.iter().map(|v| v.0).collect();
+    let start = shape.iter().position(|&p| p == top[0].0).unwrap();
+    shape.rotate_left(start);
+    assert_eq!(shape, top.iter().map(|v| v.0).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_fill_open_chain() {
+    // three triangles sharing the non manifold edge from 0 to 1, leaving a
+    // closed loop around the first two and an open chain along the last one
+    let mut mesh = super::fixtures::cube();
+    mesh.objects[0].groups[0].polys = [[0, 1, 2], [1, 0, 3], [0, 1, 4]]
+        .iter()
+        .map(|poly| poly.iter().map(|&p| IndexTuple(p, None, None)).collect())
+        .collect();
+    assert_eq!(mesh.fill_holes(4), 1);
+    assert_eq!(mesh.objects[0].groups[0].polys.len(), 4);
+}
@@ -0,0 +1,129 @@
+// indexed vertex buffer built from `MeshData`
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use cgmath::{Vector2, Vector3, Vector4, Zero};
+
+use super::{IndexTuple, MeshData};
+
+/// A single vertex of a `VertexBuffer`, with all its attributes interleaved.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vertex {
+    pub position: Vector3<f32>,
+    /// Texture coordinate, zero if the vertex has none.
+    pub texture: Vector2<f32>,
+    /// Normal, zero if the vertex has none.
+    pub normal: Vector3<f32>,
+    /// Vertex color, white if the mesh has no vertex colors.
+    pub color: Vector4<f32>,
+}
+
+/// The triangles of a single `Group`, as a range of the index buffer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrawRange {
+    /// Index of the object in `MeshData::objects`.
+    pub object: usize,
+    /// Index of the group in `Object::groups`.
+    pub group: usize,
+    /// Range of `VertexBuffer::indices`, always a multiple of 3.
+    pub indices: Range<usize>,
+}
+
+/// Triangles of a mesh as an interleaved vertex buffer and a `u32` index
+/// buffer.
+///
+/// Every unique `(position, texture, normal)` tuple of the faces becomes a
+/// single vertex, so vertices shared by several faces are only processed once.
+/// Polygons are split into triangle fans.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VertexBuffer {
+    pub vertices: Vec<Vertex>,
+    /// Indices of the welded tuple each vertex was built from.
+    pub tuples: Vec<IndexTuple>,
+    /// Three indices into `vertices` for every triangle.
+    pub indices: Vec<u32>,
+    /// One range per group, in the order of `MeshData::objects`.
+    pub ranges: Vec<DrawRange>,
+}
+
+impl VertexBuffer {
+    pub fn new(mesh: &MeshData) -> Self {
+        let mut welded: HashMap<IndexTuple, u32> = HashMap::new();
+        let mut buffer = VertexBuffer {
+            vertices: Vec::new(),
+            tuples: Vec::new(),
+            indices: Vec::new(),
+            ranges: Vec::new(),
+        };
+
+        for (o, object) in mesh.objects.iter().enumerate() {
+            for (g, group) in object.groups.iter().enumerate() {
+                let start = buffer.indices.len();
+                for poly in &group.polys {
+                    for i in 1..poly.len().saturating_sub(1) {
+                        for tuple in &[poly[0], poly[i], poly[i + 1]] {
+                            let index = *welded.entry(*tuple).or_insert_with(|| {
+                                buffer.vertices.push(Self::vertex(mesh, tuple));
+                                buffer.tuples.push(*tuple);
+                                (buffer.vertices.len() - 1) as u32
+                            });
+                            buffer.indices.push(index);
+                        }
+                    }
+                }
+                buffer.ranges.push(DrawRange {
+                    object: o,
+                    group: g,
+                    indices: start..buffer.indices.len(),
+                });
+            }
+        }
+        buffer
+    }
+
+    fn vertex(mesh: &MeshData, &IndexTuple(p, t, n): &IndexTuple) -> Vertex {
+        Vertex {
+            position: mesh.position[p],
+            texture: t.map_or(Vector2::zero(), |t| mesh.texture[t]),
+            normal: n.map_or(Vector3::zero(), |n| mesh.normal[n]),
+            color: mesh
+                .color
+                .get(p)
+                .cloned()
+                .unwrap_or_else(|| Vector4::new(1., 1., 1., 1.)),
+        }
+    }
+
+    /// Vertex indices of the triangles in `range`.
+    pub fn triangles(&self, range: &DrawRange) -> impl Iterator<Item = [usize; 3]> + '_ {
+        self.indices[range.indices.clone()]
+            .chunks_exact(3)
+            .map(|t| [t[0] as usize, t[1] as usize, t[2] as usize])
+    }
+}
+
+impl From<&MeshData> for VertexBuffer {
+    fn from(mesh: &MeshData) -> Self {
+        VertexBuffer::new(mesh)
+    }
+}
+
+#[test]
+fn test_vertex_buffer_welds_shared_vertices() {
+    let mut mesh = MeshData {
+        position: vec![Vector3::zero(); 4],
+        ..MeshData::default()
+    };
+    let mut object = super::Object::new("quad".to_string());
+    let mut group = super::Group::new("quad".to_string());
+    group.polys.push((0..4).map(|i| IndexTuple(i, None, None)).collect());
+    object.groups.push(group);
+    mesh.objects.push(object);
+
+    let buffer = VertexBuffer::new(&mesh);
+    assert_eq!(buffer.vertices.len(), 4);
+    assert_eq!(buffer.indices, vec![0, 1, 2, 0, 2, 3]);
+    assert_eq!(buffer.ranges[0].indices, 0..6);
+}
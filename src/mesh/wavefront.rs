@@ -4,17 +4,27 @@ use std::{
     borrow::Cow,
     fmt,
     io::{self, BufRead, BufReader, Error, Read},
+    mem,
     path::Path,
     str::FromStr,
     sync::Arc,
 };
 
-use cgmath::{Vector2, Vector3};
+use cgmath::{Vector2, Vector3, Vector4, Zero};
 
-use super::{Group, MeshData, MeshParser, Object, SimplePolygon, IndexTuple};
+use super::freeform::{CurveType, Element, FreeForm, Geometry, Tessellation};
+use super::{Group, LoadOptions, MeshData, MeshParser, Object, SimplePolygon, IndexTuple};
+
+#[allow(unused_imports)]
+use test::Bencher;
+
+#[cfg(test)]
+mod legacy;
 
 const DEFAULT_OBJECT: &str = "default";
 const DEFAULT_GROUP: &str = "default";
+/// Color of vertices without one, in files using the vertex color extension.
+const DEFAULT_COLOR: Vector4<f32> = Vector4::new(1., 1., 1., 1.);
 
 /// The model of an a single Material as defined in the .mtl spec.
 #[derive(Debug, Clone, PartialEq)]
@@ -70,6 +80,20 @@ impl Material {
             illum: None,
         }
     }
+
+    /// Opacity given by `d`, or by `Tr` written instead by some exporters, 1
+    /// if neither is set.
+    pub fn opacity(&self) -> f32 {
+        self.d.or_else(|| self.tr.map(|tr| 1. - tr)).unwrap_or(1.)
+    }
+
+    /// Roughness of the surface, from 0 for a mirror to 1, converted from the
+    /// Blinn-Phong exponent `Ns` if it is set.
+    pub fn roughness(&self) -> Option<f32> {
+        // the exponent of a Blinn-Phong lobe close to a GGX lobe of width
+        // `alpha`, the roughness squared, is `2 / alpha^2 - 2`
+        self.ns.map(|ns| (2. / (ns.max(0.) + 2.)).sqrt().sqrt())
+    }
 }
 
 /// Indicates type of a missing value
@@ -366,6 +390,8 @@ impl Mtl {
 pub enum ObjError {
     Io(io::Error),
     Unsupported,
+    /// The archive does not contain any supported mesh file.
+    MissingArchiveEntry,
     /// One of the arguments to `f` is malformed.
     MalformedFaceGroup {
         line_number: usize,
@@ -381,6 +407,11 @@ pub enum ObjError {
     MissingMTLName {
         line_number: usize,
     },
+    /// The control points of a free-form element do not match its degree and
+    /// `parm` values, or reference missing vertices.
+    MalformedFreeForm {
+        line_number: usize,
+    },
 }
 
 impl std::error::Error for ObjError {
@@ -411,7 +442,13 @@ impl fmt::Display for ObjError {
                 "mtllib command issued, but no name was specified. (line: {})",
                 line_number
             ),
+            ObjError::MalformedFreeForm { line_number } => write!(
+                f,
+                "free-form element does not match its degree and parameters. (line: {})",
+                line_number
+            ),
             ObjError::Unsupported => write!(f, "unsupported file extension"),
+            ObjError::MissingArchiveEntry => write!(f, "no .obj file found in the archive"),
         }
     }
 }
@@ -422,6 +459,50 @@ impl From<io::Error> for ObjError {
     }
 }
 
+/// Kind of problem reported by a `Diagnostic`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiagnosticKind {
+    /// A `v`, `vt`, `vn` or `vp` statement is malformed. Skipped `v`, `vt` and
+    /// `vn` statements are replaced by zero placeholders so the indices of the
+    /// following vertices still line up.
+    MalformedVertex,
+    /// A face, line, point, curve or surface has malformed arguments and was
+    /// skipped.
+    MalformedElement,
+    /// Any other statement is malformed and was skipped.
+    MalformedStatement,
+    /// A free-form element does not match its degree and parameters, and was
+    /// dropped.
+    MalformedFreeForm,
+    /// An index pointing past the parsed vertices was clamped to the first or
+    /// last one.
+    IndexClamped,
+}
+
+/// A problem found while parsing a .obj file in lenient mode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// 1 based number of the line of the statement, the `line_number` of
+    /// `ObjError` being 0 based.
+    pub line_number: usize,
+    /// 1 based column of the offending argument in `text`, counted in
+    /// characters.
+    pub column: usize,
+    pub kind: DiagnosticKind,
+    /// The original text of the statement, with continued lines joined.
+    pub text: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {:?}: {}",
+            self.line_number, self.column, self.kind, self.text
+        )
+    }
+}
+
 /// Error loading individual material libraries.
 ///
 /// The `Vec` items are tuples with first component being the the .mtl file, and
@@ -461,7 +542,7 @@ pub enum ObjMaterial {
 }
 
 impl ObjMaterial {
-    fn name(&self) -> &str {
+    pub fn name(&self) -> &str {
         match self {
             ObjMaterial::Ref(name) => name.as_str(),
             ObjMaterial::Mtl(material) => material.name.as_str(),
@@ -469,41 +550,206 @@ impl ObjMaterial {
     }
 }
 
-fn normalize(idx: isize, len: usize) -> usize {
+/// Convert a 1 based (or negative, relative to the end) index into a 0 based
+/// index. Returns `None` for `0` or for relative indices pointing before the
+/// first element.
+fn normalize(idx: isize, len: usize) -> Option<usize> {
     if idx < 0 {
-        (len as isize + idx) as usize
+        let idx = len as isize + idx;
+        if idx >= 0 {
+            Some(idx as usize)
+        } else {
+            None
+        }
+    } else if idx > 0 {
+        Some(idx as usize - 1)
+    } else {
+        None
+    }
+}
+
+fn current_group(group: &mut Option<Group>) -> &mut Group {
+    group.get_or_insert_with(|| Group::new(DEFAULT_GROUP.to_string()))
+}
+
+#[inline]
+fn parse_f32(word: &str) -> Option<f32> {
+    fast_float::parse(word).ok()
+}
+
+/// Parse a possibly negative vertex index, without the overhead of
+/// `FromStr` for the digits-only strings of .obj files.
+#[inline]
+fn parse_index(word: &str) -> Option<isize> {
+    let (negative, digits) = match word.as_bytes() {
+        [b'-', digits @ ..] => (true, digits),
+        [b'+', digits @ ..] => (false, digits),
+        digits => (false, digits),
+    };
+    if digits.is_empty() || digits.len() > 18 {
+        return None;
+    }
+    let mut idx = 0isize;
+    for &d in digits {
+        if !d.is_ascii_digit() {
+            return None;
+        }
+        idx = idx * 10 + (d - b'0') as isize;
+    }
+    Some(if negative { -idx } else { idx })
+}
+
+/// 1 based column of `word` in `line`, of which it must be a slice.
+fn column(line: &str, word: &str) -> usize {
+    let offset = word.as_ptr() as usize - line.as_ptr() as usize;
+    line[..offset].chars().count() + 1
+}
+
+/// Whether the statement only adds vertex data, independent of the parser
+/// state.
+fn is_attribute(first: &str) -> bool {
+    matches!(first, "v" | "vt" | "vn" | "vp")
+}
+
+/// Removes a trailing `\` from `line`, returning whether the line continues on
+/// the next one.
+fn strip_continuation(line: &mut String) -> bool {
+    let trimmed = line.trim_end();
+    if trimmed.ends_with('\\') {
+        let len = trimmed.len() - 1;
+        line.truncate(len);
+        line.push(' ');
+        true
     } else {
-        idx as usize - 1
+        false
     }
 }
 
-pub struct ObjData {}
+/// Calls `f` with the number and content of every logical line of `text`,
+/// joining lines continued with a trailing `\`.
+///
+/// Only continued lines are copied, all the others are borrowed from `text`.
+fn for_each_line<'a, F>(text: &'a str, first_line: usize, mut f: F) -> Result<(), ObjError>
+where
+    F: FnMut(usize, Cow<'a, str>) -> Result<(), ObjError>,
+{
+    let mut lines = text.lines().enumerate();
+    while let Some((idx, line)) = lines.next() {
+        if !line.trim_end().ends_with('\\') {
+            f(first_line + idx, Cow::Borrowed(line))?;
+            continue;
+        }
+
+        let mut joined = line.to_string();
+        while strip_continuation(&mut joined) {
+            match lines.next() {
+                Some((_, next)) => joined.push_str(next),
+                None => break,
+            }
+        }
+        f(first_line + idx, Cow::Owned(joined))?;
+    }
+    Ok(())
+}
+
+fn to_str(bytes: &[u8]) -> Result<&str, ObjError> {
+    std::str::from_utf8(bytes).map_err(|err| {
+        ObjError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("failed to readline {}", err),
+        ))
+    })
+}
+
+/// Number of `v`, `vt` and `vn` statements parsed so far, used to resolve
+/// relative indices.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Counts {
+    position: usize,
+    texture: usize,
+    normal: usize,
+}
+
+impl std::ops::Add for Counts {
+    type Output = Counts;
+
+    fn add(self, other: Counts) -> Counts {
+        Counts {
+            position: self.position + other.position,
+            texture: self.texture + other.texture,
+            normal: self.normal + other.normal,
+        }
+    }
+}
+
+/// Incremental .obj parser.
+///
+/// Logical lines are fed one by one to [`parse_line`], which only borrows
+/// them, and the model is assembled by [`finish`]. The `load_*` functions
+/// drive it from a reader, an in-memory buffer or, with the `parallel` feature,
+/// from chunks of a buffer parsed on several threads.
+///
+/// [`parse_line`]: #method.parse_line
+/// [`finish`]: #method.finish
+pub struct ObjData {
+    dat: MeshData,
+    object: Object,
+    group: Option<Group>,
+    counts: Counts,
+    /// `w` coordinate of each vertex position, by which the position is only
+    /// divided in `finish`: free-form elements use the undivided coordinates
+    /// as control points, and `w` as their weight if they are rational.
+    weights: Vec<f32>,
+    freeform: FreeForm,
+    /// Free-form elements waiting for all vertices to be known, along with the
+    /// object & group they belong to and the line of their `end` command.
+    tessellations: Vec<(usize, usize, usize, Tessellation)>,
+    /// Whether to skip malformed lines instead of failing.
+    lenient: bool,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Default for ObjData {
+    fn default() -> Self {
+        ObjData {
+            dat: MeshData::default(),
+            object: Object::new(DEFAULT_OBJECT.to_string()),
+            group: None,
+            counts: Counts::default(),
+            weights: Vec::new(),
+            freeform: FreeForm::default(),
+            tessellations: Vec::new(),
+            lenient: false,
+            diagnostics: Vec::new(),
+        }
+    }
+}
 
 impl ObjData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a parser configured by the given load options.
+    pub fn with_options(options: &LoadOptions) -> Self {
+        ObjData {
+            lenient: options.lenient,
+            ..Self::default()
+        }
+    }
+
     fn parse_two(
         line_number: usize,
         n0: Option<&str>,
         n1: Option<&str>,
     ) -> Result<Vector2<f32>, ObjError> {
-        let (n0, n1) = match (n0, n1) {
-            (Some(n0), Some(n1)) => (n0, n1),
-            _ => {
-                return Err(ObjError::ArgumentListFailure {
-                    line_number,
-                    list: format!("{:?} {:?}", n0, n1),
-                });
-            }
-        };
-        let normal = match (FromStr::from_str(n0), FromStr::from_str(n1)) {
-            (Ok(n0), Ok(n1)) => Vector2::new(n0, n1),
-            _ => {
-                return Err(ObjError::ArgumentListFailure {
-                    line_number,
-                    list: format!("{:?} {:?}", n0, n1),
-                });
-            }
-        };
-        Ok(normal)
+        match (n0.and_then(parse_f32), n1.and_then(parse_f32)) {
+            (Some(n0), Some(n1)) => Ok(Vector2::new(n0, n1)),
+            _ => Err(ObjError::ArgumentListFailure {
+                line_number,
+                list: format!("{:?} {:?}", n0, n1),
+            }),
+        }
     }
 
     fn parse_three(
@@ -512,58 +758,101 @@ impl ObjData {
         n1: Option<&str>,
         n2: Option<&str>,
     ) -> Result<Vector3<f32>, ObjError> {
-        let (n0, n1, n2) = match (n0, n1, n2) {
-            (Some(n0), Some(n1), Some(n2)) => (n0, n1, n2),
-            _ => {
-                return Err(ObjError::ArgumentListFailure {
-                    line_number,
-                    list: format!("{:?} {:?} {:?}", n0, n1, n2),
-                });
-            }
-        };
-        let normal = match (
-            FromStr::from_str(n0),
-            FromStr::from_str(n1),
-            FromStr::from_str(n2),
+        match (
+            n0.and_then(parse_f32),
+            n1.and_then(parse_f32),
+            n2.and_then(parse_f32),
         ) {
-            (Ok(n0), Ok(n1), Ok(n2)) => Vector3::new(n0, n1, n2),
-            _ => {
-                return Err(ObjError::ArgumentListFailure {
-                    line_number,
-                    list: format!("{:?} {:?} {:?}", n0, n1, n2),
-                });
+            (Some(n0), Some(n1), Some(n2)) => Ok(Vector3::new(n0, n1, n2)),
+            _ => Err(ObjError::ArgumentListFailure {
+                line_number,
+                list: format!("{:?} {:?} {:?}", n0, n1, n2),
+            }),
+        }
+    }
+
+    /// Parse the remaining words into `values`, returning how many were read.
+    fn parse_array<'b, I>(
+        line_number: usize,
+        line: &str,
+        words: &mut I,
+        values: &mut [f32],
+    ) -> Result<usize, ObjError>
+    where
+        I: Iterator<Item = &'b str>,
+    {
+        let mut count = 0;
+        for word in words {
+            match (values.get_mut(count), parse_f32(word)) {
+                (Some(value), Some(v)) => *value = v,
+                _ => {
+                    return Err(ObjError::ArgumentListFailure {
+                        line_number,
+                        list: line.to_string(),
+                    });
+                }
             }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Resolve an index into the `len` vertices parsed so far.
+    ///
+    /// In lenient mode, indices past either end are clamped to the first or
+    /// last vertex.
+    fn resolve(
+        &mut self,
+        line_number: usize,
+        line: &str,
+        group: &str,
+        idx: isize,
+        len: usize,
+    ) -> Option<usize> {
+        if !self.lenient {
+            return normalize(idx, len);
+        }
+        let clamped = match normalize(idx, len) {
+            Some(i) if i < len => return Some(i),
+            _ if idx == 0 || len == 0 => return None,
+            Some(_) => len - 1,
+            None => 0,
         };
-        Ok(normal)
+        self.diagnostics.push(Diagnostic {
+            line_number: line_number + 1,
+            column: column(line, group),
+            kind: DiagnosticKind::IndexClamped,
+            text: line.to_string(),
+        });
+        Some(clamped)
     }
 
     fn parse_group(
-        &self,
-        mesh_data: &mut MeshData,
+        &mut self,
         line_number: usize,
+        line: &str,
         group: &str,
     ) -> Result<IndexTuple, ObjError> {
         let mut group_split = group.split('/');
-        let p: Option<isize> = group_split
-            .next()
-            .and_then(|idx| FromStr::from_str(idx).ok());
+        let p: Option<isize> = group_split.next().and_then(parse_index);
         let t: Option<isize> = group_split.next().and_then(|idx| {
             if idx != "" {
-                FromStr::from_str(idx).ok()
+                parse_index(idx)
             } else {
                 None
             }
         });
-        let n: Option<isize> = group_split
-            .next()
-            .and_then(|idx| FromStr::from_str(idx).ok());
+        let n: Option<isize> = group_split.next().and_then(parse_index);
 
+        let counts = self.counts;
+        let p = p.and_then(|p| self.resolve(line_number, line, group, p, counts.position));
+        let t = t.map(|t| self.resolve(line_number, line, group, t, counts.texture));
+        let n = n.map(|n| self.resolve(line_number, line, group, n, counts.normal));
         match (p, t, n) {
-            (Some(p), t, n) => Ok(IndexTuple(
-                normalize(p, mesh_data.position.len()),
-                t.map(|t| normalize(t, mesh_data.texture.len())),
-                n.map(|n| normalize(n, mesh_data.normal.len())),
-            )),
+            (Some(p), None, None) => Ok(IndexTuple(p, None, None)),
+            (Some(p), Some(Some(t)), None) => Ok(IndexTuple(p, Some(t), None)),
+            (Some(p), None, Some(Some(n))) => Ok(IndexTuple(p, None, Some(n))),
+            (Some(p), Some(Some(t)), Some(Some(n))) => Ok(IndexTuple(p, Some(t), Some(n))),
             _ => Err(ObjError::MalformedFaceGroup {
                 line_number,
                 group: String::from(group),
@@ -572,9 +861,9 @@ impl ObjData {
     }
 
     fn parse_face<'b, I>(
-        &self,
-        mesh_data: &mut MeshData,
+        &mut self,
         line_number: usize,
+        line: &str,
         groups: &mut I,
     ) -> Result<SimplePolygon, ObjError>
     where
@@ -582,134 +871,841 @@ impl ObjData {
     {
         let mut ret = Vec::with_capacity(4);
         for g in groups {
-            let ituple = self.parse_group(mesh_data, line_number, g)?;
+            let ituple = self.parse_group(line_number, line, g)?;
             ret.push(ituple);
         }
         Ok(ret)
     }
 
-    pub fn load_buf<R: Read>(&mut self, input: R) -> Result<MeshData, ObjError> {
-        let input = BufReader::new(input);
-        let mut dat = MeshData::default();
-        let mut object = Object::new(DEFAULT_OBJECT.to_string());
-        let mut group: Option<Group> = None;
-
-        for (idx, line) in input.lines().enumerate() {
-            let (line, mut words) = match line {
-                Ok(ref line) => (
-                    line.clone(),
-                    line.split_whitespace().filter(|s| !s.is_empty()),
-                ),
-                Err(err) => {
-                    return Err(ObjError::Io(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        format!("failed to readline {}", err),
-                    )));
+    fn parse_floats<'b, I>(line_number: usize, words: &mut I) -> Result<Vec<f32>, ObjError>
+    where
+        I: Iterator<Item = &'b str>,
+    {
+        words
+            .map(|w| {
+                parse_f32(w).ok_or_else(|| ObjError::ArgumentListFailure {
+                    line_number,
+                    list: w.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    fn parse_degree(line_number: usize, word: Option<&str>) -> Result<usize, ObjError> {
+        word.and_then(|w| FromStr::from_str(w).ok())
+            .ok_or_else(|| ObjError::ArgumentListFailure {
+                line_number,
+                list: format!("{:?}", word),
+            })
+    }
+
+    /// Parse a single logical line, i.e. with continuation lines already
+    /// joined, numbered from 0 like the `line_number` of `ObjError`.
+    ///
+    /// In lenient mode errors are recorded as diagnostics, and the line is
+    /// skipped.
+    pub fn parse_line(&mut self, line_number: usize, line: &str) -> Result<(), ObjError> {
+        let mut words = line.split_ascii_whitespace();
+        let (first, result) = match words.next() {
+            Some(first) if is_attribute(first) => (
+                first,
+                self.parse_attribute(line_number, line, first, &mut words),
+            ),
+            Some(first) => (
+                first,
+                self.parse_element(line_number, line, first, &mut words),
+            ),
+            None => return Ok(()),
+        };
+        match result {
+            Err(err) if self.lenient => {
+                self.recover(line_number, line, first, err);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Record the error of a skipped line.
+    ///
+    /// A placeholder is pushed for skipped `v`, `vt` and `vn` statements, so
+    /// that the following vertices keep their indices.
+    fn recover(&mut self, line_number: usize, line: &str, first: &str, err: ObjError) {
+        let kind = match first {
+            "v" | "vt" | "vn" | "vp" => DiagnosticKind::MalformedVertex,
+            "f" | "l" | "p" | "curv" | "surf" => DiagnosticKind::MalformedElement,
+            _ => DiagnosticKind::MalformedStatement,
+        };
+        let word = match err {
+            ObjError::MalformedFaceGroup { ref group, .. } => {
+                line.find(group.as_str()).map(|offset| &line[offset..])
+            }
+            _ => line
+                .split_ascii_whitespace()
+                .skip(1)
+                .find(|w| parse_f32(w).is_none()),
+        };
+        // missing arguments are reported past the end of the line
+        let word = word.unwrap_or(&line[line.len()..]);
+        self.diagnostics.push(Diagnostic {
+            line_number: line_number + 1,
+            column: column(line, word),
+            kind,
+            text: line.to_string(),
+        });
+
+        match first {
+            "v" => {
+                if !self.dat.color.is_empty() {
+                    self.dat.color.push(DEFAULT_COLOR);
                 }
-            };
-            let first = words.next();
-
-            match first {
-                Some("v") => {
-                    let (v0, v1, v2) = (words.next(), words.next(), words.next());
-                    dat.position.push(Self::parse_three(idx, v0, v1, v2)?);
-                }
-                Some("vt") => {
-                    let (t0, t1) = (words.next(), words.next());
-                    dat.texture.push(Self::parse_two(idx, t0, t1)?);
-                }
-                Some("vn") => {
-                    let (n0, n1, n2) = (words.next(), words.next(), words.next());
-                    dat.normal.push(Self::parse_three(idx, n0, n1, n2)?);
-                }
-                Some("f") => {
-                    let poly = self.parse_face(&mut dat, idx, &mut words)?;
-                    group = Some(match group {
-                        None => {
-                            let mut g = Group::new(DEFAULT_GROUP.to_string());
-                            g.polys.push(poly);
-                            g
-                        }
-                        Some(mut g) => {
-                            g.polys.push(poly);
-                            g
-                        }
+                self.dat.position.push(Vector3::zero());
+                self.weights.push(1.);
+                self.counts.position += 1;
+            }
+            "vt" => {
+                self.dat.texture.push(Vector2::zero());
+                self.counts.texture += 1;
+            }
+            "vn" => {
+                self.dat.normal.push(Vector3::zero());
+                self.counts.normal += 1;
+            }
+            _ => (),
+        }
+    }
+
+    fn parse_attribute<'b, I>(
+        &mut self,
+        idx: usize,
+        line: &str,
+        first: &str,
+        words: &mut I,
+    ) -> Result<(), ObjError>
+    where
+        I: Iterator<Item = &'b str>,
+    {
+        let dat = &mut self.dat;
+        match first {
+            "v" => {
+                let (v0, v1, v2) = (words.next(), words.next(), words.next());
+                let position = Self::parse_three(idx, v0, v1, v2)?;
+                // `v x y z [w]` or the vertex color extension `v x y z r g b [a]`
+                let mut extra = [0f32; 4];
+                let (w, color) = match Self::parse_array(idx, line, words, &mut extra)? {
+                    0 => (1., None),
+                    1 if extra[0] != 0. => (extra[0], None),
+                    3 => (1., Some(Vector4::new(extra[0], extra[1], extra[2], 1.))),
+                    4 => (1., Some(Vector4::from(extra))),
+                    _ => {
+                        return Err(ObjError::ArgumentListFailure {
+                            line_number: idx,
+                            list: line.to_string(),
+                        });
+                    }
+                };
+                match color {
+                    Some(color) => {
+                        dat.color.resize(dat.position.len(), DEFAULT_COLOR);
+                        dat.color.push(color);
+                    }
+                    None if !dat.color.is_empty() => dat.color.push(DEFAULT_COLOR),
+                    None => (),
+                }
+                dat.position.push(position);
+                self.weights.push(w);
+                self.counts.position += 1;
+            }
+            "vt" => {
+                let (t0, t1) = (words.next(), words.next());
+                dat.texture.push(Self::parse_two(idx, t0, t1)?);
+                self.counts.texture += 1;
+            }
+            "vn" => {
+                let (n0, n1, n2) = (words.next(), words.next(), words.next());
+                dat.normal.push(Self::parse_three(idx, n0, n1, n2)?);
+                self.counts.normal += 1;
+            }
+            _ => {
+                let mut values = [0f32; 3];
+                let param = match Self::parse_array(idx, line, words, &mut values)? {
+                    1 => Vector3::new(values[0], 0., 1.),
+                    2 => Vector3::new(values[0], values[1], 1.),
+                    3 => Vector3::from(values),
+                    _ => {
+                        return Err(ObjError::ArgumentListFailure {
+                            line_number: idx,
+                            list: line.to_string(),
+                        });
+                    }
+                };
+                dat.param.push(param);
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_element<'b, I>(
+        &mut self,
+        idx: usize,
+        line: &str,
+        first: &str,
+        words: &mut I,
+    ) -> Result<(), ObjError>
+    where
+        I: Iterator<Item = &'b str>,
+    {
+        match first {
+            "f" => {
+                let poly = self.parse_face(idx, line, words)?;
+                current_group(&mut self.group).polys.push(poly);
+            }
+            "l" => {
+                let line = self.parse_face(idx, line, words)?;
+                if line.len() < 2 {
+                    return Err(ObjError::ArgumentListFailure {
+                        line_number: idx,
+                        list: line.iter().map(|v| v.to_string()).collect(),
                     });
                 }
-                Some("o") => {
-                    group = match group {
-                        Some(val) => {
-                            object.groups.push(val);
-                            dat.objects.push(object);
-                            None
-                        }
-                        None => None,
-                    };
-                    object = if line.len() > 2 {
-                        let name = line[1..].trim();
-                        Object::new(name.to_string())
-                    } else {
-                        Object::new(DEFAULT_OBJECT.to_string())
-                    };
-                }
-                Some("g") => {
-                    object.groups.extend(group.take());
-
-                    if line.len() > 2 {
-                        let name = line[2..].trim();
-                        group = Some(Group::new(name.to_string()));
+                current_group(&mut self.group).lines.push(line);
+            }
+            "p" => {
+                let points = self.parse_face(idx, line, words)?;
+                current_group(&mut self.group)
+                    .points
+                    .extend(points.iter().map(|p| p.0));
+            }
+            "cstype" => {
+                let mut kind = words.next();
+                self.freeform.rational = kind == Some("rat");
+                if self.freeform.rational {
+                    kind = words.next();
+                }
+                self.freeform.kind = match kind {
+                    Some("bezier") => Some(CurveType::Bezier),
+                    Some("bspline") => Some(CurveType::BSpline),
+                    // bmatrix, cardinal & taylor bases are not supported
+                    Some(_) => None,
+                    None => {
+                        return Err(ObjError::ArgumentListFailure {
+                            line_number: idx,
+                            list: line.to_string(),
+                        });
                     }
+                };
+            }
+            "deg" => {
+                let du = Self::parse_degree(idx, words.next())?;
+                let dv = match words.next() {
+                    Some(dv) => Self::parse_degree(idx, Some(dv))?,
+                    None => 0,
+                };
+                self.freeform.degree = [du, dv];
+            }
+            "curv" => {
+                let (u0, u1) = (words.next(), words.next());
+                let range = Self::parse_two(idx, u0, u1)?;
+                let control = self.parse_face(idx, line, words)?;
+                self.freeform.element = Some(Element::Curve {
+                    range: range.into(),
+                    control,
+                });
+            }
+            "surf" => {
+                let (s0, s1) = (words.next(), words.next());
+                let s = Self::parse_two(idx, s0, s1)?;
+                let (t0, t1) = (words.next(), words.next());
+                let t = Self::parse_two(idx, t0, t1)?;
+                let control = self.parse_face(idx, line, words)?;
+                self.freeform.element = Some(Element::Surface {
+                    range: [s.x, s.y, t.x, t.y],
+                    control,
+                });
+            }
+            "parm" => {
+                let direction = match words.next() {
+                    Some("u") => 0,
+                    Some("v") => 1,
+                    _ => {
+                        return Err(ObjError::ArgumentListFailure {
+                            line_number: idx,
+                            list: line.to_string(),
+                        });
+                    }
+                };
+                self.freeform.parm[direction] = Self::parse_floats(idx, words)?;
+            }
+            "end" => {
+                if let Some(tessellation) = self.freeform.take() {
+                    current_group(&mut self.group);
+                    self.tessellations.push((
+                        self.dat.objects.len(),
+                        self.object.groups.len(),
+                        idx,
+                        tessellation,
+                    ));
+                }
+            }
+            "o" => {
+                // objects without any group are skipped, like the default one
+                // before the first `o`. Unlike the line based parser, the
+                // groups closed by a bare `g` are kept with their object.
+                self.object.groups.extend(self.group.take());
+                let object = if line.len() > 2 {
+                    let name = line[1..].trim();
+                    Object::new(name.to_string())
+                } else {
+                    Object::new(DEFAULT_OBJECT.to_string())
+                };
+                let object = mem::replace(&mut self.object, object);
+                if !object.groups.is_empty() {
+                    self.dat.objects.push(object);
+                }
+            }
+            "g" => {
+                self.object.groups.extend(self.group.take());
+
+                if line.len() > 2 {
+                    let name = line[2..].trim();
+                    self.group = Some(Group::new(name.to_string()));
+                }
+            }
+            "mtllib" => {
+                // Obj strictly does not allow spaces in filenames.
+                // "mtllib Some File.mtl" is forbidden.
+                // However, everyone does it anyway and if we want to ingest blender-outputted
+                // files, we need to support it. This works by walking word
+                // by word and combining them with a space in between. This may not be a totally
+                // accurate way to do it, but until the parser can be re-worked, this is
+                // good-enough, better-than-before solution.
+                let first_word = words
+                    .next()
+                    .ok_or_else(|| ObjError::MissingMTLName { line_number: idx })?
+                    .to_string();
+                let name = words.fold(first_word, |mut existing, next| {
+                    existing.push(' ');
+                    existing.push_str(next);
+                    existing
+                });
+                self.dat.material_libs.push(Mtl::new(name));
+            }
+            "usemtl" => {
+                let mut g = self
+                    .group
+                    .take()
+                    .unwrap_or_else(|| Group::new(DEFAULT_GROUP.to_string()));
+                // we found a new material that was applied to an existing
+                // object. It is treated as a new group.
+                if g.material.is_some() {
+                    let mut next = Group::new(g.name.clone());
+                    next.index = g.index + 1;
+                    self.object.groups.push(mem::replace(&mut g, next));
                 }
-                Some("mtllib") => {
-                    // Obj strictly does not allow spaces in filenames.
-                    // "mtllib Some File.mtl" is forbidden.
-                    // However, everyone does it anyway and if we want to ingest blender-outputted
-                    // files, we need to support it. This works by walking word
-                    // by word and combining them with a space in between. This may not be a totally
-                    // accurate way to do it, but until the parser can be re-worked, this is
-                    // good-enough, better-than-before solution.
-                    let first_word = words
-                        .next()
-                        .ok_or_else(|| ObjError::MissingMTLName { line_number: idx })?
-                        .to_string();
-                    let name = words.fold(first_word, |mut existing, next| {
-                        existing.push(' ');
-                        existing.push_str(next);
-                        existing
+                g.material = words.next().map(|w| ObjMaterial::Ref(w.to_string()));
+                self.group = Some(g);
+            }
+            "s" => (),
+            // trimming curves and the remaining free-form body statements
+            // (`curv2`, `trim`, `hole`, `scrv`, `sp`, ...) are ignored
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Assemble the model once every line has been parsed.
+    ///
+    /// Returns the model along with the diagnostics of the lines skipped in
+    /// lenient mode, sorted by line.
+    pub fn finish(mut self) -> Result<(MeshData, Vec<Diagnostic>), ObjError> {
+        self.object.groups.extend(self.group.take());
+        let mut dat = self.dat;
+        let mut diagnostics = self.diagnostics;
+        dat.objects.push(self.object);
+
+        for (object, group, line_number, tessellation) in self.tessellations {
+            let geometry = match tessellation.run(&mut dat, &self.weights) {
+                Some(geometry) => geometry,
+                None if self.lenient => {
+                    diagnostics.push(Diagnostic {
+                        line_number: line_number + 1,
+                        column: 1,
+                        kind: DiagnosticKind::MalformedFreeForm,
+                        text: String::from("end"),
                     });
-                    dat.material_libs.push(Mtl::new(name));
-                }
-                Some("usemtl") => {
-                    let mut g = group.unwrap_or_else(|| Group::new(DEFAULT_GROUP.to_string()));
-                    // we found a new material that was applied to an existing
-                    // object. It is treated as a new group.
-                    if g.material.is_some() {
-                        object.groups.push(g.clone());
-                        g.index += 1;
-                        g.polys.clear();
-                    }
-                    g.material = words.next().map(|w| ObjMaterial::Ref(w.to_string()));
-                    group = Some(g);
+                    continue;
                 }
-                Some("s") => (),
-                Some("l") => (),
-                Some(_) => (),
-                None => (),
+                None => return Err(ObjError::MalformedFreeForm { line_number }),
+            };
+            let group = &mut dat.objects[object].groups[group];
+            match geometry {
+                Geometry::Polyline(line) => group.lines.push(line),
+                Geometry::Triangles(triangles) => group.polys.extend(triangles),
             }
         }
 
-        if let Some(g) = group {
-            object.groups.push(g);
+        // polygonal elements use the vertices of the file as homogeneous
+        // coordinates, tessellated vertices already are
+        for (position, w) in dat.position.iter_mut().zip(&self.weights) {
+            if *w != 1. {
+                *position /= *w;
+            }
         }
 
-        dat.objects.push(object);
-        Ok(dat)
+        // tessellated free-form vertices have no color of their own
+        if !dat.color.is_empty() {
+            dat.color.resize(dat.position.len(), DEFAULT_COLOR);
+        }
+        diagnostics.sort_by_key(|d| d.line_number);
+        Ok((dat, diagnostics))
+    }
+
+    /// Parse a .obj file from a reader.
+    ///
+    /// Lines are read into a single reused buffer, so this is suitable for
+    /// streams like decompressed files that can not be held in memory.
+    pub fn load_buf<R: Read>(mut self, input: R) -> Result<(MeshData, Vec<Diagnostic>), ObjError> {
+        fn read_line<R: BufRead>(input: &mut R, line: &mut String) -> Result<usize, ObjError> {
+            input.read_line(line).map_err(|err| {
+                ObjError::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("failed to readline {}", err),
+                ))
+            })
+        }
+
+        let mut input = BufReader::new(input);
+        let mut line = String::new();
+        let mut line_number = 0;
+        loop {
+            line.clear();
+            if read_line(&mut input, &mut line)? == 0 {
+                break;
+            }
+            let idx = line_number;
+            line_number += 1;
+            while strip_continuation(&mut line) {
+                if read_line(&mut input, &mut line)? == 0 {
+                    break;
+                }
+                line_number += 1;
+            }
+            self.parse_line(idx, line.trim_end_matches(['\n', '\r']))?;
+        }
+        self.finish()
+    }
+
+    /// Parse a .obj file held in memory, e.g. a memory mapped file.
+    ///
+    /// Lines are borrowed from `bytes`, and vertex buffers are reserved
+    /// up-front from a quick scan of the file.
+    pub fn load_bytes(mut self, bytes: &[u8]) -> Result<(MeshData, Vec<Diagnostic>), ObjError> {
+        let text = to_str(bytes)?;
+        self.reserve(text);
+        for_each_line(text, 0, |idx, line| self.parse_line(idx, &line))?;
+        self.finish()
+    }
+
+    /// Like [`load_bytes`], but parses the vertex data of chunks of the file in
+    /// parallel.
+    ///
+    /// Statements depending on the parser state, like faces and groups, are
+    /// still applied in order once the vertex data of their chunk is known.
+    ///
+    /// [`load_bytes`]: #method.load_bytes
+    #[cfg(feature = "parallel")]
+    pub fn load_bytes_parallel(mut self, bytes: &[u8]) -> Result<(MeshData, Vec<Diagnostic>), ObjError> {
+        use rayon::prelude::*;
+
+        let text = to_str(bytes)?;
+        let chunks = split_chunks(text, rayon::current_num_threads() * 4);
+        let first_lines: Vec<usize> = chunks
+            .par_iter()
+            .map(|chunk| chunk.bytes().filter(|&b| b == b'\n').count())
+            .collect::<Vec<_>>()
+            .into_iter()
+            .scan(0, |first, count| {
+                let line = *first;
+                *first += count;
+                Some(line)
+            })
+            .collect();
+
+        let chunks: Vec<Result<Chunk, ObjError>> = chunks
+            .into_par_iter()
+            .zip(first_lines)
+            .map(|(chunk, first_line)| Chunk::parse(chunk, first_line, self.lenient))
+            .collect();
+        for chunk in chunks {
+            self.append(chunk?)?;
+        }
+        self.finish()
+    }
+
+    /// Append the vertex data of a chunk and apply its remaining statements.
+    #[cfg(feature = "parallel")]
+    fn append(&mut self, chunk: Chunk) -> Result<(), ObjError> {
+        let base = self.counts;
+        let mut attributes = chunk.attributes;
+
+        // fill in colors of vertices without one, if either side has colors
+        if !attributes.dat.color.is_empty() || !self.dat.color.is_empty() {
+            self.dat.color.resize(self.dat.position.len(), DEFAULT_COLOR);
+            let len = attributes.dat.position.len();
+            attributes.dat.color.resize(len, DEFAULT_COLOR);
+        }
+        self.dat.position.append(&mut attributes.dat.position);
+        self.dat.texture.append(&mut attributes.dat.texture);
+        self.dat.normal.append(&mut attributes.dat.normal);
+        self.dat.color.append(&mut attributes.dat.color);
+        self.dat.param.append(&mut attributes.dat.param);
+        self.weights.append(&mut attributes.weights);
+        self.diagnostics.append(&mut attributes.diagnostics);
+
+        for statement in chunk.statements {
+            match statement {
+                Statement::Face(poly) => current_group(&mut self.group).polys.push(poly),
+                Statement::Other(idx, line, counts) => {
+                    self.counts = base + counts;
+                    self.parse_line(idx, &line)?;
+                }
+            }
+        }
+        self.counts = base + attributes.counts;
+        Ok(())
+    }
+
+    /// Reserve the vertex buffers for the statements found in `text`.
+    fn reserve(&mut self, text: &str) {
+        let mut counts = Counts::default();
+        for line in text.lines() {
+            match line.as_bytes() {
+                [b'v', b' ', ..] => counts.position += 1,
+                [b'v', b't', ..] => counts.texture += 1,
+                [b'v', b'n', ..] => counts.normal += 1,
+                _ => (),
+            }
+        }
+        self.dat.position.reserve(counts.position);
+        self.weights.reserve(counts.position);
+        self.dat.texture.reserve(counts.texture);
+        self.dat.normal.reserve(counts.normal);
+    }
+}
+
+/// Split `text` into about `count` chunks of whole logical lines.
+#[cfg(feature = "parallel")]
+fn split_chunks(text: &str, count: usize) -> Vec<&str> {
+    let bytes = text.as_bytes();
+    let size = bytes.len() / count.max(1) + 1;
+    let mut chunks = Vec::with_capacity(count);
+    let mut start = 0;
+    while start < bytes.len() {
+        let mut end = (start + size).min(bytes.len());
+        // move the end past the next line break not continuing its line
+        while end < bytes.len() {
+            match bytes[end..].iter().position(|&b| b == b'\n') {
+                Some(i) => {
+                    end += i + 1;
+                    if !text[start..end].trim_end().ends_with('\\') {
+                        break;
+                    }
+                }
+                None => end = bytes.len(),
+            }
+        }
+        chunks.push(&text[start..end]);
+        start = end;
+    }
+    chunks
+}
+
+/// A statement of a chunk, to be applied once the vertex data of the previous
+/// chunks is known.
+#[cfg(feature = "parallel")]
+enum Statement<'a> {
+    /// A face only using absolute indices, which can be parsed in advance.
+    Face(SimplePolygon),
+    /// Any other statement, along with the vertex counts of the chunk preceding
+    /// it.
+    Other(usize, Cow<'a, str>, Counts),
+}
+
+/// Vertex data of a chunk of a file, along with its remaining statements.
+#[cfg(feature = "parallel")]
+struct Chunk<'a> {
+    attributes: ObjData,
+    statements: Vec<Statement<'a>>,
+}
+
+#[cfg(feature = "parallel")]
+impl<'a> Chunk<'a> {
+    fn parse(text: &'a str, first_line: usize, lenient: bool) -> Result<Self, ObjError> {
+        let mut attributes = ObjData::new();
+        attributes.lenient = lenient;
+        let mut statements = Vec::new();
+        attributes.reserve(text);
+        for_each_line(text, first_line, |idx, line| {
+            let mut words = line.split_ascii_whitespace();
+            match words.next() {
+                Some(first) if is_attribute(first) => attributes.parse_line(idx, &line)?,
+                // clamping indices needs the vertex counts of the previous
+                // chunks
+                Some("f") if !lenient && !line.contains('-') => {
+                    statements.push(Statement::Face(attributes.parse_face(idx, &line, &mut words)?))
+                }
+                Some(_) => statements.push(Statement::Other(idx, line, attributes.counts)),
+                None => (),
+            }
+            Ok(())
+        })?;
+        Ok(Chunk {
+            attributes,
+            statements,
+        })
     }
 }
 
 impl MeshParser for ObjData {
-    fn parse_mesh_data<R: Read>(input: R) -> Result<MeshData, ObjError> {
-        let mut obj_data = ObjData{};
-        obj_data.load_buf(input)
+    fn parse_mesh_data_with<R: Read>(
+        input: R,
+        options: &LoadOptions,
+    ) -> Result<(MeshData, Vec<Diagnostic>), ObjError> {
+        ObjData::with_options(options).load_buf(input)
     }
 }
+
+#[cfg(test)]
+fn parse(input: &str) -> MeshData {
+    ObjData::new().load_buf(input.as_bytes()).unwrap().0
+}
+
+#[test]
+fn test_line_continuation() {
+    let dat = parse("v 1 2 \\\n 3\nv 4 5 6\nv 7 8 9\nf 1 \\\n2 \\\n3\n");
+    assert_eq!(dat.position[0], Vector3::new(1., 2., 3.));
+    assert_eq!(dat.position.len(), 3);
+    assert_eq!(
+        dat.objects[0].groups[0].polys[0],
+        vec![IndexTuple(0, None, None), IndexTuple(1, None, None), IndexTuple(2, None, None)]
+    );
+}
+
+#[test]
+fn test_vertex_colors() {
+    let dat = parse("v 0 0 0 1 0 0\nv 1 0 0 0 1 0 0.5\nv 0 1 0\n");
+    assert_eq!(dat.position[1], Vector3::new(1., 0., 0.));
+    assert_eq!(
+        dat.color,
+        vec![
+            Vector4::new(1., 0., 0., 1.),
+            Vector4::new(0., 1., 0., 0.5),
+            DEFAULT_COLOR,
+        ]
+    );
+
+    // vertices before the first colored one get the default color
+    let dat = parse("v 0 0 0\nv 1 0 0 0 0 1\n");
+    assert_eq!(dat.color, vec![DEFAULT_COLOR, Vector4::new(0., 0., 1., 1.)]);
+
+    // no color channel without colored vertices
+    assert!(parse("v 0 0 0\n").color.is_empty());
+}
+
+#[test]
+fn test_homogeneous_vertices() {
+    let dat = parse("v 2 4 6 2\nv 1 2 3 1\n");
+    assert_eq!(dat.position, vec![Vector3::new(1., 2., 3.); 2]);
+    assert!(ObjData::new().load_buf("v 1 2 3 0\n".as_bytes()).is_err());
+}
+
+#[test]
+fn test_rational_freeform() {
+    use cgmath::InnerSpace;
+    use std::f32::consts::SQRT_2;
+
+    // a quarter of the unit circle, whose middle control point is weighted
+    // by cos(45°) but keeps its Euclidean coordinates
+    let input = "v 1 0 0\nv 1 1 0 0.70710678\nv 0 1 0\ncstype rat bezier\ndeg 2\n\
+                 curv 0 1 1 2 3\nparm u 0 1\nend\nf 1 2 3\n";
+    let dat = parse(input);
+    let curve = &dat.objects[0].groups[0].lines[0];
+    assert_eq!(curve.len(), 9);
+    for &IndexTuple(p, ..) in curve {
+        let radius = dat.position[p].magnitude();
+        assert!((radius - 1.).abs() < 1e-5, "{:?}", dat.position[p]);
+    }
+
+    // the vertices of the file are still homogeneous for polygonal elements
+    let middle = Vector3::new(SQRT_2, SQRT_2, 0.);
+    assert!((dat.position[1] - middle).magnitude() < 1e-5);
+}
+
+#[test]
+fn test_load_bytes_matches_load_buf() {
+    let input = "mtllib a.mtl\no cube\nv 0 0 0\nv 1 0 0 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\n\
+                 vn 0 0 1\ng front\nusemtl red\nf 1/1/1 2/1/1 3/1/1\nusemtl blue\nf -4 -2 \\\n-1\n\
+                 cstype bezier\ndeg 1\ncurv 0 1 1 2\nparm u 0 1\nend\nv 2 2 2\nl 1 5\n";
+    let dat = parse(input);
+    assert_eq!(ObjData::new().load_bytes(input.as_bytes()).unwrap().0, dat);
+
+    // tessellated vertices come after the ones of the file
+    let group = &dat.objects[0].groups[1];
+    assert_eq!(dat.position[4], Vector3::new(2., 2., 2.));
+    assert_eq!(group.lines[0], vec![IndexTuple(0, None, None), IndexTuple(4, None, None)]);
+    assert_eq!(group.lines[1][0], IndexTuple(5, None, None));
+    assert_eq!(group.polys[0].len(), 3);
+}
+
+#[test]
+fn test_freeform_indices() {
+    let input = "v 0 0 0\nv 1 1 0\nv 2 0 0\nv 2 1 0\ncstype bezier\ndeg 2\ncurv 0 1 1 2 3\n\
+                 parm u 0 1\nend\ndeg 1 1\nsurf 0 1 0 1 1 3 2 4\nparm u 0 1\nparm v 0 1\nend\n\
+                 v 0 0 1\nv 1 0 1\nv 0 1 1\nf 5 6 7\nf -3 -2 -1\nl 1 7\n";
+    let dat = parse(input);
+
+    // 9 samples of the curve and 9 x 9 of the surface, after the 7 vertices
+    // of the file
+    assert_eq!(dat.position.len(), 7 + 9 + 9 * 9);
+    assert_eq!(dat.position[4], Vector3::new(0., 0., 1.));
+    assert_eq!(dat.position[6], Vector3::new(0., 1., 1.));
+
+    let group = &dat.objects[0].groups[0];
+    let face: Vec<_> = (4..7).map(|i| IndexTuple(i, None, None)).collect();
+    assert_eq!(group.polys[0], face);
+    assert_eq!(group.polys[1], face);
+    assert_eq!(group.polys.len(), 2 + 2 * 8 * 8);
+    assert_eq!(group.lines[0], vec![IndexTuple(0, None, None), IndexTuple(6, None, None)]);
+    let curve: Vec<_> = (7..16).map(|i| IndexTuple(i, None, None)).collect();
+    assert_eq!(group.lines[1], curve);
+    assert_eq!(group.polys[2][0], IndexTuple(16, None, None));
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn test_load_bytes_parallel() {
+    let input = bench_input(64);
+    assert_eq!(
+        ObjData::new().load_bytes_parallel(input.as_bytes()).unwrap().0,
+        parse(&input)
+    );
+}
+
+/// A `size` x `size` grid of quads with texture coordinates and normals.
+#[cfg(test)]
+fn bench_input(size: usize) -> String {
+    let mut input = String::from("o grid\n");
+    for j in 0..=size {
+        for i in 0..=size {
+            let (u, v) = (i as f32 / size as f32, j as f32 / size as f32);
+            input.push_str(&format!("v {:.6} {:.6} 0.000000\nvt {:.6} {:.6}\n", u, v, u, v));
+        }
+    }
+    input.push_str("vn 0 0 1\ng grid\nusemtl default\n");
+    for j in 0..size {
+        for i in 0..size {
+            let a = j * (size + 1) + i + 1;
+            let (b, c, d) = (a + 1, a + size + 2, a + size + 1);
+            input.push_str(&format!(
+                "f {}/{}/1 {}/{}/1 {}/{}/1 {}/{}/1\n",
+                a, a, b, b, c, c, d, d
+            ));
+        }
+    }
+    input
+}
+
+#[bench]
+fn bench_load_buf(b: &mut Bencher) {
+    let input = bench_input(256);
+    b.bytes = input.len() as u64;
+    b.iter(|| ObjData::new().load_buf(input.as_bytes()).unwrap());
+}
+
+#[bench]
+fn bench_load_bytes(b: &mut Bencher) {
+    let input = bench_input(256);
+    b.bytes = input.len() as u64;
+    b.iter(|| ObjData::new().load_bytes(input.as_bytes()).unwrap());
+}
+
+/// The line based parser this one replaced, on the same file.
+#[bench]
+fn bench_legacy_load_buf(b: &mut Bencher) {
+    let input = bench_input(256);
+    b.bytes = input.len() as u64;
+    b.iter(|| legacy::ObjData::new().load_buf(input.as_bytes()).unwrap());
+}
+
+#[test]
+fn test_legacy_parser() {
+    let input = bench_input(16);
+    let dat = ObjData::new().load_buf(input.as_bytes()).unwrap().0;
+    assert_eq!(legacy::ObjData::new().load_buf(input.as_bytes()).unwrap(), dat);
+}
+
+#[test]
+fn test_objects() {
+    let input = "v 0 0 0\nv 1 0 0\nv 0 1 0\no a\ng x\nf 1 2 3\ng\no empty\no b\nf 1 2 3\n";
+    let names = |dat: &MeshData| -> Vec<(String, Vec<String>)> {
+        dat.objects
+            .iter()
+            .map(|o| (o.name.clone(), o.groups.iter().map(|g| g.name.clone()).collect()))
+            .collect()
+    };
+    let dat = ObjData::new().load_buf(input.as_bytes()).unwrap().0;
+    let expected = vec![
+        ("a".to_string(), vec!["x".to_string()]),
+        ("b".to_string(), vec![DEFAULT_GROUP.to_string()]),
+    ];
+    assert_eq!(names(&dat), expected);
+    assert_eq!(ObjData::new().load_bytes(input.as_bytes()).unwrap().0, dat);
+    // the line based parser dropped the object, and the faces of its groups
+    let legacy = legacy::ObjData::new().load_buf(input.as_bytes()).unwrap();
+    assert_eq!(names(&legacy), expected[1..]);
+}
+
+#[cfg(feature = "parallel")]
+#[bench]
+fn bench_load_bytes_parallel(b: &mut Bencher) {
+    let input = bench_input(256);
+    b.bytes = input.len() as u64;
+    b.iter(|| ObjData::new().load_bytes_parallel(input.as_bytes()).unwrap());
+}
+
+#[test]
+fn test_lenient_mode() {
+    let input = "v 0 0 0\nv 1 x 0\nv 0 1 0\nf 1 2 3\nf 1 2 9\nf 1 a 3\nmtllib\n";
+    // strict mode stops at the first error, on a line numbered from 0
+    let strict = |result: Result<(MeshData, Vec<Diagnostic>), ObjError>| match result {
+        Err(ObjError::ArgumentListFailure { line_number, .. }) => line_number,
+        result => panic!("{:?}", result.map(|_| ())),
+    };
+    assert_eq!(strict(ObjData::new().load_buf(input.as_bytes())), 1);
+    assert_eq!(strict(ObjData::new().load_bytes(input.as_bytes())), 1);
+
+    let options = LoadOptions {
+        lenient: true,
+        ..LoadOptions::default()
+    };
+    let (dat, diagnostics) = ObjData::parse_mesh_data_with(input.as_bytes(), &options).unwrap();
+    assert_eq!(dat.position[1], Vector3::zero());
+    assert_eq!(dat.position[2], Vector3::new(0., 1., 0.));
+
+    let polys = &dat.objects[0].groups[0].polys;
+    assert_eq!(polys.len(), 2);
+    assert_eq!(polys[1][2], IndexTuple(2, None, None));
+
+    let kinds: Vec<_> = diagnostics.iter().map(|d| (d.line_number, d.column, d.kind)).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            (2, 5, DiagnosticKind::MalformedVertex),
+            (5, 7, DiagnosticKind::IndexClamped),
+            (6, 5, DiagnosticKind::MalformedElement),
+            (7, 7, DiagnosticKind::MalformedStatement),
+        ]
+    );
+    assert_eq!(diagnostics[2].text, "f 1 a 3");
+    assert_eq!(diagnostics[0].to_string(), "2:5: MalformedVertex: v 1 x 0");
+}
@@ -0,0 +1,422 @@
+// the line based .obj parser replaced by the streaming one of the parent
+// module, kept as the baseline of its benchmarks
+
+use std::io::{self, BufRead, BufReader, Read};
+use std::str::FromStr;
+
+use cgmath::{Vector2, Vector3, Vector4};
+
+use super::super::freeform::{CurveType, Element, FreeForm, Geometry};
+use super::super::{Group, IndexTuple, MeshData, Object, SimplePolygon};
+use super::{current_group, normalize, Mtl, ObjError, ObjMaterial};
+use super::{DEFAULT_COLOR, DEFAULT_GROUP, DEFAULT_OBJECT};
+
+pub struct ObjData {
+    /// Weight of each vertex position, used by rational free-form elements.
+    weights: Vec<f32>,
+    freeform: FreeForm,
+}
+
+impl ObjData {
+    pub fn new() -> Self {
+        ObjData {
+            weights: Vec::new(),
+            freeform: FreeForm::default(),
+        }
+    }
+
+    fn parse_two(
+        line_number: usize,
+        n0: Option<&str>,
+        n1: Option<&str>,
+    ) -> Result<Vector2<f32>, ObjError> {
+        let (n0, n1) = match (n0, n1) {
+            (Some(n0), Some(n1)) => (n0, n1),
+            _ => {
+                return Err(ObjError::ArgumentListFailure {
+                    line_number,
+                    list: format!("{:?} {:?}", n0, n1),
+                });
+            }
+        };
+        let normal = match (FromStr::from_str(n0), FromStr::from_str(n1)) {
+            (Ok(n0), Ok(n1)) => Vector2::new(n0, n1),
+            _ => {
+                return Err(ObjError::ArgumentListFailure {
+                    line_number,
+                    list: format!("{:?} {:?}", n0, n1),
+                });
+            }
+        };
+        Ok(normal)
+    }
+
+    fn parse_three(
+        line_number: usize,
+        n0: Option<&str>,
+        n1: Option<&str>,
+        n2: Option<&str>,
+    ) -> Result<Vector3<f32>, ObjError> {
+        let (n0, n1, n2) = match (n0, n1, n2) {
+            (Some(n0), Some(n1), Some(n2)) => (n0, n1, n2),
+            _ => {
+                return Err(ObjError::ArgumentListFailure {
+                    line_number,
+                    list: format!("{:?} {:?} {:?}", n0, n1, n2),
+                });
+            }
+        };
+        let normal = match (
+            FromStr::from_str(n0),
+            FromStr::from_str(n1),
+            FromStr::from_str(n2),
+        ) {
+            (Ok(n0), Ok(n1), Ok(n2)) => Vector3::new(n0, n1, n2),
+            _ => {
+                return Err(ObjError::ArgumentListFailure {
+                    line_number,
+                    list: format!("{:?} {:?} {:?}", n0, n1, n2),
+                });
+            }
+        };
+        Ok(normal)
+    }
+
+    fn parse_group(
+        &self,
+        mesh_data: &mut MeshData,
+        line_number: usize,
+        group: &str,
+    ) -> Result<IndexTuple, ObjError> {
+        let mut group_split = group.split('/');
+        let p: Option<isize> = group_split
+            .next()
+            .and_then(|idx| FromStr::from_str(idx).ok());
+        let t: Option<isize> = group_split.next().and_then(|idx| {
+            if !idx.is_empty() {
+                FromStr::from_str(idx).ok()
+            } else {
+                None
+            }
+        });
+        let n: Option<isize> = group_split
+            .next()
+            .and_then(|idx| FromStr::from_str(idx).ok());
+
+        let p = p.and_then(|p| normalize(p, mesh_data.position.len()));
+        let t = t.map(|t| normalize(t, mesh_data.texture.len()));
+        let n = n.map(|n| normalize(n, mesh_data.normal.len()));
+        match (p, t, n) {
+            (Some(p), None, None) => Ok(IndexTuple(p, None, None)),
+            (Some(p), Some(Some(t)), None) => Ok(IndexTuple(p, Some(t), None)),
+            (Some(p), None, Some(Some(n))) => Ok(IndexTuple(p, None, Some(n))),
+            (Some(p), Some(Some(t)), Some(Some(n))) => Ok(IndexTuple(p, Some(t), Some(n))),
+            _ => Err(ObjError::MalformedFaceGroup {
+                line_number,
+                group: String::from(group),
+            }),
+        }
+    }
+
+    fn parse_face<'b, I>(
+        &self,
+        mesh_data: &mut MeshData,
+        line_number: usize,
+        groups: &mut I,
+    ) -> Result<SimplePolygon, ObjError>
+    where
+        I: Iterator<Item = &'b str>,
+    {
+        let mut ret = Vec::with_capacity(4);
+        for g in groups {
+            let ituple = self.parse_group(mesh_data, line_number, g)?;
+            ret.push(ituple);
+        }
+        Ok(ret)
+    }
+
+    fn parse_floats<'b, I>(line_number: usize, words: &mut I) -> Result<Vec<f32>, ObjError>
+    where
+        I: Iterator<Item = &'b str>,
+    {
+        words
+            .map(|w| {
+                FromStr::from_str(w).map_err(|_| ObjError::ArgumentListFailure {
+                    line_number,
+                    list: w.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    fn parse_degree(line_number: usize, word: Option<&str>) -> Result<usize, ObjError> {
+        word.and_then(|w| FromStr::from_str(w).ok())
+            .ok_or_else(|| ObjError::ArgumentListFailure {
+                line_number,
+                list: format!("{:?}", word),
+            })
+    }
+
+    pub fn load_buf<R: Read>(&mut self, input: R) -> Result<MeshData, ObjError> {
+        let input = BufReader::new(input);
+        let mut dat = MeshData::default();
+        let mut object = Object::new(DEFAULT_OBJECT.to_string());
+        let mut group: Option<Group> = None;
+
+        let mut lines = input.lines().enumerate();
+        while let Some((idx, line)) = lines.next() {
+            let line = match Self::join_continuations(line, &mut lines) {
+                Ok(line) => line,
+                Err(err) => {
+                    return Err(ObjError::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("failed to readline {}", err),
+                    )));
+                }
+            };
+            let mut words = line.split_whitespace().filter(|s| !s.is_empty());
+            let first = words.next();
+
+            match first {
+                Some("v") => {
+                    let (v0, v1, v2) = (words.next(), words.next(), words.next());
+                    let position = Self::parse_three(idx, v0, v1, v2)?;
+                    // `v x y z [w]` or the vertex color extension `v x y z r g b [a]`
+                    let (w, color) = match Self::parse_floats(idx, &mut words)?[..] {
+                        [] => (1., None),
+                        [w] if w != 0. => (w, None),
+                        [r, g, b] => (1., Some(Vector4::new(r, g, b, 1.))),
+                        [r, g, b, a] => (1., Some(Vector4::new(r, g, b, a))),
+                        _ => {
+                            return Err(ObjError::ArgumentListFailure {
+                                line_number: idx,
+                                list: line.clone(),
+                            });
+                        }
+                    };
+                    match color {
+                        Some(color) => {
+                            dat.color.resize(dat.position.len(), DEFAULT_COLOR);
+                            dat.color.push(color);
+                        }
+                        None if !dat.color.is_empty() => dat.color.push(DEFAULT_COLOR),
+                        None => (),
+                    }
+                    dat.position.push(position / w);
+                    self.weights.push(w);
+                }
+                Some("vt") => {
+                    let (t0, t1) = (words.next(), words.next());
+                    dat.texture.push(Self::parse_two(idx, t0, t1)?);
+                }
+                Some("vn") => {
+                    let (n0, n1, n2) = (words.next(), words.next(), words.next());
+                    dat.normal.push(Self::parse_three(idx, n0, n1, n2)?);
+                }
+                Some("vp") => {
+                    let values = Self::parse_floats(idx, &mut words)?;
+                    let param = match values[..] {
+                        [u] => Vector3::new(u, 0., 1.),
+                        [u, v] => Vector3::new(u, v, 1.),
+                        [u, v, w] => Vector3::new(u, v, w),
+                        _ => {
+                            return Err(ObjError::ArgumentListFailure {
+                                line_number: idx,
+                                list: format!("{:?}", values),
+                            });
+                        }
+                    };
+                    dat.param.push(param);
+                }
+                Some("f") => {
+                    let poly = self.parse_face(&mut dat, idx, &mut words)?;
+                    current_group(&mut group).polys.push(poly);
+                }
+                Some("l") => {
+                    let line = self.parse_face(&mut dat, idx, &mut words)?;
+                    if line.len() < 2 {
+                        return Err(ObjError::ArgumentListFailure {
+                            line_number: idx,
+                            list: line.iter().map(|v| v.to_string()).collect(),
+                        });
+                    }
+                    current_group(&mut group).lines.push(line);
+                }
+                Some("p") => {
+                    let points = self.parse_face(&mut dat, idx, &mut words)?;
+                    current_group(&mut group)
+                        .points
+                        .extend(points.iter().map(|p| p.0));
+                }
+                Some("cstype") => {
+                    let mut kind = words.next();
+                    self.freeform.rational = kind == Some("rat");
+                    if self.freeform.rational {
+                        kind = words.next();
+                    }
+                    self.freeform.kind = match kind {
+                        Some("bezier") => Some(CurveType::Bezier),
+                        Some("bspline") => Some(CurveType::BSpline),
+                        // bmatrix, cardinal & taylor bases are not supported
+                        Some(_) => None,
+                        None => {
+                            return Err(ObjError::ArgumentListFailure {
+                                line_number: idx,
+                                list: line.clone(),
+                            });
+                        }
+                    };
+                }
+                Some("deg") => {
+                    let du = Self::parse_degree(idx, words.next())?;
+                    let dv = match words.next() {
+                        Some(dv) => Self::parse_degree(idx, Some(dv))?,
+                        None => 0,
+                    };
+                    self.freeform.degree = [du, dv];
+                }
+                Some("curv") => {
+                    let (u0, u1) = (words.next(), words.next());
+                    let range = Self::parse_two(idx, u0, u1)?;
+                    let control = self.parse_face(&mut dat, idx, &mut words)?;
+                    self.freeform.element = Some(Element::Curve {
+                        range: range.into(),
+                        control,
+                    });
+                }
+                Some("surf") => {
+                    let (s0, s1) = (words.next(), words.next());
+                    let s = Self::parse_two(idx, s0, s1)?;
+                    let (t0, t1) = (words.next(), words.next());
+                    let t = Self::parse_two(idx, t0, t1)?;
+                    let control = self.parse_face(&mut dat, idx, &mut words)?;
+                    self.freeform.element = Some(Element::Surface {
+                        range: [s.x, s.y, t.x, t.y],
+                        control,
+                    });
+                }
+                Some("parm") => {
+                    let direction = match words.next() {
+                        Some("u") => 0,
+                        Some("v") => 1,
+                        _ => {
+                            return Err(ObjError::ArgumentListFailure {
+                                line_number: idx,
+                                list: line.clone(),
+                            });
+                        }
+                    };
+                    self.freeform.parm[direction] = Self::parse_floats(idx, &mut words)?;
+                }
+                Some("end") => {
+                    // tessellated in place, which shifts the indices of the
+                    // vertices following the element
+                    let geometry = self
+                        .freeform
+                        .take()
+                        .map(|tessellation| tessellation.run(&mut dat, &self.weights));
+                    let g = current_group(&mut group);
+                    match geometry {
+                        Some(Some(Geometry::Polyline(line))) => g.lines.push(line),
+                        Some(Some(Geometry::Triangles(triangles))) => g.polys.extend(triangles),
+                        Some(None) => {
+                            return Err(ObjError::MalformedFreeForm { line_number: idx });
+                        }
+                        None => (),
+                    }
+                }
+                Some("o") => {
+                    group = match group {
+                        Some(val) => {
+                            object.groups.push(val);
+                            dat.objects.push(object);
+                            None
+                        }
+                        None => None,
+                    };
+                    object = if line.len() > 2 {
+                        let name = line[1..].trim();
+                        Object::new(name.to_string())
+                    } else {
+                        Object::new(DEFAULT_OBJECT.to_string())
+                    };
+                }
+                Some("g") => {
+                    object.groups.extend(group.take());
+
+                    if line.len() > 2 {
+                        let name = line[2..].trim();
+                        group = Some(Group::new(name.to_string()));
+                    }
+                }
+                Some("mtllib") => {
+                    // Obj strictly does not allow spaces in filenames.
+                    // "mtllib Some File.mtl" is forbidden.
+                    // However, everyone does it anyway and if we want to ingest blender-outputted
+                    // files, we need to support it. This works by walking word
+                    // by word and combining them with a space in between. This may not be a totally
+                    // accurate way to do it, but until the parser can be re-worked, this is
+                    // good-enough, better-than-before solution.
+                    let first_word = words
+                        .next()
+                        .ok_or(ObjError::MissingMTLName { line_number: idx })?
+                        .to_string();
+                    let name = words.fold(first_word, |mut existing, next| {
+                        existing.push(' ');
+                        existing.push_str(next);
+                        existing
+                    });
+                    dat.material_libs.push(Mtl::new(name));
+                }
+                Some("usemtl") => {
+                    let mut g = group.unwrap_or_else(|| Group::new(DEFAULT_GROUP.to_string()));
+                    // we found a new material that was applied to an existing
+                    // object. It is treated as a new group.
+                    if g.material.is_some() {
+                        object.groups.push(g.clone());
+                        g.index += 1;
+                        g.polys.clear();
+                        g.lines.clear();
+                        g.points.clear();
+                    }
+                    g.material = words.next().map(|w| ObjMaterial::Ref(w.to_string()));
+                    group = Some(g);
+                }
+                Some("s") => (),
+                // trimming curves and the remaining free-form body statements
+                // (`curv2`, `trim`, `hole`, `scrv`, `sp`, ...) are ignored
+                Some(_) => (),
+                None => (),
+            }
+        }
+
+        if let Some(g) = group {
+            object.groups.push(g);
+        }
+
+        dat.objects.push(object);
+        // tessellated free-form vertices have no color of their own
+        if !dat.color.is_empty() {
+            dat.color.resize(dat.position.len(), DEFAULT_COLOR);
+        }
+        Ok(dat)
+    }
+
+    /// Join a physical line ending in `\` with the lines following it.
+    fn join_continuations<I>(line: io::Result<String>, lines: &mut I) -> io::Result<String>
+    where
+        I: Iterator<Item = (usize, io::Result<String>)>,
+    {
+        let mut line = line?;
+        while line.trim_end().ends_with('\\') {
+            let len = line.trim_end().len() - 1;
+            line.truncate(len);
+            line.push(' ');
+            match lines.next() {
+                Some((_, next)) => line.push_str(&next?),
+                None => break,
+            }
+        }
+        Ok(line)
+    }
+}
@@ -0,0 +1,96 @@
+use super::{texel, Frame, PostEffect};
+
+/// Glow around the pixels brighter than a threshold, applied before tone
+/// mapping.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bloom {
+    /// Brightness over which the pixels glow, before exposure.
+    pub threshold: f32,
+    /// Brightness of the glow added to the pixels.
+    pub intensity: f32,
+    /// Distance the glow spreads to, in pixels.
+    pub radius: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Bloom {
+            threshold: 1.,
+            intensity: 0.5,
+            radius: 8.,
+        }
+    }
+}
+
+/// Blur the RGB channels of `colors` with a gaussian of standard deviation
+/// `sigma`, horizontally then vertically.
+fn gaussian_blur(colors: &mut [f32], width: usize, height: usize, sigma: f32) {
+    let extent = (3. * sigma).ceil() as isize;
+    let weights: Vec<f32> = (-extent..=extent)
+        .map(|d| (-(d * d) as f32 / (2. * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    for &(dx, dy) in &[(1, 0), (0, 1)] {
+        let source = colors.to_vec();
+        for (i, pixel) in colors.chunks_exact_mut(4).enumerate() {
+            let (x, y) = ((i % width) as isize, (i / width) as isize);
+            let mut sum = [0.; 3];
+            for (d, weight) in (-extent..=extent).zip(&weights) {
+                let texel = texel(&source, width, height, x + d * dx, y + d * dy);
+                for c in 0..3 {
+                    sum[c] += texel[c] * weight;
+                }
+            }
+            for c in 0..3 {
+                pixel[c] = sum[c] / total;
+            }
+        }
+    }
+}
+
+impl PostEffect for Bloom {
+    fn before_tone_mapping(&self) -> bool {
+        true
+    }
+
+    fn apply(&mut self, frame: &mut Frame) {
+        let mut glow: Vec<f32> = frame
+            .colors
+            .iter()
+            .map(|&value| (value - self.threshold).max(0.))
+            .collect();
+        if self.radius > 0. {
+            // most of the gaussian is within 3 standard deviations
+            gaussian_blur(&mut glow, frame.width, frame.height, self.radius / 3.);
+        }
+        for (pixel, glow) in frame.colors.chunks_exact_mut(4).zip(glow.chunks_exact(4)) {
+            for c in 0..3 {
+                pixel[c] += glow[c] * self.intensity;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_bloom() {
+    let (mut colors, depth) = super::test_frame();
+    colors[4 * 12..4 * 12 + 3].copy_from_slice(&[3.; 3]);
+    let mut frame = Frame {
+        colors: &mut colors,
+        depth: &depth,
+        width: 5,
+        height: 5,
+    };
+    let mut bloom = Bloom {
+        radius: 3.,
+        ..Bloom::default()
+    };
+    assert!(bloom.before_tone_mapping());
+    bloom.apply(&mut frame);
+    // the bright pixel glows on its neighbors, less and less far from it
+    let red = |x: usize, y: usize| colors[4 * (y * 5 + x)];
+    assert!(red(2, 2) > 3.);
+    assert!(red(1, 2) > red(0, 2) && red(0, 2) > 0.);
+    assert_eq!(red(1, 2), red(2, 1));
+    assert_eq!(colors[4 * 11 + 3], 1.);
+}
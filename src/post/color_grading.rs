@@ -0,0 +1,288 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use super::{Frame, PostEffect};
+use crate::renderer::{linear_to_srgb, srgb_to_linear};
+
+/// 3D color lookup table, as read from `.cube` files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lut3d {
+    /// Number of entries along every axis.
+    pub size: usize,
+    /// Colors of the entries, red changing the fastest, then green, then
+    /// blue.
+    pub table: Vec<[f32; 3]>,
+    /// Input colors mapped to the first and the last entries.
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+}
+
+/// Errors parsing or loading a .cube file.
+#[derive(Debug)]
+pub enum LutError {
+    Io(io::Error),
+    /// A keyword is unknown or has malformed arguments, or an entry is
+    /// malformed.
+    InvalidLine {
+        line_number: usize,
+        line: String,
+    },
+    /// `LUT_3D_SIZE` is missing, or the file only has a 1D table.
+    MissingSize,
+    /// The number of entries doesn't match `LUT_3D_SIZE`.
+    WrongEntryCount {
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl std::error::Error for LutError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LutError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LutError::Io(err) => write!(f, "I/O error loading a .cube file: {}", err),
+            LutError::InvalidLine { line_number, line } => {
+                write!(
+                    f,
+                    "invalid line in a .cube file (line: {}, {})",
+                    line_number, line
+                )
+            }
+            LutError::MissingSize => write!(f, "no 3D table size in the .cube file"),
+            LutError::WrongEntryCount { expected, found } => write!(
+                f,
+                "the .cube file has {} entries instead of {}",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl From<io::Error> for LutError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl Lut3d {
+    /// Table leaving the colors as they are.
+    pub fn identity(size: usize) -> Self {
+        let step = 1. / (size - 1).max(1) as f32;
+        let table = (0..size * size * size)
+            .map(|i| {
+                let (r, g, b) = (i % size, i / size % size, i / (size * size));
+                [r as f32 * step, g as f32 * step, b as f32 * step]
+            })
+            .collect();
+        Lut3d {
+            size,
+            table,
+            domain_min: [0.; 3],
+            domain_max: [1.; 3],
+        }
+    }
+
+    /// Load a `.cube` file from the given path.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LutError> {
+        Self::parse(BufReader::new(File::open(path)?))
+    }
+
+    /// Parse the content of a `.cube` file, in the format of Adobe and
+    /// Resolve.
+    ///
+    /// The 1D shaper tables of Resolve are not supported.
+    pub fn parse<R: BufRead>(reader: R) -> Result<Self, LutError> {
+        let mut size = None;
+        let mut lut = Lut3d {
+            size: 0,
+            table: Vec::new(),
+            domain_min: [0.; 3],
+            domain_max: [1.; 3],
+        };
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let invalid = || LutError::InvalidLine {
+                line_number: i + 1,
+                line: line.clone(),
+            };
+            let mut words = line.split_whitespace();
+            let floats = |words: std::str::SplitWhitespace| -> Result<Vec<f32>, LutError> {
+                words
+                    .map(|word| word.parse::<f32>().map_err(|_| invalid()))
+                    .collect()
+            };
+            let numbers = |words| -> Result<[f32; 3], LutError> {
+                floats(words)?.try_into().map_err(|_| invalid())
+            };
+            match words.next() {
+                None => (),
+                Some(word) if word.starts_with('#') => (),
+                Some("TITLE") => (),
+                Some("LUT_1D_SIZE") => return Err(LutError::MissingSize),
+                Some("LUT_3D_SIZE") => {
+                    let value = words.next().and_then(|word| word.parse().ok());
+                    size = Some(value.filter(|&n: &usize| n >= 2).ok_or_else(invalid)?);
+                }
+                Some("DOMAIN_MIN") => lut.domain_min = numbers(words)?,
+                Some("DOMAIN_MAX") => lut.domain_max = numbers(words)?,
+                // the same domain for every channel, in the format of Resolve
+                Some("LUT_3D_INPUT_RANGE") => match floats(words)?[..] {
+                    [min, max] => {
+                        lut.domain_min = [min; 3];
+                        lut.domain_max = [max; 3];
+                    }
+                    _ => return Err(invalid()),
+                },
+                Some("LUT_1D_INPUT_RANGE") => (),
+                Some(_) => lut.table.push(numbers(line.split_whitespace())?),
+            }
+        }
+
+        lut.size = size.ok_or(LutError::MissingSize)?;
+        let expected = lut.size.pow(3);
+        if lut.table.len() != expected {
+            return Err(LutError::WrongEntryCount {
+                expected,
+                found: lut.table.len(),
+            });
+        }
+        Ok(lut)
+    }
+
+    /// Color mapped to the given one, interpolated between the 8 entries
+    /// around it.
+    pub fn lookup(&self, color: [f32; 3]) -> [f32; 3] {
+        let last = (self.size - 1) as f32;
+        let mut base = [0; 3];
+        let mut fract = [0.; 3];
+        for c in 0..3 {
+            let range = self.domain_max[c] - self.domain_min[c];
+            let position = ((color[c] - self.domain_min[c]) / range).clamp(0., 1.) * last;
+            base[c] = (position.floor() as usize).min(self.size - 2);
+            fract[c] = position - base[c] as f32;
+        }
+        let mut result = [0.; 3];
+        for corner in 0..8 {
+            let offset = [corner & 1, corner >> 1 & 1, corner >> 2 & 1];
+            let mut weight = 1.;
+            for c in 0..3 {
+                weight *= if offset[c] == 1 {
+                    fract[c]
+                } else {
+                    1. - fract[c]
+                };
+            }
+            let [r, g, b] = [0, 1, 2].map(|c| base[c] + offset[c]);
+            let entry = self.table[r + self.size * (g + self.size * b)];
+            for c in 0..3 {
+                result[c] += entry[c] * weight;
+            }
+        }
+        result
+    }
+}
+
+/// Color grading with a 3D lookup table, applied to the sRGB encoded colors
+/// after tone mapping, as expected by the tables of grading software.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorGrading {
+    pub lut: Lut3d,
+    /// Mix between the original colors, at 0, and the graded ones, at 1.
+    pub strength: f32,
+}
+
+impl ColorGrading {
+    pub fn new(lut: Lut3d) -> Self {
+        ColorGrading { lut, strength: 1. }
+    }
+}
+
+impl PostEffect for ColorGrading {
+    fn apply(&mut self, frame: &mut Frame) {
+        for pixel in frame.colors.chunks_exact_mut(4) {
+            // the tables expect straight colors
+            let alpha = pixel[3];
+            if alpha <= 0. {
+                continue;
+            }
+            let encoded = [0, 1, 2].map(|c| linear_to_srgb((pixel[c] / alpha).clamp(0., 1.)));
+            let graded = self.lut.lookup(encoded);
+            for c in 0..3 {
+                let mixed = encoded[c] + (graded[c] - encoded[c]) * self.strength;
+                pixel[c] = srgb_to_linear(mixed.clamp(0., 1.)) * alpha;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_color_grading() {
+    let cube = "# inverted red
+TITLE \"invert\"
+LUT_3D_SIZE 2
+DOMAIN_MIN 0 0 0
+DOMAIN_MAX 1 1 1
+
+1 0 0
+0 0 0
+1 1 0
+0 1 0
+1 0 1
+0 0 1
+1 1 1
+0 1 1
+";
+    let lut = Lut3d::parse(cube.as_bytes()).unwrap();
+    assert_eq!(lut.size, 2);
+    assert_eq!(lut.lookup([0.25, 0.5, 1.]), [0.75, 0.5, 1.]);
+    let identity = Lut3d::identity(3);
+    assert_eq!(identity.lookup([0.25, 0.5, 1.]), [0.25, 0.5, 1.]);
+    let resolve = cube.replace(
+        "DOMAIN_MIN 0 0 0\nDOMAIN_MAX 1 1 1",
+        "LUT_1D_INPUT_RANGE 0 1\nLUT_3D_INPUT_RANGE 0 2",
+    );
+    let resolve = Lut3d::parse(resolve.as_bytes()).unwrap();
+    assert_eq!(resolve.domain_max, [2.; 3]);
+    assert_eq!(resolve.lookup([0.5, 1., 2.]), [0.75, 0.5, 1.]);
+
+    // premultiplied colors, the first one being (1, 0.5, 0) half transparent
+    let mut colors = vec![0.5, 0.25, 0., 0.5, 0., 0., 0., 0.];
+    let mut frame = Frame {
+        colors: &mut colors,
+        depth: &[f32::MIN; 2],
+        width: 2,
+        height: 1,
+    };
+    ColorGrading::new(lut).apply(&mut frame);
+    assert!(colors[0].abs() < 1e-6);
+    assert!((colors[1] - 0.25).abs() < 1e-5);
+    assert_eq!(colors[3], 0.5);
+    assert_eq!(colors[4..], [0.; 4]);
+
+    assert!(matches!(
+        Lut3d::parse("LUT_3D_SIZE 2\n0 0 0\n".as_bytes()),
+        Err(LutError::WrongEntryCount {
+            expected: 8,
+            found: 1
+        })
+    ));
+    assert!(matches!(
+        Lut3d::parse("LUT_3D_SIZE 2\n0 0 x\n".as_bytes()),
+        Err(LutError::InvalidLine { line_number: 2, .. })
+    ));
+    assert!(matches!(
+        Lut3d::parse("LUT_1D_SIZE 2\n".as_bytes()),
+        Err(LutError::MissingSize)
+    ));
+}
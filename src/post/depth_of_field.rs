@@ -0,0 +1,126 @@
+use super::{smoothstep, texel, Frame, PostEffect};
+
+/// Number of pixels averaged by the blur of every pixel.
+const SAMPLES: usize = 24;
+
+/// Blur of the pixels away from a depth in focus, like with a camera lens,
+/// applied before tone mapping.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthOfField {
+    /// Depth in focus, from -1 for the farthest to 1 for the nearest points
+    /// of the view cube.
+    pub focus_depth: f32,
+    /// Distance in depth around `focus_depth` which stays sharp.
+    pub focus_range: f32,
+    /// Radius of the blur in pixels, reached at twice `focus_range` from the
+    /// focus.
+    pub max_radius: f32,
+}
+
+impl Default for DepthOfField {
+    fn default() -> Self {
+        DepthOfField {
+            focus_depth: 0.,
+            focus_range: 0.2,
+            max_radius: 6.,
+        }
+    }
+}
+
+impl DepthOfField {
+    /// Radius of the blur of a pixel at the given depth, the background being
+    /// the farthest.
+    fn blur_radius(&self, depth: Option<f32>) -> f32 {
+        let distance = (depth.unwrap_or(-1.) - self.focus_depth).abs();
+        self.max_radius * smoothstep(self.focus_range, 2. * self.focus_range, distance)
+    }
+}
+
+impl PostEffect for DepthOfField {
+    fn before_tone_mapping(&self) -> bool {
+        true
+    }
+
+    fn apply(&mut self, frame: &mut Frame) {
+        let source = frame.colors.to_vec();
+        let (width, height) = (frame.width, frame.height);
+        let radii: Vec<f32> = (0..width * height)
+            .map(|i| self.blur_radius(frame.depth_at((i % width) as isize, (i / width) as isize)))
+            .collect();
+        // a disc of samples, as a spiral
+        let golden_angle = std::f32::consts::PI * (3. - 5f32.sqrt());
+        let disc: Vec<(f32, f32)> = (0..SAMPLES)
+            .map(|i| {
+                let r = ((i as f32 + 0.5) / SAMPLES as f32).sqrt();
+                let (sin, cos) = (i as f32 * golden_angle).sin_cos();
+                (r * cos, r * sin)
+            })
+            .collect();
+
+        for (i, pixel) in frame.colors.chunks_exact_mut(4).enumerate() {
+            let radius = radii[i];
+            if radius < 0.5 {
+                continue;
+            }
+            let (x, y) = ((i % width) as f32, (i / width) as f32);
+            let mut sum = [0.; 3];
+            let mut total = 0.;
+            for &(dx, dy) in &disc {
+                let (sx, sy) = (
+                    (x + dx * radius).round() as isize,
+                    (y + dy * radius).round() as isize,
+                );
+                let j = sy.clamp(0, height as isize - 1) as usize * width
+                    + sx.clamp(0, width as isize - 1) as usize;
+                // sharper pixels don't spread over the blurred ones
+                let distance = (dx * dx + dy * dy).sqrt() * radius;
+                let weight = smoothstep(distance - 1., distance, radii[j]);
+                let texel = texel(&source, width, height, sx, sy);
+                for c in 0..3 {
+                    sum[c] += texel[c] * weight;
+                }
+                total += weight;
+            }
+            if total > 0. {
+                for c in 0..3 {
+                    pixel[c] = sum[c] / total;
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_depth_of_field() {
+    let (mut colors, mut depth) = super::test_frame();
+    let mut dof = DepthOfField {
+        focus_depth: 0.5,
+        focus_range: 0.1,
+        max_radius: 2.,
+    };
+    assert_eq!(dof.blur_radius(Some(0.55)), 0.);
+    assert_eq!(dof.blur_radius(None), 2.);
+
+    // the white pixel is in focus
+    let mut frame = Frame {
+        colors: &mut colors.clone(),
+        depth: &depth,
+        width: 5,
+        height: 5,
+    };
+    dof.apply(&mut frame);
+    assert_eq!(frame.colors, &colors[..]);
+
+    // the white pixel is blurred over its neighbors
+    depth.iter_mut().for_each(|z| *z = 0.);
+    let mut frame = Frame {
+        colors: &mut colors,
+        depth: &depth,
+        width: 5,
+        height: 5,
+    };
+    dof.apply(&mut frame);
+    let red = |x: usize, y: usize| colors[4 * (y * 5 + x)];
+    assert!(red(2, 2) < 1. && red(1, 2) > 0. && red(2, 3) > 0.);
+    assert_eq!(red(0, 0), 0.);
+}
@@ -0,0 +1,153 @@
+use super::{bilinear, luminance, smoothstep, Frame, PostEffect};
+use crate::renderer::linear_to_srgb;
+
+/// Steps taken along an edge, in both directions, to find its ends.
+const SEARCH_STEPS: usize = 12;
+
+/// Fast approximate anti-aliasing (Timothy Lottes, 2009): the edges are
+/// found from the contrast of the pixels and smoothed along their direction,
+/// which also smooths lines, points and the texture of the faces.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fxaa {
+    /// Contrast around a pixel, relative to the brightest pixel, over which
+    /// it is smoothed.
+    pub edge_threshold: f32,
+    /// Contrast under which dark pixels are left as they are.
+    pub edge_threshold_min: f32,
+    /// Amount of smoothing of the pixels brighter or darker than all of their
+    /// neighbors, from 0 to 1.
+    pub subpixel: f32,
+}
+
+impl Default for Fxaa {
+    fn default() -> Self {
+        Fxaa {
+            edge_threshold: 0.125,
+            edge_threshold_min: 0.0312,
+            subpixel: 0.75,
+        }
+    }
+}
+
+impl PostEffect for Fxaa {
+    fn apply(&mut self, frame: &mut Frame) {
+        let source = frame.colors.to_vec();
+        let (width, height) = (frame.width, frame.height);
+        // perceived brightness, as a fourth channel for `bilinear`
+        let luma: Vec<f32> = source
+            .chunks_exact(4)
+            .flat_map(|pixel| {
+                let l = linear_to_srgb(luminance(pixel).clamp(0., 1.));
+                [l, l, l, l]
+            })
+            .collect();
+        let luma_at = |x: f32, y: f32| bilinear(&luma, width, height, x, y)[0];
+
+        for (i, pixel) in frame.colors.chunks_exact_mut(4).enumerate() {
+            let (x, y) = ((i % width) as f32, (i / width) as f32);
+            let l = |dx: f32, dy: f32| luma_at(x + dx, y + dy);
+            let (center, n, s, w, e) = (l(0., 0.), l(0., -1.), l(0., 1.), l(-1., 0.), l(1., 0.));
+            let max = center.max(n).max(s).max(w).max(e);
+            let min = center.min(n).min(s).min(w).min(e);
+            let range = max - min;
+            if range < self.edge_threshold_min.max(max * self.edge_threshold) {
+                continue;
+            }
+            let (nw, ne, sw, se) = (l(-1., -1.), l(1., -1.), l(-1., 1.), l(1., 1.));
+
+            // pixels standing out from all of their neighbors
+            let average = (2. * (n + s + w + e) + nw + ne + sw + se) / 12.;
+            let subpixel = smoothstep(0., 1., (average - center).abs() / range);
+            let subpixel = subpixel * subpixel * self.subpixel;
+
+            // an edge is horizontal when the contrast is between rows
+            let horizontal = (nw + sw - 2. * w).abs()
+                + 2. * (n + s - 2. * center).abs()
+                + (ne + se - 2. * e).abs()
+                >= (nw + ne - 2. * n).abs()
+                    + 2. * (w + e - 2. * center).abs()
+                    + (sw + se - 2. * s).abs();
+            let (before, after) = if horizontal { (n, s) } else { (w, e) };
+            // the other side of the edge is where the contrast is the highest
+            let (side, opposite) = if (before - center).abs() >= (after - center).abs() {
+                (-1., before)
+            } else {
+                (1., after)
+            };
+            let gradient = (opposite - center).abs() / 4.;
+            let edge_luma = (center + opposite) / 2.;
+            let (across, along) = if horizontal {
+                ((0., 1.), (1., 0.))
+            } else {
+                ((1., 0.), (0., 1.))
+            };
+
+            // walk along the edge, between the pixel and the other side, until
+            // the brightness changes
+            let start = (x + across.0 * side / 2., y + across.1 * side / 2.);
+            let walk = |direction: f32| {
+                let mut delta = 0.;
+                for step in 1..=SEARCH_STEPS {
+                    let d = step as f32 * direction;
+                    delta = luma_at(start.0 + along.0 * d, start.1 + along.1 * d) - edge_luma;
+                    if delta.abs() >= gradient {
+                        return (step as f32, delta);
+                    }
+                }
+                (SEARCH_STEPS as f32, delta)
+            };
+            let (distance_before, delta_before) = walk(-1.);
+            let (distance_after, delta_after) = walk(1.);
+            let (distance, delta) = if distance_before < distance_after {
+                (distance_before, delta_before)
+            } else {
+                (distance_after, delta_after)
+            };
+            // only the pixels on the side of the edge going past the nearest
+            // end are blended, the farther from the end the less
+            let blends = (delta < 0.) != (center < edge_luma);
+            let edge = if blends {
+                0.5 - distance / (distance_before + distance_after)
+            } else {
+                0.
+            };
+
+            let offset = edge.max(subpixel) * side;
+            let color = bilinear(
+                &source,
+                width,
+                height,
+                x + across.0 * offset,
+                y + across.1 * offset,
+            );
+            pixel[..3].copy_from_slice(&color[..3]);
+        }
+    }
+}
+
+#[test]
+fn test_fxaa() {
+    // a staircase edge between black and white
+    let (width, height) = (8, 4);
+    let mut colors = vec![0.; 4 * width * height];
+    for (i, pixel) in colors.chunks_exact_mut(4).enumerate() {
+        let (x, y) = (i % width, i / width);
+        let value = if y >= 2 || (y == 1 && x >= 4) { 1. } else { 0. };
+        pixel.copy_from_slice(&[value, value, value, 1.]);
+    }
+    let original = colors.clone();
+    let mut frame = Frame {
+        colors: &mut colors,
+        depth: &vec![f32::MIN; width * height],
+        width,
+        height,
+    };
+    Fxaa::default().apply(&mut frame);
+    let red = |colors: &[f32], x: usize, y: usize| colors[4 * (y * width + x)];
+    // the step of the staircase is smoothed
+    assert!(red(&colors, 3, 1) > 0. && red(&colors, 3, 1) < 1.);
+    assert!(red(&colors, 4, 1) > 0. && red(&colors, 4, 1) < 1.);
+    // away from the edge, the pixels are left as they are
+    assert_eq!(red(&colors, 0, 3), red(&original, 0, 3));
+    assert_eq!(colors[4 * 9 + 3], 1.);
+}
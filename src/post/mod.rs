@@ -0,0 +1,194 @@
+//! Effects applied to the frames once the scene is drawn.
+//!
+//! Every effect is a `PostEffect`, and `render_scene` applies the effects of
+//! `post_effects` in order, on the colors and the depth of the pixels, before
+//! writing the frame buffer. Effects may be written outside of this crate as
+//! well.
+
+mod bloom;
+mod color_grading;
+mod depth_of_field;
+mod fxaa;
+mod outline;
+mod sharpen;
+mod vignette;
+
+use cgmath::prelude::*;
+use cgmath::Vector3;
+
+use crate::utils;
+use crate::Config;
+pub use bloom::Bloom;
+pub use color_grading::{ColorGrading, Lut3d, LutError};
+pub use depth_of_field::DepthOfField;
+pub use fxaa::Fxaa;
+pub use outline::Outline;
+pub use sharpen::Sharpen;
+pub use vignette::Vignette;
+
+/// A pass processing the pixels of a frame.
+pub trait PostEffect {
+    /// Whether the effect is applied to the colors before they are exposed
+    /// and tone mapped, which may then be brighter than 1, instead of after.
+    fn before_tone_mapping(&self) -> bool {
+        false
+    }
+
+    /// Process the pixels of the frame in place.
+    fn apply(&mut self, frame: &mut Frame);
+}
+
+/// Pixels given to a `PostEffect`, at the size of the frame buffer.
+///
+/// Pixels are stored like in the frame buffer, row after row from the top of
+/// the image.
+pub struct Frame<'a> {
+    /// Linear RGBA colors of the pixels, in `[0, 1]` after tone mapping and
+    /// premultiplied by their alpha.
+    pub colors: &'a mut [f32],
+    /// Depth of every pixel, from -1 for the farthest to 1 for the nearest
+    /// points of the view cube, `f32::MIN` where nothing is drawn.
+    pub depth: &'a [f32],
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Frame<'_> {
+    /// Depth of the pixel `(x, y)`, counted from the top left corner, if it
+    /// is on screen and covered.
+    pub fn depth_at(&self, x: isize, y: isize) -> Option<f32> {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return None;
+        }
+        Some(self.depth[y as usize * self.width + x as usize]).filter(|&z| z != f32::MIN)
+    }
+
+    /// Normal facing the viewer of the surface drawn at the pixel `(x, y)`,
+    /// reconstructed from the depth around it, if it is covered.
+    pub fn normal(&self, x: isize, y: isize) -> Option<Vector3<f32>> {
+        self.depth_at(x, y)?;
+        let offsets = [-2, -1, 0, 1, 2];
+        let slope_x = utils::depth_slope(offsets.map(|d| self.depth_at(x + d, y)));
+        // the rows go down
+        let slope_y = -utils::depth_slope(offsets.map(|d| self.depth_at(x, y + d)));
+        // a pixel spans 2 / (width - 1) in the `[-1, 1]` cube
+        let dx = 2. / (self.width - 1).max(1) as f32;
+        let dy = 2. / (self.height - 1).max(1) as f32;
+        Some(Vector3::new(-slope_x * dy, -slope_y * dx, dx * dy).normalize())
+    }
+}
+
+/// Color of the pixel `(x, y)` of `colors`, the nearest one on screen if it
+/// is out of it.
+fn texel(colors: &[f32], width: usize, height: usize, x: isize, y: isize) -> [f32; 4] {
+    let x = x.clamp(0, width as isize - 1) as usize;
+    let y = y.clamp(0, height as isize - 1) as usize;
+    let i = 4 * (y * width + x);
+    [colors[i], colors[i + 1], colors[i + 2], colors[i + 3]]
+}
+
+/// Color at the position `(x, y)` of `colors`, interpolated between the
+/// centers of the 4 pixels around it.
+fn bilinear(colors: &[f32], width: usize, height: usize, x: f32, y: f32) -> [f32; 4] {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as isize, y0 as isize);
+    let mut color = [0.; 4];
+    for (dx, dy, weight) in [
+        (0, 0, (1. - fx) * (1. - fy)),
+        (1, 0, fx * (1. - fy)),
+        (0, 1, (1. - fx) * fy),
+        (1, 1, fx * fy),
+    ] {
+        let texel = texel(colors, width, height, x0 + dx, y0 + dy);
+        for c in 0..4 {
+            color[c] += texel[c] * weight;
+        }
+    }
+    color
+}
+
+/// Brightness of a linear color.
+fn luminance(color: &[f32]) -> f32 {
+    0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2]
+}
+
+/// Hermite interpolation from 0 at `edge0` to 1 at `edge1`.
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+/// Apply the effects to the colors and the depth of a frame of the given
+/// size, expose and tone map the colors in between.
+pub(crate) fn apply_effects(
+    effects: &mut [Box<dyn PostEffect>],
+    colors: &mut [f32],
+    depth: &[f32],
+    config: &Config,
+) {
+    let mut frame = Frame {
+        colors,
+        depth,
+        width: config.width as usize,
+        height: config.height as usize,
+    };
+    for effect in effects.iter_mut() {
+        if effect.before_tone_mapping() {
+            effect.apply(&mut frame);
+        }
+    }
+    crate::renderer::expose(frame.colors, config);
+    for effect in effects.iter_mut() {
+        if !effect.before_tone_mapping() {
+            effect.apply(&mut frame);
+        }
+    }
+}
+
+/// A 5x5 frame, black with a white pixel in the middle, over a plane tilted
+/// along `x` with a square step towards the viewer in its middle 3x3 pixels.
+#[cfg(test)]
+fn test_frame() -> (Vec<f32>, Vec<f32>) {
+    let mut colors = vec![0.; 4 * 25];
+    for pixel in colors.chunks_exact_mut(4) {
+        pixel[3] = 1.;
+    }
+    colors[4 * 12..4 * 12 + 4].copy_from_slice(&[1.; 4]);
+    let depth = (0..25)
+        .map(|i| {
+            let (x, y) = (i % 5, i / 5);
+            let step = if (1..4).contains(&x) && (1..4).contains(&y) {
+                0.5
+            } else {
+                0.
+            };
+            x as f32 * 0.01 + step
+        })
+        .collect();
+    (colors, depth)
+}
+
+#[test]
+fn test_frame_normals() {
+    let (mut colors, mut depth) = test_frame();
+    depth[0] = f32::MIN;
+    let frame = Frame {
+        colors: &mut colors,
+        depth: &depth,
+        width: 5,
+        height: 5,
+    };
+    assert_eq!(frame.depth_at(0, 0), None);
+    assert_eq!(frame.depth_at(5, 0), None);
+    assert!(frame.depth_at(2, 2).unwrap() > 0.5);
+    // the step doesn't bend the normals of the pixels next to it
+    let tilted = frame.normal(1, 2).unwrap();
+    assert!((tilted - frame.normal(3, 2).unwrap()).magnitude() < 1e-5);
+    assert!(tilted.x < 0. && tilted.y.abs() < 1e-5 && tilted.z > 0.);
+    assert_eq!(frame.normal(0, 0), None);
+
+    let colors = [0., 0., 0., 0., 1., 1., 1., 1.];
+    assert_eq!(bilinear(&colors, 2, 1, 0.25, 0.), [0.25; 4]);
+    assert_eq!(texel(&colors, 2, 1, 5, -1), [1.; 4]);
+}
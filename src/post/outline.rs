@@ -0,0 +1,109 @@
+use cgmath::prelude::*;
+
+use super::{Frame, PostEffect};
+
+/// Lines drawn where the depth or the normals of the surfaces change
+/// abruptly, at the silhouettes and the creases.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Outline {
+    /// Linear color of the lines, blended by its alpha.
+    pub color: [f32; 4],
+    /// Jump in depth, beyond the slope of the surface, drawn as an edge.
+    pub depth_threshold: f32,
+    /// Smallest angle between the normals of two pixels, in degrees, drawn as
+    /// a crease.
+    pub crease_angle: f32,
+}
+
+impl Default for Outline {
+    fn default() -> Self {
+        Outline {
+            color: [0., 0., 0., 1.],
+            depth_threshold: 0.02,
+            crease_angle: 30.,
+        }
+    }
+}
+
+impl Outline {
+    /// Whether the pixel `(x, y)` is on an edge. The edges are drawn on the
+    /// nearest side.
+    fn is_edge(&self, frame: &Frame, x: isize, y: isize) -> bool {
+        let z = match frame.depth_at(x, y) {
+            Some(z) => z,
+            None => return false,
+        };
+        let cos_crease = self.crease_angle.to_radians().cos();
+        let normal = frame.normal(x, y);
+        for &(dx, dy) in &[(-1, 0), (1, 0), (0, -1), (0, 1)] {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx < 0 || ny < 0 || nx >= frame.width as isize || ny >= frame.height as isize {
+                continue;
+            }
+            let neighbor = match frame.depth_at(nx, ny) {
+                Some(neighbor) => neighbor,
+                // silhouette
+                None => return true,
+            };
+            // the difference with the depth extrapolated from the other side
+            let expected = frame
+                .depth_at(x - dx, y - dy)
+                .map_or(z, |before| 2. * z - before);
+            if expected - neighbor > self.depth_threshold {
+                return true;
+            }
+            if let (Some(n), Some(other)) = (normal, frame.normal(nx, ny)) {
+                if n.dot(other) < cos_crease && neighbor <= z {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+impl PostEffect for Outline {
+    fn apply(&mut self, frame: &mut Frame) {
+        let edges: Vec<bool> = (0..frame.width * frame.height)
+            .map(|i| {
+                self.is_edge(
+                    frame,
+                    (i % frame.width) as isize,
+                    (i / frame.width) as isize,
+                )
+            })
+            .collect();
+        let alpha = self.color[3];
+        for (pixel, _) in frame
+            .colors
+            .chunks_exact_mut(4)
+            .zip(edges)
+            .filter(|(_, e)| *e)
+        {
+            for (c, line) in pixel.iter_mut().zip(&self.color[..3]) {
+                *c += (line - *c) * alpha;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_outline() {
+    let (_, depth) = super::test_frame();
+    let mut colors = vec![1.; 4 * 25];
+    let mut frame = Frame {
+        colors: &mut colors,
+        depth: &depth,
+        width: 5,
+        height: 5,
+    };
+    Outline::default().apply(&mut frame);
+    let red = |x: usize, y: usize| colors[4 * (y * 5 + x)];
+    // the border of the step, in front of the plane, is drawn
+    assert_eq!((1..4).map(|x| red(x, 1)).collect::<Vec<_>>(), [0., 0., 0.]);
+    assert_eq!(red(1, 2), 0.);
+    assert_eq!(red(2, 2), 1.);
+    // the plane behind it is not
+    assert_eq!(red(0, 2), 1.);
+    assert_eq!(red(4, 2), 1.);
+}
@@ -0,0 +1,47 @@
+use super::{texel, Frame, PostEffect};
+
+/// Unsharp mask, amplifying the difference between every pixel and the 4
+/// pixels around it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sharpen {
+    /// Part of the difference added to the pixels.
+    pub amount: f32,
+}
+
+impl Default for Sharpen {
+    fn default() -> Self {
+        Sharpen { amount: 0.5 }
+    }
+}
+
+impl PostEffect for Sharpen {
+    fn apply(&mut self, frame: &mut Frame) {
+        let source = frame.colors.to_vec();
+        let (width, height) = (frame.width, frame.height);
+        for (i, pixel) in frame.colors.chunks_exact_mut(4).enumerate() {
+            let (x, y) = ((i % width) as isize, (i / width) as isize);
+            let neighbors = [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                .map(|(dx, dy)| texel(&source, width, height, x + dx, y + dy));
+            for c in 0..3 {
+                let sum: f32 = neighbors.iter().map(|n| n[c]).sum();
+                pixel[c] = (pixel[c] + self.amount * (4. * pixel[c] - sum)).max(0.);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_sharpen() {
+    let (mut colors, depth) = super::test_frame();
+    let mut frame = Frame {
+        colors: &mut colors,
+        depth: &depth,
+        width: 5,
+        height: 5,
+    };
+    Sharpen { amount: 0.25 }.apply(&mut frame);
+    // the white pixel stands out more, the flat areas are left as they are
+    assert_eq!(colors[4 * 12..4 * 13], [2., 2., 2., 1.]);
+    assert_eq!(colors[4 * 11..4 * 12], [0., 0., 0., 1.]);
+    assert_eq!(colors[..4], [0., 0., 0., 1.]);
+}
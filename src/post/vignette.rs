@@ -0,0 +1,56 @@
+use super::{smoothstep, Frame, PostEffect};
+
+/// Darkening of the borders of the frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vignette {
+    /// Part of the light removed in the corners, from 0 to 1.
+    pub intensity: f32,
+    /// Distance from the center where the darkening starts, 1 being the
+    /// corners.
+    pub radius: f32,
+    /// Distance over which the darkening goes from nothing to `intensity`.
+    pub softness: f32,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Vignette {
+            intensity: 0.5,
+            radius: 0.5,
+            softness: 0.5,
+        }
+    }
+}
+
+impl PostEffect for Vignette {
+    fn apply(&mut self, frame: &mut Frame) {
+        let center = (
+            (frame.width - 1) as f32 / 2.,
+            (frame.height - 1) as f32 / 2.,
+        );
+        let corner = (center.0 * center.0 + center.1 * center.1).sqrt().max(1.);
+        for (i, pixel) in frame.colors.chunks_exact_mut(4).enumerate() {
+            let (x, y) = ((i % frame.width) as f32, (i / frame.width) as f32);
+            let distance = ((x - center.0).powi(2) + (y - center.1).powi(2)).sqrt() / corner;
+            let darkening = smoothstep(self.radius, self.radius + self.softness, distance);
+            for value in &mut pixel[..3] {
+                *value *= 1. - self.intensity * darkening;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_vignette() {
+    let mut colors = vec![1.; 4 * 25];
+    let mut frame = Frame {
+        colors: &mut colors,
+        depth: &[f32::MIN; 25],
+        width: 5,
+        height: 5,
+    };
+    Vignette::default().apply(&mut frame);
+    assert_eq!(colors[4 * 12..4 * 13], [1.; 4]);
+    assert_eq!(colors[..4], [0.5, 0.5, 0.5, 1.]);
+    assert!(colors[4] < 1. && colors[4] > 0.5);
+}
@@ -0,0 +1,112 @@
+// multisampling and supersampling
+
+/// Offsets of the samples from the center of the pixel, using the standard
+/// patterns of graphics hardware.
+const MSAA_1: [(f32, f32); 1] = [(0., 0.)];
+const MSAA_2: [(f32, f32); 2] = [(0.25, 0.25), (-0.25, -0.25)];
+const MSAA_4: [(f32, f32); 4] = [
+    (-0.125, -0.375),
+    (0.375, -0.125),
+    (-0.375, 0.125),
+    (0.125, 0.375),
+];
+const MSAA_8: [(f32, f32); 8] = [
+    (0.0625, -0.1875),
+    (-0.0625, 0.1875),
+    (0.3125, 0.0625),
+    (-0.1875, -0.3125),
+    (-0.3125, 0.3125),
+    (-0.4375, -0.0625),
+    (0.1875, 0.4375),
+    (0.4375, -0.4375),
+];
+
+/// Positions of the samples of a pixel relative to its center, for 1, 2, 4
+/// or 8 samples.
+pub fn sample_offsets(count: usize) -> &'static [(f32, f32)] {
+    match count {
+        1 => &MSAA_1,
+        2 => &MSAA_2,
+        4 => &MSAA_4,
+        8 => &MSAA_8,
+        _ => panic!("no pattern for {} samples", count),
+    }
+}
+
+/// Average the `count` consecutive samples of every pixel into `frame`.
+pub fn resolve_samples(samples: &[f32], frame: &mut [f32], count: usize) {
+    for (pixel, samples) in frame
+        .chunks_exact_mut(4)
+        .zip(samples.chunks_exact(4 * count))
+    {
+        for (c, value) in pixel.iter_mut().enumerate() {
+            let sum: f32 = samples.iter().skip(c).step_by(4).sum();
+            *value = sum / count as f32;
+        }
+    }
+}
+
+/// Average every block of `factor` by `factor` pixels of `source` into a
+/// pixel of `target`, which is `width` pixels wide.
+pub fn downsample(source: &[f32], target: &mut [f32], width: usize, factor: usize) {
+    let count = (factor * factor) as f32;
+    for (i, pixel) in target.chunks_exact_mut(4).enumerate() {
+        let (x, y) = (i % width * factor, i / width * factor);
+        let mut sum = [0.; 4];
+        for dy in 0..factor {
+            let row = (y + dy) * width * factor;
+            for dx in 0..factor {
+                let p = 4 * (row + x + dx);
+                for c in 0..4 {
+                    sum[c] += source[p + c];
+                }
+            }
+        }
+        for c in 0..4 {
+            pixel[c] = sum[c] / count;
+        }
+    }
+}
+
+/// Depth of every pixel of `depth`, which is `width` pixels wide, taken from
+/// the first sample of the middle pixel of its block of `factor` by `factor`
+/// pixels in the `zbuffer`, which has `samples` samples per pixel.
+pub fn resolve_depth(
+    zbuffer: &[f32],
+    depth: &mut [f32],
+    samples: usize,
+    width: usize,
+    factor: usize,
+) {
+    for (i, z) in depth.iter_mut().enumerate() {
+        let (x, y) = (
+            i % width * factor + factor / 2,
+            i / width * factor + factor / 2,
+        );
+        *z = zbuffer[(y * width * factor + x) * samples];
+    }
+}
+
+#[test]
+fn test_resolve_and_downsample() {
+    let samples = [
+        1., 0., 0., 1., 0., 0., 0., 1., 0.5, 0.5, 0.5, 0.5, 0.5, 0.5, 0.5, 0.5,
+    ];
+    let mut frame = [0.; 8];
+    resolve_samples(&samples, &mut frame, 2);
+    assert_eq!(frame, [0.5, 0., 0., 1., 0.5, 0.5, 0.5, 0.5]);
+
+    // a 4x2 image, with a white pixel in the top left block
+    let mut source = [0.; 32];
+    source[..4].copy_from_slice(&[1.; 4]);
+    let mut target = [1.; 8];
+    downsample(&source, &mut target, 2, 2);
+    assert_eq!(target, [0.25, 0.25, 0.25, 0.25, 0., 0., 0., 0.]);
+
+    let zbuffer = [0., 1., 2., 3., 4., 5., 6., 7.];
+    let mut depth = [0.; 2];
+    resolve_depth(&zbuffer, &mut depth, 2, 2, 1);
+    assert_eq!(depth, [0., 2.]);
+    resolve_depth(&zbuffer, &mut depth[..1], 1, 1, 2);
+    assert_eq!(depth[0], 3.);
+}
@@ -0,0 +1,193 @@
+// pixels behind the mesh, set by `Config::background`
+
+use super::color;
+use crate::Background;
+
+/// Draw the background in the linear RGBA `colors`, premultiplied by their
+/// alpha, of a frame of the given size, row after row from the top.
+///
+/// The frame is `scale` times as large as the frame buffer, and the squares
+/// of checkerboards are scaled accordingly.
+pub fn draw_background(
+    background: &Background,
+    colors: &mut [f32],
+    width: usize,
+    height: usize,
+    scale: u32,
+) {
+    let premultiplied = |color: [f32; 4]| {
+        let alpha = color[3];
+        [color[0] * alpha, color[1] * alpha, color[2] * alpha, alpha]
+    };
+    for (i, pixel) in colors.chunks_exact_mut(4).enumerate() {
+        let (x, y) = (i % width, i / width);
+        let color = match *background {
+            Background::Color(rgba) => premultiplied(color::decode(&rgba)),
+            Background::Gradient { top, bottom } => {
+                let t = y as f32 / (height - 1).max(1) as f32;
+                let top = premultiplied(color::decode(&top));
+                let bottom = premultiplied(color::decode(&bottom));
+                [0, 1, 2, 3].map(|c| top[c] + (bottom[c] - top[c]) * t)
+            }
+            Background::Checkerboard {
+                colors: squares,
+                size,
+            } => {
+                let size = (size * scale).max(1) as usize;
+                premultiplied(color::decode(&squares[(x / size + y / size) % 2]))
+            }
+            // nothing to stretch
+            Background::Image {
+                width,
+                height,
+                pixels,
+            } if width == 0
+                || height == 0
+                || pixels.len() < 4 * width as usize * height as usize =>
+            {
+                [0.; 4]
+            }
+            Background::Image {
+                width: image_width,
+                height: image_height,
+                pixels,
+            } => {
+                // the centers of the corner pixels of the image and the frame
+                // are aligned
+                let u = x as f32 / (width - 1).max(1) as f32 * (image_width - 1) as f32;
+                let v = y as f32 / (height - 1).max(1) as f32 * (image_height - 1) as f32;
+                let texel = |x: u32, y: u32| {
+                    let x = x.min(image_width - 1);
+                    let y = y.min(image_height - 1);
+                    let i = 4 * (y * image_width + x) as usize;
+                    premultiplied(color::decode(&[
+                        pixels[i],
+                        pixels[i + 1],
+                        pixels[i + 2],
+                        pixels[i + 3],
+                    ]))
+                };
+                let (x0, y0) = (u as u32, v as u32);
+                let (fx, fy) = (u.fract(), v.fract());
+                let mut color = [0.; 4];
+                for (dx, dy, weight) in [
+                    (0, 0, (1. - fx) * (1. - fy)),
+                    (1, 0, fx * (1. - fy)),
+                    (0, 1, (1. - fx) * fy),
+                    (1, 1, fx * fy),
+                ] {
+                    let texel = texel(x0 + dx, y0 + dy);
+                    for c in 0..4 {
+                        color[c] += texel[c] * weight;
+                    }
+                }
+                color
+            }
+            Background::Transparent => [0.; 4],
+        };
+        pixel.copy_from_slice(&color);
+    }
+}
+
+/// Put the `background` behind the pixels of `colors` which are not opaque.
+pub fn put_behind(colors: &mut [f32], background: &[f32]) {
+    for (pixel, behind) in colors.chunks_exact_mut(4).zip(background.chunks_exact(4)) {
+        let visible = 1. - pixel[3];
+        for c in 0..4 {
+            pixel[c] += behind[c] * visible;
+        }
+    }
+}
+
+#[test]
+fn test_background() {
+    let drawn = |background: Background| {
+        let mut colors = vec![1.; 4 * 4 * 3];
+        draw_background(&background, &mut colors, 4, 3, 1);
+        colors
+    };
+    let pixel = |colors: &[f32], x: usize, y: usize| colors[4 * (y * 4 + x)..][..4].to_vec();
+
+    let color = drawn(Background::Color([255, 0, 255, 255]));
+    assert!(color.chunks_exact(4).all(|c| c == [1., 0., 1., 1.]));
+    // colors are premultiplied by their alpha
+    let transparent = drawn(Background::Color([255, 255, 255, 51]));
+    assert_eq!(pixel(&transparent, 0, 0), [0.2; 4]);
+    assert_eq!(drawn(Background::Transparent), vec![0.; 48]);
+
+    let gradient = drawn(Background::Gradient {
+        top: [255, 255, 255, 255],
+        bottom: [0, 0, 0, 255],
+    });
+    assert_eq!(pixel(&gradient, 3, 0), [1.; 4]);
+    assert_eq!(pixel(&gradient, 0, 1), [0.5, 0.5, 0.5, 1.]);
+    assert_eq!(pixel(&gradient, 2, 2), [0., 0., 0., 1.]);
+
+    let white = [255; 4];
+    let black = [0, 0, 0, 255];
+    let checkerboard = drawn(Background::Checkerboard {
+        colors: [white, black],
+        size: 2,
+    });
+    assert_eq!(pixel(&checkerboard, 1, 1), [1.; 4]);
+    assert_eq!(pixel(&checkerboard, 2, 1), [0., 0., 0., 1.]);
+    assert_eq!(pixel(&checkerboard, 2, 2), [1.; 4]);
+
+    // a 2x1 image stretched over the frame
+    let pixels = [white, black].concat();
+    let image = drawn(Background::Image {
+        width: 2,
+        height: 1,
+        pixels: &pixels,
+    });
+    assert_eq!(pixel(&image, 0, 2), [1.; 4]);
+    assert_eq!(pixel(&image, 3, 0), [0., 0., 0., 1.]);
+    assert!((pixel(&image, 1, 1)[0] - 2. / 3.).abs() < 1e-6);
+    // images without enough pixels are left out
+    for (width, height) in [(0, 1), (1, 0), (2, 2)] {
+        let image = drawn(Background::Image {
+            width,
+            height,
+            pixels: &pixels,
+        });
+        assert_eq!(image, vec![0.; 48]);
+    }
+
+    let mut colors = vec![0.5, 0., 0., 0.5, 0., 0., 0., 0.];
+    put_behind(&mut colors, &[0., 1., 0., 1., 0., 0., 1., 1.]);
+    assert_eq!(colors, vec![0.5, 0.5, 0., 1., 0., 0., 1., 1.]);
+}
+
+#[test]
+fn test_background_rendering() {
+    use crate::fixtures::{glass_squares, pixel, render};
+    use crate::{Config, Culling, RenderMode, Transparency};
+
+    let (mesh, mut config) = glass_squares();
+    config.background = Background::Transparent;
+    // the pixels in the middle of the screen, then on the large square only
+    let draw = |config: Config| {
+        let (frame, _) = render(config, &mesh);
+        [100, 10]
+            .iter()
+            .map(|&x| pixel(&frame, &config, x, 100))
+            .collect::<Vec<_>>()
+    };
+
+    // the transparent square lets the background show through, its color
+    // being written with a straight alpha
+    let white = [255; 4];
+    assert_eq!(draw(config), vec![white, [255, 255, 255, 128]]);
+    config.transparency = Transparency::WeightedBlended;
+    assert_eq!(draw(config), vec![white, [255, 255, 255, 128]]);
+
+    // the background is put behind the overdraw once counted
+    config.background = Background::Color([0, 0, 255, 255]);
+    config.render_mode = RenderMode::Overdraw;
+    config.culling = Culling::Front;
+    assert_eq!(draw(config)[0], [0, 0, 255, 255]);
+    config.culling = Culling::Back;
+    for pixel in draw(config) {
+        assert!(pixel[1] > 0 && pixel[3] == 255, "{:?}", pixel);
+    }
+}
@@ -0,0 +1,100 @@
+// linear colors, tone mapping and sRGB encoding
+
+use crate::{Config, RenderMode, ToneMapping};
+
+/// Linear value of an sRGB encoded value, both in `[0, 1]`.
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// sRGB encoded value of a linear value, both in `[0, 1]`.
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// Linear RGBA of an sRGB color like `Config::default_color`.
+pub fn decode(color: &[u8; 4]) -> [f32; 4] {
+    let v = |c: u8| srgb_to_linear(c as f32 / 255.);
+    [
+        v(color[0]),
+        v(color[1]),
+        v(color[2]),
+        color[3] as f32 / 255.,
+    ]
+}
+
+/// Map a linear value of any brightness to `[0, 1]`.
+fn tone_map(v: f32, operator: ToneMapping) -> f32 {
+    match operator {
+        ToneMapping::Clamp => v,
+        ToneMapping::Reinhard => v / (1. + v),
+        ToneMapping::AcesFilmic => {
+            // Krzysztof Narkowicz's fit of the ACES curve
+            (v * (2.51 * v + 0.03)) / (v * (2.43 * v + 0.59) + 0.14)
+        }
+    }
+    .clamp(0., 1.)
+}
+
+/// Expose and tone map the linear `colors`, premultiplied by their alpha, to
+/// `[0, 1]` with `RenderMode::Shaded`. The other modes show data which is
+/// only clamped.
+pub fn expose(colors: &mut [f32], config: &Config) {
+    let shaded = config.render_mode == RenderMode::Shaded;
+    let exposure = 2f32.powf(config.exposure);
+    for color in colors.chunks_exact_mut(4) {
+        color[3] = color[3].clamp(0., 1.);
+        let alpha = color[3];
+        for value in &mut color[..3] {
+            *value = if shaded && alpha > 0. {
+                // the color itself is tone mapped, without its alpha
+                tone_map(*value / alpha * exposure, config.tone_mapping) * alpha
+            } else {
+                value.clamp(0., 1.)
+            };
+        }
+    }
+}
+
+/// Write the linear `colors`, in `[0, 1]` and premultiplied by their alpha,
+/// to the frame buffer, sRGB encoded and with a straight alpha, as in PNG
+/// files.
+pub fn encode(colors: &[f32], frame: &mut [u8]) {
+    for (pixel, color) in frame.chunks_exact_mut(4).zip(colors.chunks_exact(4)) {
+        let alpha = color[3].clamp(0., 1.);
+        for c in 0..3 {
+            let straight = if alpha > 0. { color[c] / alpha } else { 0. };
+            pixel[c] = (linear_to_srgb(straight.clamp(0., 1.)) * 255.).round() as u8;
+        }
+        pixel[3] = (alpha * 255.).round() as u8;
+    }
+}
+
+#[test]
+fn test_tone_mapping() {
+    for &v in &[0., 0.002, 0.2, 0.5, 1.] {
+        assert!((linear_to_srgb(srgb_to_linear(v)) - v).abs() < 1e-5);
+    }
+    assert_eq!(decode(&[255, 0, 255, 255]), [1., 0., 1., 1.]);
+
+    // highlights are compressed instead of clipped
+    for &operator in &[ToneMapping::Reinhard, ToneMapping::AcesFilmic] {
+        let (one, two) = (tone_map(1., operator), tone_map(2., operator));
+        assert!(one < two && two < 1.);
+    }
+    assert_eq!(tone_map(2., ToneMapping::Clamp), 1.);
+    assert_eq!(tone_map(0., ToneMapping::AcesFilmic), 0.);
+
+    // premultiplied colors are written with a straight alpha
+    let mut frame = [0; 8];
+    encode(&[0.25, 0., 0.5, 0.5, 1., 1., 1., 0.], &mut frame);
+    assert_eq!(frame, [188, 0, 255, 128, 0, 0, 0, 0]);
+}
@@ -0,0 +1,181 @@
+// debug visualizations selected by `RenderMode`
+
+use cgmath::prelude::*;
+use cgmath::{Vector2, Vector3};
+
+use super::color;
+use crate::mesh::Vertex;
+use crate::RenderMode;
+
+/// Number of checks per unit of texture coordinates.
+const CHECKER_SIZE: f32 = 8.;
+
+/// Overdraw shown with the hottest color of the heatmap.
+const MAX_OVERDRAW: f32 = 8.;
+
+/// Write the color of a pixel of a triangle for the debug modes, where
+/// `corners` are the vertices of the triangle and `bc` the barycentric
+/// coordinates of the pixel.
+///
+/// Colors are written linear, so that they are shown as computed once sRGB
+/// encoded. With `RenderMode::Depth` and `RenderMode::Overdraw`, the pixel is
+/// only prepared for `resolve`.
+pub fn shade(
+    mode: RenderMode,
+    corners: &[&Vertex; 3],
+    normal: Vector3<f32>,
+    front: bool,
+    bc: Vector3<f32>,
+    pixel: &mut [f32],
+) {
+    let color = match mode {
+        RenderMode::Shaded | RenderMode::Depth => return,
+        RenderMode::Overdraw => {
+            // fragments are counted in the red channel
+            pixel[0] += 1.;
+            return;
+        }
+        RenderMode::Normals => {
+            let interpolated = if corners.iter().all(|v| !v.normal.is_zero()) {
+                (0..3).map(|i| corners[i].normal * bc[i]).sum()
+            } else {
+                normal
+            };
+            let n = interpolated.normalize() * 0.5 + Vector3::new(0.5, 0.5, 0.5);
+            [n.x, n.y, n.z]
+        }
+        RenderMode::Uv | RenderMode::UvChecker => {
+            let uv = (0..3).fold(Vector2::zero(), |uv, i| uv + corners[i].texture * bc[i]);
+            if mode == RenderMode::Uv {
+                [uv.x - uv.x.floor(), uv.y - uv.y.floor(), 0.]
+            } else {
+                let check = (uv.x * CHECKER_SIZE).floor() + (uv.y * CHECKER_SIZE).floor();
+                let value = if check.rem_euclid(2.) < 1. { 1. } else { 0.25 };
+                [value, value, value]
+            }
+        }
+        RenderMode::FaceOrientation => {
+            if front {
+                [0., 1., 0.]
+            } else {
+                [1., 0., 0.]
+            }
+        }
+    };
+    for (channel, &value) in pixel.iter_mut().zip(color.iter()) {
+        *channel = color::srgb_to_linear(value);
+    }
+    pixel[3] = 1.;
+}
+
+/// Turn the pixels prepared by `shade` into their final colors, once every
+/// triangle is drawn.
+pub fn resolve(mode: RenderMode, frame: &mut [f32], zbuffer: &[f32]) {
+    match mode {
+        RenderMode::Depth => resolve_depth(frame, zbuffer),
+        RenderMode::Overdraw => {
+            for pixel in frame.chunks_exact_mut(4) {
+                if pixel[0] > 0. {
+                    pixel.copy_from_slice(&heat(pixel[0] / MAX_OVERDRAW));
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Gray levels from white for the nearest pixel to black for the farthest.
+///
+/// The projection is orthographic, so the `zbuffer` already holds linear
+/// depths and only needs to be rescaled.
+fn resolve_depth(frame: &mut [f32], zbuffer: &[f32]) {
+    let covered = || zbuffer.iter().cloned().filter(|&z| z != f32::MIN);
+    let near = covered().fold(f32::MIN, f32::max);
+    let far = covered().fold(f32::MAX, f32::min);
+    let range = if near > far { near - far } else { 1. };
+    for (pixel, &z) in frame.chunks_exact_mut(4).zip(zbuffer) {
+        if z != f32::MIN {
+            let value = color::srgb_to_linear((z - far) / range);
+            pixel.copy_from_slice(&[value, value, value, 1.]);
+        }
+    }
+}
+
+/// Heatmap going from blue through green and yellow to red, for values from 0
+/// to 1, as linear colors.
+fn heat(value: f32) -> [f32; 4] {
+    let stops = [[0., 0., 1.], [0., 1., 0.], [1., 1., 0.], [1., 0., 0.]];
+    let t = value.clamp(0., 1.) * (stops.len() - 1) as f32;
+    let i = (t as usize).min(stops.len() - 2);
+    let f = t - i as f32;
+    let mut color = [0., 0., 0., 1.];
+    for c in 0..3 {
+        color[c] = color::srgb_to_linear(stops[i][c] * (1. - f) + stops[i + 1][c] * f);
+    }
+    color
+}
+
+#[test]
+fn test_resolve() {
+    let mut frame = vec![0.; 12];
+    resolve(RenderMode::Depth, &mut frame, &[0.5, f32::MIN, -0.5]);
+    assert_eq!(frame, vec![1., 1., 1., 1., 0., 0., 0., 0., 0., 0., 0., 1.]);
+
+    let mut frame = vec![0., 0., 0., 1., MAX_OVERDRAW, 0., 0., 1., 1., 0., 0., 1.];
+    resolve(RenderMode::Overdraw, &mut frame, &[f32::MIN; 3]);
+    assert_eq!(frame[..8], [0., 0., 0., 1., 1., 0., 0., 1.]);
+    assert!(frame[10] > frame[9]);
+}
+
+#[test]
+fn test_interpolation() {
+    use crate::fixtures::{pixel, render, squares};
+    use crate::mesh::{Group, IndexTuple, MeshData, Normalization, Object};
+    use crate::Config;
+
+    // a triangle over the lower left half of the screen, with different
+    // attributes at every corner
+    let mut group = Group::new("triangle".to_string());
+    group.polys = vec![(0..3).map(|i| IndexTuple(i, Some(i), Some(i))).collect()];
+    let mut object = Object::new("triangle".to_string());
+    object.groups.push(group);
+    let mesh = MeshData {
+        position: vec![
+            Vector3::new(-1., -1., 0.),
+            Vector3::new(1., -1., 0.),
+            Vector3::new(-1., 1., 0.),
+        ],
+        texture: vec![
+            Vector2::new(0.05, 0.05),
+            Vector2::new(0.95, 0.05),
+            Vector2::new(0.05, 0.95),
+        ],
+        normal: vec![Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()],
+        objects: vec![object],
+        ..MeshData::default()
+    };
+    let (_, mut config) = squares();
+    config.normalization = Normalization::None;
+    // the colors next to the corners, in the same order
+    let draw = |render_mode| {
+        let config = Config {
+            render_mode,
+            ..config
+        };
+        let (frame, _) = render(config, &mesh);
+        [(5, 195), (190, 195), (5, 10)].map(|(x, y)| pixel(&frame, &config, x, y))
+    };
+
+    let [first, second, third] = draw(RenderMode::Uv);
+    assert!(first[0] < 64 && first[1] < 64, "{:?}", first);
+    assert!(second[0] > 192 && second[1] < 64, "{:?}", second);
+    assert!(third[0] < 64 && third[1] > 192, "{:?}", third);
+
+    // the first and the last checks of a row or column
+    let checker = draw(RenderMode::UvChecker).map(|color| color[0]);
+    assert_eq!(checker, [255, 64, 64]);
+
+    // the channel of the axis of the normal at the corner is the highest
+    let highest = |color: [u8; 4]| (0..3).max_by_key(|&c| color[c]).unwrap();
+    assert_eq!(draw(RenderMode::Normals).map(highest), [0, 1, 2]);
+}
@@ -1,23 +1,335 @@
+mod antialiasing;
+mod background;
+mod color;
+mod debug;
 mod rasterizer;
+mod ssao;
+mod transparency;
 mod wireframe;
 
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Vector3, Vector4};
+
+use crate::environment::Environment;
+use crate::id_buffer::{IdBuffers, NO_ID};
 use crate::mesh;
-use crate::Config;
+use crate::utils;
+use crate::{Config, RenderMode, Wireframe};
+pub use antialiasing::{downsample, resolve_depth};
+pub use background::{draw_background, put_behind};
+pub use color::{encode, expose, linear_to_srgb, srgb_to_linear};
+use rasterizer::{Dither, Surface};
+pub use transparency::OitBuffers;
+
+/// Fraction of the error of the next coarser level over which levels are
+/// cross-faded, when enabled.
+const LOD_FADE_RANGE: f32 = 0.5;
+
+/// Pick the coarsest level whose error, projected on screen, stays within
+/// `config.lod_pixel_error`.
+///
+/// Also returns how far the budget is into the fade range of the next coarser
+/// level, from 0 to 1, if there is such a level.
+fn select_lod(
+    lods: &mesh::LodChain,
+    model: &Matrix4<f32>,
+    config: &Config,
+) -> (usize, Option<f32>) {
+    // vertices in [-1, 1] are mapped to the whole screen, and the model
+    // transform is a uniform scale
+    let scale = (config.width.max(config.height) - 1) as f32 / 2.;
+    let radius_px = lods.sphere.radius * model.x.truncate().magnitude() * scale;
+    if radius_px <= 0. {
+        return (0, None);
+    }
+    let pixel_error = |lod: &mesh::Lod| lod.error / lods.sphere.radius * radius_px;
+
+    let budget = config.lod_pixel_error;
+    let level = lods
+        .levels
+        .iter()
+        .rposition(|lod| pixel_error(lod) <= budget)
+        .unwrap_or(0);
+    let fade = lods.levels.get(level + 1).and_then(|next| {
+        let end = pixel_error(next);
+        let start = end / (1. + LOD_FADE_RANGE);
+        if budget > start {
+            Some((budget - start) / (end - start))
+        } else {
+            None
+        }
+    });
+    (level, fade)
+}
+
+/// Volume seen by the orthographic projection of `rasterizer`, which maps
+/// `[-1, 1]` to the screen, in model coordinates. The depth is not clipped, so
+/// `z` is flattened.
+fn view_frustum(model: &Matrix4<f32>) -> mesh::Frustum {
+    mesh::Frustum::from_matrix(Matrix4::from_nonuniform_scale(1., 1., 0.) * model)
+}
+
+/// Ray in model coordinates through the pixel `(x, y)` of the frame buffer,
+/// counted from its top left corner, going away from the viewer.
+///
+/// The depth is not clipped, so the ray starts in front of `bounds`.
+pub fn pixel_ray(
+    x: u32,
+    y: u32,
+    model: &Matrix4<f32>,
+    bounds: &mesh::Aabb,
+    config: &Config,
+) -> Option<mesh::Ray> {
+    if x >= config.width || y >= config.height || bounds.is_empty() {
+        return None;
+    }
+    // inverse of the viewport transform, the frame buffer rows going down
+    let width = (config.width - 1).max(1) as f32;
+    let height = (config.height - 1).max(1) as f32;
+    let screen_x = x as f32 / width * 2. - 1.;
+    let screen_y = (height - y as f32) / height * 2. - 1.;
 
+    let inverse = model.invert()?;
+    let origin = (inverse * Vector4::new(screen_x, screen_y, 0., 1.)).truncate();
+    let direction = (inverse * Vector4::new(0., 0., -1., 0.)).truncate();
+    let back = (bounds.center() - origin).magnitude() + bounds.size().magnitude();
+    Some(mesh::Ray::new(
+        origin - direction * (back / direction.magnitude()),
+        direction,
+    ))
+}
+
+/// Buffers written when drawing a frame.
+pub struct Targets<'a> {
+    /// Linear RGBA colors of the pixels, premultiplied by their alpha, see
+    /// `expose`.
+    pub frame: &'a mut [f32],
+    /// Depth of every pixel, or of every sample with multisampling.
+    pub zbuffer: &'a mut [f32],
+    /// Colors of the samples with multisampling, resolved into `frame` once
+    /// every triangle is drawn. Empty otherwise.
+    pub samples: &'a mut [f32],
+    /// Ambient occlusion of every pixel with `Config::ssao_samples`, empty
+    /// otherwise.
+    pub occlusion: &'a mut [f32],
+    /// Transparent fragments with order-independent transparency, empty
+    /// otherwise.
+    pub oit: &'a mut OitBuffers,
+    pub ids: Option<&'a mut IdBuffers>,
+}
+
+/// Draw the level of detail of the mesh selected for its size on screen, lit
+/// by the `environment` if any, and write the labels of its triangles to the
+/// `ids` of the targets if any.
 pub fn render_object(
-    mesh: &mesh::MeshData,
+    lods: &mesh::LodChain,
+    model: &Matrix4<f32>,
+    screen: &mut Vec<Vector3<f32>>,
+    environment: Option<&Environment>,
     config: &Config,
-    frame_buffer: &mut [u8],
-    zbuffer: &mut [f32],
+    targets: &mut Targets,
 ) {
-    for obj in &mesh.objects {
-        for g in obj.groups.iter() {
-            if config.is_wireframe {
-                // show wireframe
-                wireframe::draw_mesh_wireframe(&mesh.position, &g.polys, frame_buffer, config);
-            } else {
-                rasterizer::rasterize_mesh(&mesh.position, &g.polys, frame_buffer, zbuffer, config);
+    let (level, fade) = select_lod(lods, model, config);
+    if !targets.occlusion.is_empty()
+        && config.wireframe.shows_faces()
+        && config.render_mode == RenderMode::Shaded
+    {
+        // with cross-fading, the finer level stands for both
+        render_occlusion(&lods.levels[level], model, screen, config, targets);
+    }
+    match fade {
+        Some(fade) if config.lod_cross_fade && config.wireframe == Wireframe::None => {
+            // both levels are drawn in complementary pixels
+            for (i, coarser) in [(level, false), (level + 1, true)].iter().cloned() {
+                let dither = Some(Dither { fade, coarser });
+                let lod = &lods.levels[i];
+                render_lod(lod, dither, model, screen, environment, config, targets);
             }
         }
+        _ => {
+            let lod = &lods.levels[level];
+            render_lod(lod, None, model, screen, environment, config, targets)
+        }
     }
+
+    if config.wireframe.shows_faces() {
+        let count = config.anti_aliasing.msaa_samples();
+        if count > 1 {
+            debug::resolve(config.render_mode, targets.samples, targets.zbuffer);
+            antialiasing::resolve_samples(targets.samples, targets.frame, count);
+        } else {
+            debug::resolve(config.render_mode, targets.frame, targets.zbuffer);
+        }
+    }
+    // the edges are drawn over the resolved faces
+    if config.wireframe != Wireframe::None {
+        render_edges(&lods.levels[level], model, config, targets);
+    }
+    // lines and points have no surface, so they are always drawn, except in
+    // the modes resolving the pixels after drawing
+    if !config.wireframe.shows_faces() || !config.render_mode.is_resolved() {
+        render_lines(&lods.levels[level], model, config, targets);
+    }
+}
+
+fn render_lod(
+    lod: &mesh::Lod,
+    dither: Option<Dither>,
+    model: &Matrix4<f32>,
+    screen: &mut Vec<Vector3<f32>>,
+    environment: Option<&Environment>,
+    config: &Config,
+    targets: &mut Targets,
+) {
+    // without shaded faces, their depth is only needed to hide the lines
+    let shaded = config.wireframe.shows_faces();
+    if !shaded && !config.depth_tests_lines() {
+        return;
+    }
+    let (mesh, buffer) = (&lod.mesh, &lod.buffer);
+    let materials = match targets.ids {
+        Some(_) if shaded => mesh.group_materials(),
+        _ => Vec::new(),
+    };
+    rasterizer::transform_vertices(&buffer.vertices, model, screen, config);
+
+    let frustum = view_frustum(model);
+    let mut visible: Vec<_> = buffer
+        .ranges
+        .iter()
+        .zip(&lod.bounds)
+        .filter(|(_, bounds)| frustum.intersects(bounds))
+        .map(|(range, bounds)| {
+            let opacity = mesh.objects[range.object].groups[range.group].opacity();
+            (range, opacity, utils::transform(model, bounds.center()).z)
+        })
+        .collect();
+    // opaque groups first, then transparent ones from back to front
+    visible.sort_by(|a, b| {
+        let key = |&(_, opacity, depth): &(_, f32, f32)| {
+            (opacity < 1., if opacity < 1. { depth } else { 0. })
+        };
+        let ((a_transparent, a_depth), (b_transparent, b_depth)) = (key(a), key(b));
+        a_transparent
+            .cmp(&b_transparent)
+            .then(a_depth.total_cmp(&b_depth))
+    });
+    for (range, opacity, _) in visible {
+        if shaded {
+            let material = materials
+                .get(range.object)
+                .and_then(|groups| groups[range.group])
+                .map_or(NO_ID, |m| m as u32);
+            let (specular, roughness) = mesh.objects[range.object].groups[range.group].specular();
+            let surface = Surface {
+                material,
+                opacity,
+                specular,
+                roughness,
+            };
+            rasterizer::rasterize_mesh(
+                buffer,
+                range,
+                surface,
+                screen,
+                dither,
+                environment,
+                targets,
+                config,
+            );
+        } else {
+            rasterizer::rasterize_depth(buffer, range, screen, targets.zbuffer, config);
+        }
+    }
+    if shaded && !targets.oit.is_empty() {
+        let colors = if config.anti_aliasing.msaa_samples() > 1 {
+            &mut *targets.samples
+        } else {
+            &mut *targets.frame
+        };
+        targets.oit.resolve(colors);
+    }
+}
+
+/// Compute the ambient occlusion of the opaque faces of the groups in view
+/// from a first pass only drawing their depth, which is then cleared.
+fn render_occlusion(
+    lod: &mesh::Lod,
+    model: &Matrix4<f32>,
+    screen: &mut Vec<Vector3<f32>>,
+    config: &Config,
+    targets: &mut Targets,
+) {
+    let (mesh, buffer) = (&lod.mesh, &lod.buffer);
+    rasterizer::transform_vertices(&buffer.vertices, model, screen, config);
+    let frustum = view_frustum(model);
+    for (range, bounds) in buffer.ranges.iter().zip(&lod.bounds) {
+        let opaque = mesh.objects[range.object].groups[range.group].opacity() >= 1.;
+        if opaque && frustum.intersects(bounds) {
+            rasterizer::rasterize_depth(buffer, range, screen, targets.zbuffer, config);
+        }
+    }
+    ssao::ambient_occlusion(targets.zbuffer, targets.occlusion, config);
+    for z in targets.zbuffer.iter_mut() {
+        *z = f32::MIN;
+    }
+}
+
+/// Draw the edges of the polygons of the groups in view.
+fn render_edges(lod: &mesh::Lod, model: &Matrix4<f32>, config: &Config, targets: &mut Targets) {
+    let frustum = view_frustum(model);
+    let pen = wireframe::Pen::new(config, targets.zbuffer);
+    for (edges, bounds) in lod.edges.iter().zip(&lod.bounds) {
+        if frustum.intersects(bounds) {
+            wireframe::draw_mesh_wireframe(&lod.mesh.position, model, edges, targets.frame, &pen);
+        }
+    }
+}
+
+/// Draw the lines and points of the groups in view.
+fn render_lines(lod: &mesh::Lod, model: &Matrix4<f32>, config: &Config, targets: &mut Targets) {
+    let (mesh, buffer) = (&lod.mesh, &lod.buffer);
+    let frustum = view_frustum(model);
+    let pen = wireframe::Pen::new(config, targets.zbuffer);
+    for (range, bounds) in buffer.ranges.iter().zip(&lod.bounds) {
+        if !frustum.intersects(bounds) {
+            continue;
+        }
+        let g = &mesh.objects[range.object].groups[range.group];
+        wireframe::draw_polylines(&mesh.position, model, &g.lines, targets.frame, &pen);
+        wireframe::draw_points(&mesh.position, model, &g.points, targets.frame, &pen);
+    }
+}
+
+#[test]
+fn test_select_lod() {
+    let lod = |error| mesh::Lod::new(mesh::MeshData::default(), error);
+    let lods = mesh::LodChain {
+        levels: vec![lod(0.), lod(0.01), lod(0.04)],
+        sphere: mesh::Sphere {
+            center: Vector3::new(0., 0., 0.),
+            radius: 1.,
+        },
+    };
+    let mut config = Config {
+        width: 201,
+        height: 201,
+        lod_levels: 2,
+        lod_pixel_error: 0.5,
+        lod_cross_fade: true,
+        normalization: mesh::Normalization::None,
+        ..Config::default()
+    };
+    let model = Matrix4::identity();
+
+    // levels have an error of 0, 1 and 4 pixels
+    assert_eq!(select_lod(&lods, &model, &config), (0, None));
+    config.lod_pixel_error = 1.;
+    assert_eq!(select_lod(&lods, &model, &config), (1, None));
+    config.lod_pixel_error = 3.;
+    let (level, fade) = select_lod(&lods, &model, &config);
+    assert_eq!(level, 1);
+    assert!((fade.unwrap() - 0.25).abs() < 1e-5);
+    config.lod_pixel_error = 10.;
+    assert_eq!(select_lod(&lods, &model, &config), (2, None));
 }
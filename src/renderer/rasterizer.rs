@@ -1,8 +1,11 @@
-use crate::mesh::SimplePolygon;
+use super::{antialiasing, debug, transparency, Targets};
+use crate::environment::Environment;
+use crate::id_buffer::Ids;
+use crate::mesh::{DrawRange, Vertex, VertexBuffer};
 use crate::utils;
-use crate::Config;
+use crate::{Blending, Config, Culling, RenderMode, Winding};
 use cgmath::prelude::*;
-use cgmath::{Vector2, Vector3};
+use cgmath::{Matrix4, Vector2, Vector3};
 
 #[allow(unused_imports)]
 use test::Bencher;
@@ -46,18 +49,62 @@ fn barycentric_coordinates(vertices: &[Vector3<f32>], point: &Vector3<f32>) -> V
     }
     return Vector3::new(
         1. - (u.x + u.y) as f32 / u.z as f32,
-        u.y as f32 / u.z as f32,
         u.x as f32 / u.z as f32,
+        u.y as f32 / u.z as f32,
     );
 }
 
-fn render_triangle(
+/// 4x4 Bayer matrix, giving the order in which pixels are covered by a
+/// dithered fade, or spreading values over a tile of pixels.
+pub const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Screen door transparency, used to cross-fade between two levels of detail.
+///
+/// With a `fade` going from 0 to 1, pixels are progressively moved from the
+/// finer level to the `coarser` one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dither {
+    pub fade: f32,
+    pub coarser: bool,
+}
+
+impl Dither {
+    fn covers(&self, x: i32, y: i32) -> bool {
+        let threshold = (BAYER[(y & 3) as usize][(x & 3) as usize] as f32 + 0.5) / 16.;
+        (threshold < self.fade) == self.coarser
+    }
+}
+
+/// How the samples of a triangle use the `zbuffer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DepthTest {
+    /// Every sample is drawn, and the `zbuffer` is left untouched.
+    Off,
+    /// Samples behind the `zbuffer` are skipped, and the `zbuffer` is left
+    /// untouched, for transparent faces.
+    ReadOnly,
+    /// Samples behind the `zbuffer` are skipped, and the other ones replace
+    /// it.
+    ReadWrite,
+}
+
+/// Rasterize a triangle given in screen coordinates, calling `plot` with the
+/// index of every pixel covered, as given by `utils::pixel_index`, the
+/// barycentric coordinates of its first covered sample and the mask of its
+/// covered samples.
+///
+/// `samples` are the positions of the samples in a pixel, each of them having
+/// its depth in `zbuffer`.
+#[allow(clippy::too_many_arguments)]
+fn render_triangle<F: FnMut(usize, Vector3<f32>, u8)>(
     vertices: &[Vector3<f32>],
-    frame: &mut [u8],
+    dither: Option<Dither>,
     zbuffer: &mut [f32],
-    color: &[u8],
+    samples: &[(f32, f32)],
+    depth_test: DepthTest,
     width: f32,
     height: f32,
+    mut plot: F,
 ) {
     let mut bboxmin: Vector2<f32> = Vector2::new(f32::MAX, f32::MAX);
     let mut bboxmax: Vector2<f32> = Vector2::new(f32::MIN, f32::MIN);
@@ -72,28 +119,38 @@ fn render_triangle(
     let mut point: Vector3<f32> = Vector3::new(0., 0., 0.);
     for i in bboxmin.x as i32..bboxmax.x as i32 + 1 {
         for j in bboxmin.y as i32..bboxmax.y as i32 + 1 {
-            utils::update_vector2(&mut point, i, j);
-            let bc_screen = barycentric_coordinates(vertices, &point);
-            if bc_screen.x < 0. || bc_screen.y < 0. || bc_screen.z < 0. {
+            if dither.is_some_and(|d| !d.covers(i, j)) {
                 continue;
-            };
-            point.z = 0.;
+            }
+            let q = utils::pixel_index(i as usize, j as usize, width as usize, height as usize);
+            let mut mask = 0;
+            let mut first_bc = None;
+            for (s, (dx, dy)) in samples.iter().enumerate() {
+                point.x = i as f32 + dx;
+                point.y = j as f32 + dy;
+                let bc_screen = barycentric_coordinates(vertices, &point);
+                if bc_screen.x < 0. || bc_screen.y < 0. || bc_screen.z < 0. {
+                    continue;
+                };
+                point.z = 0.;
 
-            // check with z buffer & then draw
-            for i in 0..2 {
-                point.z += vertices[i][2] * bc_screen[i]
+                // check with z buffer
+                for k in 0..3 {
+                    point.z += vertices[k][2] * bc_screen[k]
+                }
+                let depth = &mut zbuffer[q * samples.len() + s];
+                if depth_test != DepthTest::Off && *depth >= point.z {
+                    continue;
+                }
+                if depth_test == DepthTest::ReadWrite {
+                    *depth = point.z;
+                }
+                mask |= 1 << s;
+                first_bc.get_or_insert(bc_screen);
             }
-            let q = i as usize + (j * width as i32) as usize;
-            if zbuffer[q] < point.z {
-                utils::set_pixel(
-                    i as usize,
-                    j as usize,
-                    frame,
-                    color,
-                    width as usize,
-                    height as usize,
-                );
-                zbuffer[q] = point.z;
+            // & then draw
+            if let Some(bc) = first_bc {
+                plot(q, bc, mask);
             }
         }
     }
@@ -104,9 +161,11 @@ fn bench_render_triangle(b: &mut Bencher) {
     const HEIGHT: usize = 512;
     const WIDTH: usize = 512;
 
-    let mut frame = [0; WIDTH * HEIGHT * 4];
-    let mut zbuffer = [0f32; WIDTH * HEIGHT];
-    let red = [255, 0, 0, 255];
+    // on the heap, as the buffers overflow the stack of the test threads
+    // running benchmarks with `cargo test`
+    let mut frame = vec![0.; WIDTH * HEIGHT * 4];
+    let mut zbuffer = vec![0f32; WIDTH * HEIGHT];
+    let red = [1., 0., 0., 1.];
 
     let pts = [
         Vector3::new(0., 0., 0.),
@@ -117,15 +176,41 @@ fn bench_render_triangle(b: &mut Bencher) {
     b.iter(|| {
         render_triangle(
             &pts,
-            &mut frame,
+            None,
             &mut zbuffer,
-            &red,
-            WIDTH as f32,
-            HEIGHT as f32,
+            antialiasing::sample_offsets(1),
+            DepthTest::ReadWrite,
+            (WIDTH - 1) as f32,
+            (HEIGHT - 1) as f32,
+            |pixel, _, _| frame[4 * pixel..4 * pixel + 4].copy_from_slice(&red),
         )
     });
 }
 
+/// Whether the triangle faces the viewer, i.e. has the winding of front faces
+/// on screen.
+fn is_front_facing(screen: &[Vector3<f32>; 3], front_face: Winding) -> bool {
+    let (a, b) = (screen[1] - screen[0], screen[2] - screen[0]);
+    // the y axis of the screen goes up
+    let area = a.x * b.y - a.y * b.x;
+    match front_face {
+        Winding::CounterClockwise => area > 0.,
+        Winding::Clockwise => area < 0.,
+    }
+}
+
+/// Whether the triangle is skipped by `config.culling`, along with whether
+/// it faces the viewer.
+fn cull(screen: &[Vector3<f32>; 3], config: &Config) -> (bool, bool) {
+    let front = is_front_facing(screen, config.front_face);
+    let culled = match config.culling {
+        Culling::None => false,
+        Culling::Back => !front,
+        Culling::Front => front,
+    };
+    (culled, front)
+}
+
 fn world_to_screen(world_c: &Vector3<f32>, width: f32, height: f32) -> Vector3<f32> {
     Vector3::new(
         (world_c.x + 1.) * width / 2.,
@@ -134,45 +219,276 @@ fn world_to_screen(world_c: &Vector3<f32>, width: f32, height: f32) -> Vector3<f
     )
 }
 
+/// Transform every vertex of the buffer to screen coordinates.
+///
+/// This is done once per frame, so vertices shared by several triangles are
+/// only transformed once.
+pub fn transform_vertices(
+    vertices: &[Vertex],
+    model: &Matrix4<f32>,
+    screen: &mut Vec<Vector3<f32>>,
+    config: &Config,
+) {
+    let width_f32: f32 = (config.width - 1) as f32;
+    let height_f32: f32 = (config.height - 1) as f32;
+
+    screen.clear();
+    screen.extend(
+        vertices
+            .iter()
+            .map(|v| utils::transform(model, v.position))
+            .map(|p| world_to_screen(&p, width_f32, height_f32)),
+    );
+}
+
+/// Material of the group drawn by `rasterize_mesh`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Surface {
+    /// Label of the material in the `ids`.
+    pub material: u32,
+    /// Faces below 1 are transparent.
+    pub opacity: f32,
+    /// Reflectance at normal incidence, only reflecting the environment.
+    pub specular: [f32; 3],
+    /// Blur of the reflections, from 0 for a mirror to 1.
+    pub roughness: f32,
+}
+
+/// Diffuse light and reflections of the `environment` on a surface facing
+/// `normal`, seen from the viewer looking down the `z` axis.
+fn environment_light(
+    environment: &Environment,
+    normal: Vector3<f32>,
+    surface: &Surface,
+) -> ([f32; 3], [f32; 3]) {
+    let view = Vector3::unit_z();
+    let cos = normal.dot(view).max(0.);
+    let reflected = normal * (2. * cos) - view;
+    // Karis' fit of the integral of the GGX specular lobe with Schlick's
+    // Fresnel term, scaling and biasing the reflectance
+    let r = surface.roughness;
+    let (x, y) = (1. - r, 0.0425 - 0.0275 * r);
+    let (z, w) = (1.04 - 0.572 * r, 0.022 * r - 0.04);
+    let a004 = (x * x).min(2f32.powf(-9.28 * cos)) * x + y;
+    let (scale, bias) = (-1.04 * a004 + z, 1.04 * a004 + w);
+    let light = environment.specular(reflected.normalize(), surface.roughness);
+    let mut specular = [0.; 3];
+    for c in 0..3 {
+        specular[c] = light[c] * (surface.specular[c] * scale + bias);
+    }
+    (environment.diffuse(normal), specular)
+}
+
+/// Draw the triangles of `range`, lit by the `environment` if any, and write
+/// their labels to the `ids` of the targets if any.
+///
+/// Transparent triangles are blended with the pixels, sorted from back to
+/// front when this matters, or added to the `oit` buffers of the targets if
+/// they are used.
+#[allow(clippy::too_many_arguments)]
 pub fn rasterize_mesh(
-    vertices: &Vec<Vector3<f32>>,
-    faces: &Vec<SimplePolygon>,
-    frame: &mut [u8],
-    zbuffer: &mut [f32],
+    buffer: &VertexBuffer,
+    range: &DrawRange,
+    surface: Surface,
+    screen: &[Vector3<f32>],
+    dither: Option<Dither>,
+    environment: Option<&Environment>,
+    targets: &mut Targets,
     config: &Config,
 ) {
     let width_f32: f32 = (config.width - 1) as f32;
     let height_f32: f32 = (config.height - 1) as f32;
 
-    // each face is a triangle
-    for face in faces {
-        let mut world_coordinates: Vec<Vector3<f32>> = Vec::with_capacity(3);
-        // coordinates of face triangles in screen coordinates
-        let mut screen_coordinates: Vec<Vector3<f32>> = Vec::with_capacity(3);
-        for i in 0..3 {
-            // world coordinate of triangle vertex
-            let tr_wc = vertices[face[i].0];
-            screen_coordinates.push(world_to_screen(&tr_wc, width_f32, height_f32));
-            world_coordinates.push(tr_wc);
+    let samples = antialiasing::sample_offsets(config.anti_aliasing.msaa_samples());
+    let count = samples.len();
+    let Targets {
+        frame,
+        zbuffer,
+        samples: sample_colors,
+        occlusion,
+        oit,
+        ids,
+    } = targets;
+    // with multisampling, pixels are written to their samples
+    let colors: &mut [f32] = if count > 1 { sample_colors } else { frame };
+
+    let transparent = surface.opacity < 1. && config.render_mode == RenderMode::Shaded;
+    let alpha_blended = transparent && config.blending == Blending::Alpha;
+    let order_independent = alpha_blended && !oit.is_empty();
+    let mut triangles: Vec<_> = buffer.triangles(range).enumerate().collect();
+    if alpha_blended && !order_independent {
+        // from back to front, the depth growing towards the viewer
+        let depth = |triangle: &[usize; 3]| triangle.iter().map(|&i| screen[i].z).sum::<f32>();
+        triangles.sort_by(|(_, a), (_, b)| depth(a).total_cmp(&depth(b)));
+    }
+
+    let first = range.indices.start / 3;
+    for (t, triangle) in triangles {
+        // coordinates of triangle vertices in world & screen coordinates
+        let world_coordinates = triangle.map(|i| buffer.vertices[i].position);
+        let screen_coordinates = triangle.map(|i| screen[i]);
+
+        let (culled, front) = cull(&screen_coordinates, config);
+        if culled {
+            continue;
         }
+
         // get normal vector to triangle and take dot product with light direction
         // to get intensity.
-        let normal = Vector3::cross(
+        let mut normal = Vector3::cross(
             world_coordinates[1] - world_coordinates[0],
             world_coordinates[2] - world_coordinates[0],
         ).normalize();
+        if config.front_face == Winding::Clockwise {
+            normal = -normal;
+        }
+
+        let shaded = match config.render_mode {
+            RenderMode::Shaded => {
+                // back faces are lit like the other side of the surface
+                let lit = if config.two_sided_lighting && !front {
+                    -normal
+                } else {
+                    normal
+                };
+                // linear intensity, which may be above 1 for bright lights.
+                // Faces turned away from the light only get the ambient light
+                let direct = lit.dot(config.light_direction).max(0.);
+                let indirect = environment.map_or(([0.; 3], [0.; 3]), |environment| {
+                    environment_light(environment, lit, &surface)
+                });
+                Some((direct, indirect))
+            }
+            _ => None,
+        };
+        let corners = triangle.map(|i| &buffer.vertices[i]);
+        let mut ids = ids.as_deref_mut().map(|buffers| {
+            let ids = Ids {
+                object: range.object as u32,
+                group: range.group as u32,
+                material: surface.material,
+                triangle: (first + t) as u32,
+            };
+            (buffers, ids)
+        });
+        let depth_test = if transparent {
+            DepthTest::ReadOnly
+        } else if config.render_mode == RenderMode::Overdraw {
+            DepthTest::Off
+        } else {
+            DepthTest::ReadWrite
+        };
 
-        // use intensity as it is. Ignoring gamma correction
-        let intensity = (normal.dot(config.light_direction) * 255.) as u8;
-        if intensity > 0 {
-            render_triangle(
-                &screen_coordinates,
-                frame,
-                zbuffer,
-                &[intensity, intensity, intensity, 255],
-                width_f32,
-                height_f32,
-            );
+        render_triangle(
+            &screen_coordinates,
+            dither,
+            zbuffer,
+            samples,
+            depth_test,
+            width_f32,
+            height_f32,
+            |pixel, bc, mask| {
+                // the pixel is shaded once, then copied to its covered samples
+                let covered = (0..count)
+                    .filter(|s| mask & 1 << s != 0)
+                    .map(|s| 4 * (pixel * count + s));
+                let color = match shaded {
+                    Some((direct, (diffuse, specular))) => {
+                        // the occlusion darkens all of the indirect light
+                        let visible = occlusion.get(pixel).unwrap_or(&1.);
+                        let mut color = [0., 0., 0., 1.];
+                        for c in 0..3 {
+                            let indirect = config.ambient_light + diffuse[c] + specular[c];
+                            color[c] = direct + indirect * visible;
+                        }
+                        color
+                    }
+                    None => {
+                        let first = covered.clone().next().unwrap();
+                        let mut color = [0.; 4];
+                        color.copy_from_slice(&colors[first..first + 4]);
+                        debug::shade(config.render_mode, &corners, normal, front, bc, &mut color);
+                        color
+                    }
+                };
+                if !transparent {
+                    for c in covered {
+                        colors[c..c + 4].copy_from_slice(&color);
+                    }
+                    if let Some((buffers, ids)) = &mut ids {
+                        buffers.set(pixel, ids);
+                    }
+                } else if order_independent {
+                    let depth = (0..3).map(|i| screen_coordinates[i].z * bc[i]).sum();
+                    for c in covered {
+                        oit.add(c / 4, &color, surface.opacity, depth);
+                    }
+                } else {
+                    for c in covered {
+                        transparency::blend(
+                            &mut colors[c..c + 4],
+                            &color,
+                            surface.opacity,
+                            config.blending,
+                        );
+                    }
+                }
+            },
+        );
+    }
+}
+
+/// Only write the depth of the triangles of `range` to the `zbuffer`, for the
+/// wireframe to be tested against or the ambient occlusion.
+pub fn rasterize_depth(
+    buffer: &VertexBuffer,
+    range: &DrawRange,
+    screen: &[Vector3<f32>],
+    zbuffer: &mut [f32],
+    config: &Config,
+) {
+    let samples = antialiasing::sample_offsets(config.anti_aliasing.msaa_samples());
+    for triangle in buffer.triangles(range) {
+        let screen_coordinates = triangle.map(|i| screen[i]);
+        if cull(&screen_coordinates, config).0 {
+            continue;
         }
+        render_triangle(
+            &screen_coordinates,
+            None,
+            zbuffer,
+            samples,
+            DepthTest::ReadWrite,
+            (config.width - 1) as f32,
+            (config.height - 1) as f32,
+            |_, _, _| (),
+        );
     }
 }
+
+#[test]
+fn test_culling() {
+    use crate::fixtures::{pixel, render, squares};
+    use crate::id_buffer::NO_ID;
+
+    let (mesh, mut config) = squares();
+    config.id_buffers = true;
+    // the color and the group drawn in the middle of the screen
+    let draw = |config: Config| {
+        let (frame, rcontext) = render(config, &mesh);
+        let ids = crate::id_buffers(&rcontext).unwrap();
+        let group = ids.group[(100 * config.width + 100) as usize];
+        (pixel(&frame, &config, 100, 100)[0], group)
+    };
+
+    // the squares are counter clockwise, facing the light
+    assert_eq!(draw(config), (255, 0));
+    config.culling = Culling::Front;
+    assert_eq!(draw(config).1, NO_ID);
+    config.front_face = Winding::Clockwise;
+    assert_eq!(draw(config), (0, 0));
+    config.two_sided_lighting = true;
+    assert_eq!(draw(config), (255, 0));
+    config.culling = Culling::Back;
+    assert_eq!(draw(config).1, NO_ID);
+}
@@ -0,0 +1,169 @@
+// screen space ambient occlusion
+
+use cgmath::prelude::*;
+use cgmath::Vector3;
+
+use super::rasterizer::BAYER;
+use crate::utils;
+use crate::Config;
+
+/// Part of the radius a sample must be behind a surface to be occluded by
+/// it, for flat surfaces not to occlude themselves.
+const DEPTH_BIAS: f32 = 0.1;
+
+/// Offsets of the samples in the hemisphere of radius 1 around the z axis,
+/// more of them being close to its center.
+fn kernel(count: usize) -> Vec<Vector3<f32>> {
+    let golden_angle = std::f32::consts::PI * (3. - 5f32.sqrt());
+    (0..count)
+        .map(|i| {
+            // a spiral kept away from the tangent plane, where the surface
+            // itself would occlude the samples
+            let r = 0.9 * ((i as f32 + 0.5) / count as f32).sqrt();
+            let angle = i as f32 * golden_angle;
+            let direction = Vector3::new(r * angle.cos(), r * angle.sin(), (1. - r * r).sqrt());
+            let length = (i as f32 * 0.618_034).fract();
+            direction * (0.1 + 0.9 * length * length)
+        })
+        .collect()
+}
+
+/// Write the ambient occlusion of every pixel to `occlusion`, from 0 for a
+/// fully occluded pixel to 1, from the depth of its first sample in the
+/// `zbuffer`.
+///
+/// `config.ssao_samples` points are taken in the hemisphere of radius
+/// `config.ssao_radius` around the normal of every pixel, reconstructed from
+/// the depth of its neighbors, and the ones behind the `zbuffer` are occluded.
+/// The hemisphere is rotated differently in every pixel of a 4x4 tile, and
+/// the noise is then removed by averaging the tiles.
+///
+/// Nothing is occluded if the radius is not positive.
+pub fn ambient_occlusion(zbuffer: &[f32], occlusion: &mut [f32], config: &Config) {
+    if config.ssao_radius <= 0. || config.ssao_radius.is_nan() {
+        occlusion.iter_mut().for_each(|o| *o = 1.);
+        return;
+    }
+    let width = (config.width - 1) as usize;
+    let height = (config.height - 1) as usize;
+    let samples = zbuffer.len() / occlusion.len();
+    let depth = |x: usize, y: usize| {
+        let z = zbuffer[utils::pixel_index(x, y, width, height) * samples];
+        Some(z).filter(|&z| z != f32::MIN)
+    };
+    // a pixel spans 2 / width in the `[-1, 1]` cube
+    let (dx, dy) = (2. / width as f32, 2. / height as f32);
+    let radius = config.ssao_radius;
+    let kernel = kernel(config.ssao_samples as usize);
+
+    let mut unoccluded = vec![1.; occlusion.len()];
+    for y in 0..=height {
+        for x in 0..=width {
+            let z = match depth(x, y) {
+                Some(z) => z,
+                None => continue,
+            };
+            let point = Vector3::new(x as f32 * dx - 1., y as f32 * dy - 1., z);
+            let neighbor = |x: usize, y: usize, dx: isize, dy: isize| {
+                let (x, y) = (x as isize + dx, y as isize + dy);
+                if x < 0 || y < 0 || x as usize > width || y as usize > height {
+                    return None;
+                }
+                depth(x as usize, y as usize)
+            };
+            let slope_x = utils::depth_slope([-2, -1, 0, 1, 2].map(|d| neighbor(x, y, d, 0)));
+            let slope_y = utils::depth_slope([-2, -1, 0, 1, 2].map(|d| neighbor(x, y, 0, d)));
+            let normal = Vector3::new(-slope_x * dy, -slope_y * dx, dx * dy).normalize();
+            // samples rounded to the nearest pixel are compared to the depth
+            // at its center, and the normal may be the one of a neighbor
+            let bias = (slope_x.abs() + slope_y.abs()) / 2. + radius * DEPTH_BIAS;
+
+            // basis around the normal, rotated by the position in the tile
+            let angle = (BAYER[y & 3][x & 3] as f32 + 0.5) / 16. * 2. * std::f32::consts::PI;
+            let (sin, cos) = angle.sin_cos();
+            let mut tangent = Vector3::new(cos, sin, 0.);
+            tangent -= normal * tangent.dot(normal);
+            if tangent.magnitude2() < 1e-6 {
+                tangent = Vector3::new(-sin, cos, 0.);
+            }
+            let tangent = tangent.normalize();
+            let bitangent = normal.cross(tangent);
+
+            let mut occluded = 0.;
+            for offset in &kernel {
+                let offset = tangent * offset.x + bitangent * offset.y + normal * offset.z;
+                let sample = point + offset * radius;
+                let (sx, sy) = (
+                    ((sample.x + 1.) / dx).round(),
+                    ((sample.y + 1.) / dy).round(),
+                );
+                if sx < 0. || sy < 0. || sx > width as f32 || sy > height as f32 {
+                    continue;
+                }
+                match depth(sx as usize, sy as usize) {
+                    // surfaces far in front of the point barely occlude it
+                    Some(d) if d > sample.z + bias => occluded += (radius / (d - z).abs()).min(1.),
+                    _ => (),
+                }
+            }
+            let q = utils::pixel_index(x, y, width, height);
+            unoccluded[q] = 1. - occluded / kernel.len().max(1) as f32;
+        }
+    }
+
+    // average over the 4x4 tile around every pixel, on the same surface
+    for y in 0..=height {
+        for x in 0..=width {
+            let q = utils::pixel_index(x, y, width, height);
+            let z = match depth(x, y) {
+                Some(z) => z,
+                None => {
+                    occlusion[q] = 1.;
+                    continue;
+                }
+            };
+            let (mut sum, mut count) = (0., 0.);
+            for ny in y.saturating_sub(2)..(y + 2).min(height + 1) {
+                for nx in x.saturating_sub(2)..(x + 2).min(width + 1) {
+                    if matches!(depth(nx, ny), Some(d) if (d - z).abs() < radius) {
+                        sum += unoccluded[utils::pixel_index(nx, ny, width, height)];
+                        count += 1.;
+                    }
+                }
+            }
+            occlusion[q] = (1. - config.ssao_strength * (1. - sum / count)).clamp(0., 1.);
+        }
+    }
+}
+
+#[test]
+fn test_ambient_occlusion() {
+    use crate::fixtures::{pixel, render, squares};
+
+    let (mesh, mut config) = squares();
+    // only the ambient light
+    config.light_direction = Vector3::new(0., 0., 0.);
+    config.ambient_light = 1.;
+    config.ssao_samples = 16;
+    config.ssao_radius = 0.5;
+    // the red channel in the middle row, on the large square away from the
+    // small one then next to it, and on the small one
+    let draw = |config: Config| {
+        let (frame, _) = render(config, &mesh);
+        [5, 47, 100]
+            .iter()
+            .map(|&x| pixel(&frame, &config, x, 100)[0])
+            .collect::<Vec<_>>()
+    };
+
+    let occluded = draw(config);
+    assert_eq!(occluded[0], 255);
+    assert!(occluded[1] < 230, "{:?}", occluded);
+    assert_eq!(occluded[2], 255);
+    config.ssao_strength = 0.;
+    assert_eq!(draw(config), vec![255, 255, 255]);
+    // no neighbor is on the same surface within a zero radius
+    config.ssao_strength = 1.;
+    config.ssao_radius = 0.;
+    assert_eq!(draw(config), vec![255, 255, 255]);
+}
@@ -0,0 +1,150 @@
+// blending of transparent faces
+
+use crate::Blending;
+
+/// Combine the linear `color` with the given opacity with `pixel`.
+pub fn blend(pixel: &mut [f32], color: &[f32], alpha: f32, blending: Blending) {
+    for (d, &c) in pixel.iter_mut().zip(color) {
+        *d = match blending {
+            Blending::Alpha => *d + (c - *d) * alpha,
+            Blending::Additive => *d + c * alpha,
+            Blending::Multiply => *d * (1. + (c - 1.) * alpha),
+        };
+    }
+}
+
+/// Accumulation buffers of weighted blended order-independent transparency
+/// (McGuire and Bavoil, 2013), with an entry per pixel or per sample.
+///
+/// Transparent fragments are averaged with weights favoring the nearest ones,
+/// so that they don't need to be sorted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OitBuffers {
+    /// Sums of the weighted colors and of the weights.
+    accum: Vec<[f32; 4]>,
+    /// Part of the background left visible by the fragments.
+    revealage: Vec<f32>,
+}
+
+impl OitBuffers {
+    pub fn new(len: usize) -> Self {
+        OitBuffers {
+            accum: vec![[0.; 4]; len],
+            revealage: vec![1.; len],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.revealage.is_empty()
+    }
+
+    /// Add a fragment at the given depth, from -1 for the farthest to 1 for
+    /// the nearest.
+    pub fn add(&mut self, i: usize, color: &[f32], alpha: f32, depth: f32) {
+        let nearness = (1. + depth) / 2.;
+        let weight = alpha * (3e3 * nearness.powi(3)).max(1e-2);
+        let accum = &mut self.accum[i];
+        for c in 0..3 {
+            accum[c] += color[c] * weight;
+        }
+        accum[3] += weight;
+        self.revealage[i] *= 1. - alpha;
+    }
+
+    /// Composite the fragments over `colors`, and clear the buffers.
+    pub fn resolve(&mut self, colors: &mut [f32]) {
+        for ((pixel, accum), revealage) in colors
+            .chunks_exact_mut(4)
+            .zip(&mut self.accum)
+            .zip(&mut self.revealage)
+        {
+            if *revealage < 1. {
+                for c in 0..3 {
+                    let average = accum[c] / accum[3].max(1e-5);
+                    pixel[c] = average * (1. - *revealage) + pixel[c] * *revealage;
+                }
+                // what is behind may be transparent too
+                pixel[3] = 1. - (1. - pixel[3]) * *revealage;
+            }
+            *accum = [0.; 4];
+            *revealage = 1.;
+        }
+    }
+}
+
+#[test]
+fn test_blending() {
+    let blended = |blending| {
+        let mut pixel = [0.25, 1., 0., 1.];
+        blend(&mut pixel, &[1., 0.5, 1., 1.], 0.5, blending);
+        pixel
+    };
+    assert_eq!(blended(Blending::Alpha), [0.625, 0.75, 0.5, 1.]);
+    assert_eq!(blended(Blending::Additive), [0.75, 1.25, 0.5, 1.5]);
+    assert_eq!(blended(Blending::Multiply), [0.25, 0.75, 0., 1.]);
+
+    // the result doesn't depend on the order of the fragments
+    let red = ([1., 0., 0., 1.], 0.5, 0.5);
+    let blue = ([0., 0., 1., 1.], 0.8, -0.5);
+    let mut composited = Vec::new();
+    for fragments in [[red, blue], [blue, red]] {
+        let mut oit = OitBuffers::new(2);
+        for (color, alpha, depth) in fragments {
+            oit.add(0, &color, alpha, depth);
+        }
+        let mut colors = [0., 0., 0., 0., 0.5, 0.5, 0.5, 1.];
+        oit.resolve(&mut colors);
+        composited.push(colors);
+        assert_eq!(colors[4..], [0.5, 0.5, 0.5, 1.]);
+        assert!(colors[0] > colors[2] && colors[2] > 0.);
+        assert!((colors[3] - 0.9).abs() < 1e-6);
+    }
+    assert_eq!(composited[0], composited[1]);
+    assert!(OitBuffers::new(0).is_empty());
+}
+
+#[test]
+fn test_transparency() {
+    use cgmath::Vector3;
+
+    use crate::fixtures::{glass_squares, pixel, render};
+    use crate::id_buffer::NO_ID;
+    use crate::mesh::IndexTuple;
+    use crate::{Config, Transparency};
+
+    let (mesh, mut config) = glass_squares();
+    config.id_buffers = true;
+    // the red channel and the group in the middle of the screen, then on the
+    // large square only
+    let draw = |config: Config| {
+        let (frame, rcontext) = render(config, &mesh);
+        let ids = crate::id_buffers(&rcontext).unwrap();
+        [100, 10]
+            .iter()
+            .map(|&x| {
+                let group = ids.group[(100 * config.width + x) as usize];
+                (pixel(&frame, &config, x, 100)[0], group)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // half of the light, blended in linear space then sRGB encoded
+    assert_eq!(draw(config), vec![(255, 0), (188, NO_ID)]);
+    config.transparency = Transparency::WeightedBlended;
+    assert_eq!(draw(config), vec![(255, 0), (188, NO_ID)]);
+    config.blending = Blending::Multiply;
+    assert_eq!(draw(config), vec![(255, 0), (0, NO_ID)]);
+
+    // a face with a NaN vertex doesn't stop the sorting by depth
+    let (mut mesh, mut config) = glass_squares();
+    mesh.position.push(Vector3::new(f32::NAN, 0., 0.));
+    let back = &mut mesh.objects[0].groups[1];
+    back.polys.push(vec![
+        IndexTuple(4, None, None),
+        IndexTuple(5, None, None),
+        IndexTuple(8, None, None),
+    ]);
+    config.transparency = Transparency::Sorted;
+    let (frame, _) = render(config, &mesh);
+    assert_eq!(pixel(&frame, &config, 10, 100)[0], 188);
+}
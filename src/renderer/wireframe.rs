@@ -1,54 +1,198 @@
-use cgmath::Vector3;
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Vector2, Vector3};
 
+use super::{color, transparency};
 use crate::mesh::SimplePolygon;
 use crate::utils;
-use crate::Config;
+use crate::{Blending, Config};
 
 #[allow(unused_imports)]
 use test::Bencher;
 
-pub fn draw_mesh_wireframe(
-    vertices: &Vec<Vector3<f32>>,
-    faces: &Vec<SimplePolygon>,
-    frame: &mut [u8],
-    config: &Config,
-) {
-    let width: f32 = (config.width - 1) as f32;
-    let height: f32 = (config.height - 1) as f32;
-
-    for face in faces {
-        for i in 0..2 {
-            let v0 = vertices[face[i].0];
-            let v1 = vertices[face[(i + 1) % 3].0];
-
-            let x0 = (v0.x + 1f32) * width / 2.;
-            let y0 = (v0.y + 1f32) * height / 2.;
-            let x1 = (v1.x + 1f32) * width / 2.;
-            let y1 = (v1.y + 1f32) * height / 2.;
-
-            draw_line(
-                x0 as i32,
-                y0 as i32,
-                x1 as i32,
-                y1 as i32,
+/// Distance in pixels a line may be behind the `zbuffer` and still be drawn,
+/// for the rounding errors of the faces it lies on.
+const DEPTH_BIAS: f32 = 0.5;
+
+/// How lines and points are drawn to the frame buffer.
+pub struct Pen<'a> {
+    /// Linear color.
+    color: [f32; 4],
+    line_width: f32,
+    smooth: bool,
+    /// The `zbuffer` and its number of samples per pixel, with depth testing.
+    depth: Option<(&'a [f32], usize)>,
+    bias: f32,
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Pen<'a> {
+    pub fn new(config: &Config, zbuffer: &'a [f32]) -> Self {
+        let width = (config.width - 1) as usize;
+        let samples = config.anti_aliasing.msaa_samples();
+        Pen {
+            color: color::decode(&config.default_color),
+            line_width: config.line_width,
+            smooth: config.smooth_lines,
+            depth: config.depth_tests_lines().then_some((zbuffer, samples)),
+            // a pixel spans 2 / width in the `[-1, 1]` cube
+            bias: DEPTH_BIAS * 2. / width as f32,
+            width,
+            height: (config.height - 1) as usize,
+        }
+    }
+
+    /// Screen coordinates and depth of a position.
+    fn project(&self, model: &Matrix4<f32>, position: Vector3<f32>) -> Vector3<f32> {
+        let v = utils::transform(model, position);
+        Vector3::new(
+            (v.x + 1.) * self.width as f32 / 2.,
+            (v.y + 1.) * self.height as f32 / 2.,
+            v.z,
+        )
+    }
+
+    /// Whether a point of a line in screen coordinates is in front of the
+    /// faces in the `zbuffer`, if lines are depth tested.
+    ///
+    /// The point is compared to the farthest face around it, since the faces
+    /// may be steep while the line lies on them.
+    fn is_visible(&self, point: Vector3<f32>) -> bool {
+        let (zbuffer, samples) = match self.depth {
+            Some(depth) => depth,
+            None => return true,
+        };
+        let (x, y) = (point.x.round() as i32, point.y.round() as i32);
+        let mut farthest = f32::MAX;
+        for (dx, dy) in (-1..=1).flat_map(|dx| (-1..=1).map(move |dy| (dx, dy))) {
+            let (x, y) = (x + dx, y + dy);
+            if x >= 0 && y >= 0 && x as usize <= self.width && y as usize <= self.height {
+                let q = utils::pixel_index(x as usize, y as usize, self.width, self.height);
+                farthest = farthest.min(zbuffer[q * samples]);
+            }
+        }
+        point.z >= farthest - self.bias
+    }
+
+    /// Blend the color into a pixel with the given coverage, if it is on
+    /// screen.
+    fn plot(&self, frame: &mut [f32], x: i32, y: i32, coverage: f32) {
+        // the vertices may be out of the screen
+        if x < 0 || y < 0 || x as usize > self.width || y as usize > self.height {
+            return;
+        }
+        if coverage >= 1. {
+            utils::set_pixel(
+                x as usize,
+                y as usize,
                 frame,
-                &config.default_color,
-                width as usize,
-                height as usize,
+                &self.color,
+                self.width,
+                self.height,
+            );
+            return;
+        }
+        let q = utils::pixel_index(x as usize, y as usize, self.width, self.height);
+        transparency::blend(
+            &mut frame[4 * q..4 * q + 4],
+            &self.color,
+            coverage,
+            Blending::Alpha,
+        );
+    }
+
+    /// Draw a line between two points in screen coordinates.
+    ///
+    /// Thick lines end with round caps, so that consecutive segments are
+    /// joined without gaps. With depth testing, every pixel is drawn if the
+    /// nearest point of the line is visible.
+    fn segment(&self, frame: &mut [f32], a: Vector3<f32>, b: Vector3<f32>) {
+        let mut plot = |x, y, t, coverage| {
+            if self.is_visible(a + (b - a) * t) {
+                self.plot(frame, x, y, coverage);
+            }
+        };
+        if self.line_width > 1. {
+            draw_thick_line(
+                a.truncate(),
+                b.truncate(),
+                self.line_width,
+                self.smooth,
+                plot,
             );
+        } else if self.smooth {
+            draw_smooth_line(a.truncate(), b.truncate(), plot);
+        } else {
+            draw_line(a.x as i32, a.y as i32, b.x as i32, b.y as i32, |x, y, t| {
+                plot(x, y, t, 1.)
+            });
+        }
+    }
+
+    fn point(&self, frame: &mut [f32], a: Vector3<f32>) {
+        if self.line_width > 1. || self.smooth {
+            self.segment(frame, a, a);
+        } else {
+            if self.is_visible(a) {
+                self.plot(frame, a.x as i32, a.y as i32, 1.);
+            }
+        }
+    }
+}
+
+/// Draws the polygon edges of a group, as listed by `Lod::edges`.
+pub fn draw_mesh_wireframe(
+    vertices: &[Vector3<f32>],
+    model: &Matrix4<f32>,
+    edges: &[[usize; 2]],
+    frame: &mut [f32],
+    pen: &Pen,
+) {
+    for edge in edges {
+        let v0 = pen.project(model, vertices[edge[0]]);
+        let v1 = pen.project(model, vertices[edge[1]]);
+        pen.segment(frame, v0, v1);
+    }
+}
+
+/// Draws the polylines (`l` elements and tessellated curves) of a group.
+pub fn draw_polylines(
+    vertices: &[Vector3<f32>],
+    model: &Matrix4<f32>,
+    lines: &[SimplePolygon],
+    frame: &mut [f32],
+    pen: &Pen,
+) {
+    for line in lines {
+        for segment in line.windows(2) {
+            let v0 = pen.project(model, vertices[segment[0].0]);
+            let v1 = pen.project(model, vertices[segment[1].0]);
+            pen.segment(frame, v0, v1);
         }
     }
 }
 
-pub fn draw_line(
+/// Draws the `p` elements of a group as dots of the width of the lines.
+pub fn draw_points(
+    vertices: &[Vector3<f32>],
+    model: &Matrix4<f32>,
+    points: &[usize],
+    frame: &mut [f32],
+    pen: &Pen,
+) {
+    for &point in points {
+        pen.point(frame, pen.project(model, vertices[point]));
+    }
+}
+
+/// Bresenham's line, calling `plot` with the coordinates of every pixel and
+/// its position along the line, from 0 at `(x1, y1)` to 1 at `(x2, y2)`.
+pub fn draw_line<F: FnMut(i32, i32, f32)>(
     mut x1: i32,
     mut y1: i32,
     mut x2: i32,
     mut y2: i32,
-    frame: &mut [u8],
-    color: &[u8],
-    width: usize,
-    height: usize,
+    mut plot: F,
 ) {
     let mut steep = false;
     if ((x1 - x2) as i32).abs() < ((y1 - y2) as i32).abs() {
@@ -56,7 +200,8 @@ pub fn draw_line(
         std::mem::swap(&mut x2, &mut y2);
         steep = true;
     }
-    if x1 > x2 {
+    let reversed = x1 > x2;
+    if reversed {
         std::mem::swap(&mut x1, &mut x2);
         std::mem::swap(&mut y1, &mut y2);
     }
@@ -66,11 +211,9 @@ pub fn draw_line(
     let mut error = 0;
     let mut y = y1;
     for x in x1..x2 {
-        if steep {
-            utils::set_pixel(y as usize, x as usize, frame, color, width, height);
-        } else {
-            utils::set_pixel(x as usize, y as usize, frame, color, width, height);
-        }
+        let (px, py) = if steep { (y, x) } else { (x, y) };
+        let t = (x - x1) as f32 / dx as f32;
+        plot(px, py, if reversed { 1. - t } else { t });
         error += derror;
         if error > dx {
             y += if y2 > y1 { 1 } else { -1 };
@@ -79,12 +222,199 @@ pub fn draw_line(
     }
 }
 
+/// Xiaolin Wu's anti-aliased line, calling `plot` with the coordinates of
+/// every pixel, its position along the line as with `draw_line`, and the part
+/// of the pixel covered by the line.
+pub fn draw_smooth_line<F: FnMut(i32, i32, f32, f32)>(
+    a: Vector2<f32>,
+    b: Vector2<f32>,
+    mut plot: F,
+) {
+    let fract = |v: f32| v - v.floor();
+    let steep = (b.y - a.y).abs() > (b.x - a.x).abs();
+    let (mut a, mut b) = if steep {
+        (Vector2::new(a.y, a.x), Vector2::new(b.y, b.x))
+    } else {
+        (a, b)
+    };
+    let reversed = a.x > b.x;
+    if reversed {
+        std::mem::swap(&mut a, &mut b);
+    }
+    let d = b - a;
+    let gradient = if d.x == 0. { 1. } else { d.y / d.x };
+    let mut put = |x: i32, y: f32, coverage: f32| {
+        let t = if d.x == 0. {
+            0.
+        } else {
+            ((x as f32 - a.x) / d.x).clamp(0., 1.)
+        };
+        let t = if reversed { 1. - t } else { t };
+        // the line covers the two pixels around it
+        for (y, c) in [
+            (y.floor() as i32, 1. - fract(y)),
+            (y.floor() as i32 + 1, fract(y)),
+        ] {
+            if steep {
+                plot(y, x, t, c * coverage);
+            } else {
+                plot(x, y, t, c * coverage);
+            }
+        }
+    };
+
+    // the ends only cover part of their pixels
+    let start = a.x.round();
+    put(
+        start as i32,
+        a.y + gradient * (start - a.x),
+        1. - fract(a.x + 0.5),
+    );
+    let end = b.x.round();
+    if end != start {
+        put(end as i32, b.y + gradient * (end - b.x), fract(b.x + 0.5));
+    }
+    let mut y = a.y + gradient * (start - a.x + 1.);
+    for x in start as i32 + 1..end as i32 {
+        put(x, y, 1.);
+        y += gradient;
+    }
+}
+
+/// Line of the given width with round caps, calling `plot` like
+/// `draw_smooth_line`. Without `smooth`, pixels are either covered or not.
+pub fn draw_thick_line<F: FnMut(i32, i32, f32, f32)>(
+    a: Vector2<f32>,
+    b: Vector2<f32>,
+    width: f32,
+    smooth: bool,
+    mut plot: F,
+) {
+    let radius = width / 2.;
+    let (min, max) = (
+        Vector2::new(a.x.min(b.x), a.y.min(b.y)),
+        Vector2::new(a.x.max(b.x), a.y.max(b.y)),
+    );
+    let ab = b - a;
+    let length2 = ab.magnitude2();
+    for y in (min.y - radius).floor() as i32..(max.y + radius).ceil() as i32 + 1 {
+        for x in (min.x - radius).floor() as i32..(max.x + radius).ceil() as i32 + 1 {
+            let p = Vector2::new(x as f32, y as f32);
+            let t = if length2 > 0. {
+                ((p - a).dot(ab) / length2).clamp(0., 1.)
+            } else {
+                0.
+            };
+            let distance = (p - (a + ab * t)).magnitude();
+            let coverage = if smooth {
+                (radius + 0.5 - distance).clamp(0., 1.)
+            } else if distance <= radius {
+                1.
+            } else {
+                0.
+            };
+            if coverage > 0. {
+                plot(x, y, t, coverage);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_lines() {
+    let mut coverage = std::collections::HashMap::new();
+    draw_smooth_line(Vector2::new(0., 0.), Vector2::new(4., 2.), |x, y, t, c| {
+        assert!((0. ..=1.).contains(&t));
+        *coverage.entry((x, y)).or_insert(0.) += c;
+    });
+    // every column is covered once, shared by the two pixels around the line
+    for x in 0..=4 {
+        let column: f32 = (-1..=3).map(|y| coverage.get(&(x, y)).unwrap_or(&0.)).sum();
+        let expected = if x == 0 || x == 4 { 0.5 } else { 1. };
+        assert!((column - expected).abs() < 1e-5, "column {}: {}", x, column);
+    }
+    assert_eq!(coverage[&(2, 1)], 1.);
+
+    let mut pixels = Vec::new();
+    draw_thick_line(
+        Vector2::new(2., 2.),
+        Vector2::new(2., 2.),
+        3.,
+        false,
+        |x, y, _, _| pixels.push((x, y)),
+    );
+    // a dot of radius 1.5
+    assert_eq!(pixels.len(), 9);
+    assert!(pixels.contains(&(1, 1)) && !pixels.contains(&(0, 2)));
+}
+
+#[test]
+fn test_wireframe() {
+    use crate::fixtures::{pixel, render, squares};
+    use crate::mesh::IndexTuple;
+    use crate::Wireframe;
+
+    let (mut mesh, mut config) = squares();
+    // move the small square behind the large one
+    for p in &mut mesh.position[..4] {
+        p.z = -2.;
+    }
+    config.wireframe = Wireframe::Lines;
+    // the pixels of the middle row at the left edges of the large and the
+    // small squares, then in the middle
+    let middle_row = |config: Config| {
+        let (frame, _) = render(config, &mesh);
+        [0, 1, 49, 50, 51, 100]
+            .iter()
+            .map(|&x| pixel(&frame, &config, x, 100)[0])
+            .collect::<Vec<_>>()
+    };
+
+    // the last edge of the polygons is drawn too
+    assert!(middle_row(config)[2..5].contains(&255));
+    config.line_width = 3.;
+    assert_eq!(middle_row(config), vec![255, 255, 255, 255, 255, 0]);
+    config.line_depth_test = true;
+    assert_eq!(middle_row(config), vec![255, 255, 0, 0, 0, 0]);
+
+    config.line_depth_test = false;
+    config.wireframe = Wireframe::HiddenLine;
+    assert_eq!(middle_row(config), vec![255, 255, 0, 0, 0, 0]);
+    // the faces are lit at half intensity, encoded to 188 in sRGB
+    config.light_direction = Vector3::new(0., 0., 0.5);
+    config.wireframe = Wireframe::Overlay;
+    assert_eq!(middle_row(config), vec![255, 255, 188, 188, 188, 188]);
+
+    // lines and points past the last vertex, which the strict parser accepts
+    // like faces, are left out
+    let group = &mut mesh.objects[0].groups[0];
+    group
+        .lines
+        .push(vec![IndexTuple(0, None, None), IndexTuple(99, None, None)]);
+    group.points.push(99);
+    assert_eq!(mesh.clone().remove_invalid_faces(), 2);
+    render(config, &mesh);
+}
+
 #[bench]
 fn bench_draw_line(b: &mut Bencher) {
     const HEIGHT: usize = 512;
     const WIDTH: usize = 512;
-    let mut frame = [0; WIDTH * HEIGHT * 4];
-    let red = [255, 0, 0, 255];
+    let mut frame = vec![0.; WIDTH * HEIGHT * 4];
+    let red = [1., 0., 0., 1.];
 
-    b.iter(|| draw_line(10, 10, 0, 0, &mut frame, &red, WIDTH, HEIGHT));
+    // like the callers, pass the largest pixel coordinates, the frame size
+    // would write the line past the end of the frame
+    b.iter(|| {
+        draw_line(10, 10, 0, 0, |x, y, _| {
+            utils::set_pixel(
+                x as usize,
+                y as usize,
+                &mut frame,
+                &red,
+                WIDTH - 1,
+                HEIGHT - 1,
+            )
+        })
+    });
 }
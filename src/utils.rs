@@ -1,19 +1,61 @@
-use cgmath::Vector3;
+use cgmath::{Matrix4, Vector3};
 
 // Sets the pixel color in frame buffer
 // Also invert the y coordinate to make origin at bottom left corner
-pub fn set_pixel(x: usize, y: usize, frame: &mut [u8], color: &[u8], width: usize, height: usize) {
-    let si = 4 * (x + (width + 1) * (height - y));
+pub fn set_pixel<T: Copy>(
+    x: usize,
+    y: usize,
+    frame: &mut [T],
+    color: &[T],
+    width: usize,
+    height: usize,
+) {
+    let si = 4 * pixel_index(x, y, width, height);
     frame[si..si + 4].copy_from_slice(color);
 }
 
-pub fn clear(frame: &mut [u8], color: &[u8]) {
+/// Index of the pixel `(x, y)` in the frame buffer and the other per-pixel
+/// buffers, with the same arguments as `set_pixel`.
+pub fn pixel_index(x: usize, y: usize, width: usize, height: usize) -> usize {
+    x + (width + 1) * (height - y)
+}
+
+pub fn clear<T: Copy>(frame: &mut [T], color: &[T]) {
     for pixel in frame.chunks_exact_mut(4) {
         pixel.copy_from_slice(color);
     }
 }
 
-pub fn update_vector2(v: &mut Vector3<f32>, x: i32, y: i32) {
-    v.x = x as f32;
-    v.y = y as f32;
+/// Change of depth from a pixel to the next one, given the depths from 2
+/// pixels before it to 2 pixels after it, `None` where nothing is drawn.
+///
+/// The change is taken on the side where it is the most constant, for the
+/// pixels next to an edge to get the slope of their own surface.
+pub fn depth_slope(depths: [Option<f32>; 5]) -> f32 {
+    let center = depths[2].unwrap();
+    // change towards a side, with how much it varies on the next pixel
+    let side = |near: Option<f32>, far: Option<f32>| {
+        near.map(|near| {
+            let step = near - center;
+            let variation = far.map_or(f32::INFINITY, |far| (far - near - step).abs());
+            (step, variation)
+        })
+    };
+    match (side(depths[1], depths[0]), side(depths[3], depths[4])) {
+        (Some((before, a)), Some((after, b))) => {
+            if a < b {
+                -before
+            } else {
+                after
+            }
+        }
+        (Some((before, _)), None) => -before,
+        (None, Some((after, _))) => after,
+        (None, None) => 0.,
+    }
+}
+
+/// Apply an affine transform to a position.
+pub fn transform(model: &Matrix4<f32>, v: Vector3<f32>) -> Vector3<f32> {
+    (model * v.extend(1.)).truncate()
 }